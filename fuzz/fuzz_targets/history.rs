@@ -0,0 +1,13 @@
+//! History rings as read off the bus: parsing must never panic, whatever the entries hold.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vki2cfile::history::{History, HISTORY_SIZE};
+
+fuzz_target!(|ring: [u8; HISTORY_SIZE]| {
+    let history = History::from_bytes(&ring);
+
+    let _ = history.next();
+    let _ = history.newest_first();
+});
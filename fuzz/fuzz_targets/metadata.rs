@@ -0,0 +1,17 @@
+//! Metadata blocks as read off the bus: parsing must never panic, and whatever parses must serialize back into a block
+//! parsing to the same metadata.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vki2cfile::metadata::{FileInfo, METADATA_SIZE};
+
+fuzz_target!(|block: [u8; METADATA_SIZE]| {
+    let Ok(metadata) = FileInfo::parse(&block) else {
+        return;
+    };
+
+    let bytes: [u8; METADATA_SIZE] = metadata.to_bytes().try_into().unwrap();
+
+    assert_eq!(FileInfo::parse(&bytes), Ok(metadata));
+});
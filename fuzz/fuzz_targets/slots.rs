@@ -0,0 +1,18 @@
+//! Slot tables and A/B headers as read off the bus: parsing must never panic, and whatever parses must round-trip.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vki2cfile::ab;
+use vki2cfile::slots::{SlotTable, SLOT_TABLE_SIZE};
+
+fuzz_target!(|input: ([u8; SLOT_TABLE_SIZE], [u8; ab::HEADER_SIZE])| {
+    let (table, header) = input;
+    let table = SlotTable::from_bytes(&table);
+
+    assert_eq!(SlotTable::from_bytes(&table.to_bytes()), table);
+
+    if let Some(header) = ab::Header::from_bytes(&header) {
+        assert_eq!(ab::Header::from_bytes(&header.to_bytes()), Some(header));
+    }
+});
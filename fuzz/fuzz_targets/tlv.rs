@@ -0,0 +1,13 @@
+//! Key-value containers as stored in the content: parsing must never panic, and whatever parses must serialize back
+//! into the same bytes.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vki2cfile::tlv;
+
+fuzz_target!(|content: &[u8]| {
+    if let Ok(entries) = tlv::parse(content) {
+        assert_eq!(tlv::serialize(&entries), content);
+    }
+});
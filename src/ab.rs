@@ -0,0 +1,132 @@
+//! A/B layout, for updates that keep the previous file intact until the new one is fully written and verified.
+//!
+//! The space for content is split into two halves, A and B, each holding a file behind its own header. A write always
+//! targets the inactive half, and is committed by writing the header of that half once its content is written and
+//! read back, with a sequence number one higher than the one of the active half. The active half is the one whose
+//! header is valid and has the highest sequence number, so a write interrupted at any point leaves the previously
+//! active half in use.
+//!
+//! The metadata block has `FLAG_AB` set and describes no content. Each half starts with a header of `HEADER_SIZE`
+//! bytes, all multi-byte fields being little-endian and the remaining bytes zero:
+//!
+//! | Bytes   | Field               |
+//! |---------|---------------------|
+//! | `0..2`  | `size`              |
+//! | `2..4`  | `crc`               |
+//! | `4..8`  | `sequence`          |
+//! | `8..10` | CRC of bytes `0..8` |
+
+use std::ops::Range;
+
+/// Size of the header at the start of each half in bytes, a whole 32-byte block so that it is written at once.
+pub const HEADER_SIZE: usize = 32;
+
+/// Names of the halves, by index.
+pub const HALF_NAMES: [&str; 2] = ["A", "B"];
+
+/// CRC algorithm used for the header, the same as for the content.
+const HEADER_CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_USB);
+
+/// Header of a half, describing the file stored in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub size: u16,
+    pub crc: u16,
+    /// Incremented on every write, the half with the highest one being the active half.
+    pub sequence: u32,
+}
+
+impl Header {
+    pub fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+
+        bytes[0..2].copy_from_slice(&self.size.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.crc.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+
+        let crc = HEADER_CRC.checksum(&bytes[0..8]);
+        bytes[8..10].copy_from_slice(&crc.to_le_bytes());
+
+        bytes
+    }
+
+    /// Parse a header. Returns `None` if its CRC does not match, e.g. as the half was never written or the write of
+    /// the header was interrupted.
+    pub fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Option<Self> {
+        if u16::from_le_bytes([bytes[8], bytes[9]]) != HEADER_CRC.checksum(&bytes[0..8]) {
+            return None;
+        }
+
+        Some(Self {
+            size: u16::from_le_bytes([bytes[0], bytes[1]]),
+            crc: u16::from_le_bytes([bytes[2], bytes[3]]),
+            sequence: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        })
+    }
+}
+
+/// Index of the active half given the headers of both halves, or `None` if neither holds a file.
+pub fn active(headers: &[Option<Header>; 2]) -> Option<usize> {
+    match headers {
+        [Some(a), Some(b)] => Some(if b.sequence > a.sequence { 1 } else { 0 }),
+        [Some(_), None] => Some(0),
+        [None, Some(_)] => Some(1),
+        [None, None] => None,
+    }
+}
+
+/// Addresses of half `index` within the space for content `start..end`, both halves starting on a 32-byte block.
+pub fn half(start: u16, end: u16, index: usize) -> Range<u16> {
+    let size = (end - start) / 2 / 32 * 32;
+    let half_start = start + index as u16 * size;
+
+    half_start..half_start + size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let header = Header { size: 100, crc: 0xBEEF, sequence: 7 };
+
+        assert_eq!(Header::from_bytes(&header.to_bytes()), Some(header));
+    }
+
+    #[test]
+    fn unwritten_or_corrupted_header_is_invalid() {
+        let mut bytes = Header { size: 100, crc: 0xBEEF, sequence: 7 }.to_bytes();
+        bytes[4] ^= 1;
+
+        assert_eq!(Header::from_bytes(&bytes), None);
+        assert_eq!(Header::from_bytes(&[0xFF; HEADER_SIZE]), None);
+        assert_eq!(Header::from_bytes(&[0; HEADER_SIZE]), None);
+    }
+
+    #[test]
+    fn active_half_has_highest_sequence() {
+        let header = |sequence| Some(Header { size: 1, crc: 0, sequence });
+
+        assert_eq!(active(&[header(3), header(4)]), Some(1));
+        assert_eq!(active(&[header(5), header(4)]), Some(0));
+        assert_eq!(active(&[None, header(4)]), Some(1));
+        assert_eq!(active(&[None, None]), None);
+    }
+
+    #[test]
+    fn halves_are_aligned_and_disjoint() {
+        assert_eq!(half(32, 8192, 0), 32..4096);
+        assert_eq!(half(32, 8192, 1), 4096..8160);
+        assert_eq!(half(32, 8064, 1), 4032..8032);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn arbitrary_headers_round_trip(size: u16, crc: u16, sequence: u32) {
+            let header = Header { size, crc, sequence };
+
+            proptest::prop_assert_eq!(Header::from_bytes(&header.to_bytes()), Some(header));
+        }
+    }
+}
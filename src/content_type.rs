@@ -0,0 +1,102 @@
+//! Hint of the kind of content stored, so that a reader can tell e.g. a JSON configuration from a binary firmware
+//! blob without knowing the role of the module.
+//!
+//! The hint is stored as a code in the bits of the metadata flags covered by `CONTENT_TYPE_MASK`, 0 meaning unknown.
+
+use crate::metadata::CONTENT_TYPE_MASK;
+
+/// Position of the lowest bit of the code in the metadata flags.
+const SHIFT: u32 = CONTENT_TYPE_MASK.trailing_zeros();
+
+/// Kind of content stored.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    /// JSON document.
+    Json = 1,
+    /// Other UTF-8 text.
+    Text = 2,
+    /// Gzip-compressed data.
+    Gzip = 3,
+    /// ELF executable, e.g. a firmware image.
+    Elf = 4,
+    /// Any other binary data.
+    Binary = 5,
+}
+
+/// All content types, by code.
+const CONTENT_TYPES: [ContentType; 5] = [ContentType::Json, ContentType::Text, ContentType::Gzip, ContentType::Elf, ContentType::Binary];
+
+impl ContentType {
+    /// Content type stored in metadata `flags`, or `None` if unknown.
+    pub fn from_flags(flags: u16) -> Option<Self> {
+        let code = (flags & CONTENT_TYPE_MASK) >> SHIFT;
+
+        CONTENT_TYPES.into_iter().find(|content_type| *content_type as u16 == code)
+    }
+
+    /// Metadata flags storing the content type.
+    pub fn to_flags(self) -> u16 {
+        (self as u16) << SHIFT
+    }
+
+    /// Name of the content type, the same as the value of `--content-type` selecting it.
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentType::Json => "json",
+            ContentType::Text => "text",
+            ContentType::Gzip => "gzip",
+            ContentType::Elf => "elf",
+            ContentType::Binary => "binary",
+        }
+    }
+
+    /// Infer the content type of `content` from its first bytes, or from it being text.
+    pub fn sniff(content: &[u8]) -> Self {
+        if content.starts_with(&[0x1f, 0x8b]) {
+            return ContentType::Gzip;
+        }
+
+        if content.starts_with(b"\x7fELF") {
+            return ContentType::Elf;
+        }
+
+        let Ok(text) = std::str::from_utf8(content) else {
+            return ContentType::Binary;
+        };
+
+        if content.is_empty() || text.chars().any(|char| char.is_control() && !char.is_ascii_whitespace()) {
+            return ContentType::Binary;
+        }
+
+        match text.trim_start().chars().next() {
+            Some('{' | '[') => ContentType::Json,
+            _ => ContentType::Text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_common_content() {
+        assert_eq!(ContentType::sniff(b"  {\"gain\": 1.5}\n"), ContentType::Json);
+        assert_eq!(ContentType::sniff(b"[1, 2]"), ContentType::Json);
+        assert_eq!(ContentType::sniff(b"gain=1.5\n"), ContentType::Text);
+        assert_eq!(ContentType::sniff(&[0x1f, 0x8b, 8, 0]), ContentType::Gzip);
+        assert_eq!(ContentType::sniff(b"\x7fELF\x02\x01"), ContentType::Elf);
+        assert_eq!(ContentType::sniff(&[0x00, 0x42, 0xFF]), ContentType::Binary);
+        assert_eq!(ContentType::sniff(&[]), ContentType::Binary);
+    }
+
+    #[test]
+    fn round_trips_through_flags() {
+        for content_type in CONTENT_TYPES {
+            assert_eq!(ContentType::from_flags(content_type.to_flags() | 1), Some(content_type));
+            assert_eq!(content_type.to_flags() & !CONTENT_TYPE_MASK, 0);
+        }
+
+        assert_eq!(ContentType::from_flags(0x00FF), None);
+    }
+}
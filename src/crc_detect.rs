@@ -0,0 +1,61 @@
+//! Detection of the CRC algorithm a stored content CRC was computed with, for EEPROMs written by other tools.
+
+use crc::{Algorithm, Crc};
+
+/// 16-bit algorithms of the CRC catalogue, along with their catalogue names.
+const ALGORITHMS: [(&str, &Algorithm<u16>); 31] = [
+    ("CRC-16/ARC", &crc::CRC_16_ARC),
+    ("CRC-16/CDMA2000", &crc::CRC_16_CDMA2000),
+    ("CRC-16/CMS", &crc::CRC_16_CMS),
+    ("CRC-16/DDS-110", &crc::CRC_16_DDS_110),
+    ("CRC-16/DECT-R", &crc::CRC_16_DECT_R),
+    ("CRC-16/DECT-X", &crc::CRC_16_DECT_X),
+    ("CRC-16/DNP", &crc::CRC_16_DNP),
+    ("CRC-16/EN-13757", &crc::CRC_16_EN_13757),
+    ("CRC-16/GENIBUS", &crc::CRC_16_GENIBUS),
+    ("CRC-16/GSM", &crc::CRC_16_GSM),
+    ("CRC-16/IBM-3740", &crc::CRC_16_IBM_3740),
+    ("CRC-16/IBM-SDLC", &crc::CRC_16_IBM_SDLC),
+    ("CRC-16/ISO-IEC-14443-3-A", &crc::CRC_16_ISO_IEC_14443_3_A),
+    ("CRC-16/KERMIT", &crc::CRC_16_KERMIT),
+    ("CRC-16/LJ1200", &crc::CRC_16_LJ1200),
+    ("CRC-16/M17", &crc::CRC_16_M17),
+    ("CRC-16/MAXIM-DOW", &crc::CRC_16_MAXIM_DOW),
+    ("CRC-16/MCRF4XX", &crc::CRC_16_MCRF4XX),
+    ("CRC-16/MODBUS", &crc::CRC_16_MODBUS),
+    ("CRC-16/NRSC-5", &crc::CRC_16_NRSC_5),
+    ("CRC-16/OPENSAFETY-A", &crc::CRC_16_OPENSAFETY_A),
+    ("CRC-16/OPENSAFETY-B", &crc::CRC_16_OPENSAFETY_B),
+    ("CRC-16/PROFIBUS", &crc::CRC_16_PROFIBUS),
+    ("CRC-16/RIELLO", &crc::CRC_16_RIELLO),
+    ("CRC-16/SPI-FUJITSU", &crc::CRC_16_SPI_FUJITSU),
+    ("CRC-16/T10-DIF", &crc::CRC_16_T10_DIF),
+    ("CRC-16/TELEDISK", &crc::CRC_16_TELEDISK),
+    ("CRC-16/TMS37157", &crc::CRC_16_TMS37157),
+    ("CRC-16/UMTS", &crc::CRC_16_UMTS),
+    ("CRC-16/USB", &crc::CRC_16_USB),
+    ("CRC-16/XMODEM", &crc::CRC_16_XMODEM),
+];
+
+/// Names of the algorithms for which the CRC of `content` is `crc`.
+pub fn matching(content: &[u8], crc: u16) -> Vec<&'static str> {
+    ALGORITHMS.iter()
+        .filter(|(_, algorithm)| Crc::<u16>::new(algorithm).checksum(content) == crc)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_the_algorithm_used() {
+        let content = b"123456789";
+
+        // The check values of the catalogue, i.e. the CRC of "123456789".
+        assert!(matching(content, 0xB4C8).contains(&"CRC-16/USB"));
+        assert!(matching(content, 0x29B1).contains(&"CRC-16/IBM-3740"));
+        assert!(matching(content, 0x1234).is_empty());
+    }
+}
@@ -0,0 +1,579 @@
+//! Access to the I2C device the EEPROM sits behind, abstracted so that the EEPROM logic can run against a mock, and
+//! so that the tool builds on platforms without an I2C backend, where opening a device fails at runtime.
+//!
+//! `simulate` opens a simulated EEPROM persisted to a file instead of an I2C device, see `image`.
+
+#[cfg(target_os = "linux")]
+pub use linux::{open, simulate, PlatformDevice};
+#[cfg(not(target_os = "linux"))]
+pub use unsupported::{open, simulate, PlatformDevice};
+
+/// I2C device addressed at the EEPROM.
+pub trait Device {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Write `data` in a single transaction.
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Write `data`, then read `buffer.len()` bytes into `buffer` after a repeated start, in a single transaction.
+    fn write_read(&mut self, data: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Check whether `error` is the device not acknowledging, as opposed to e.g. a failure of the bus or adapter.
+    fn is_nack(error: &Self::Error) -> bool;
+
+    /// Errno of `error`, if it has one.
+    fn errno(error: &Self::Error) -> Option<i32>;
+
+    /// Largest number of bytes, address included, that `write` can send in one transaction, or `None` if unlimited.
+    fn max_write_size(&self) -> Option<usize>;
+}
+
+/// Backend using the Linux i2c-dev interface.
+///
+/// Adapters that only support SMBus, such as some USB dongles and PC SMBus controllers, cannot make the plain I2C
+/// transfers the EEPROM is normally accessed with. For them, accesses are emulated with SMBus transactions: the first
+/// address byte is sent as the SMBus command and the rest as data, so that writes are I2C block writes of at most 32
+/// bytes after the command, and reads set the address pointer then read the bytes one at a time, with a stop rather
+/// than a repeated start in between.
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::os::fd::{AsRawFd, RawFd};
+    use std::time::Duration;
+    use i2cdev::core::{I2CDevice, I2CMessage, I2CTransfer};
+    use i2cdev::linux::{LinuxI2CDevice, LinuxI2CError, LinuxI2CMessage};
+    use crate::watchdog;
+    use super::image::ImageDevice;
+    use super::Device;
+
+    /// `I2C_FUNCS` ioctl request, see `linux/i2c-dev.h`.
+    const I2C_FUNCS: u64 = 0x0705;
+    /// Adapter supports plain I2C-level commands, see `linux/i2c.h`.
+    const I2C_FUNC_I2C: libc::c_ulong = 0x0000_0001;
+    /// SMBus transactions the emulation needs, see `linux/i2c.h`: quick (for zero-length writes, as when ACK polling),
+    /// receive byte, send byte (for the mux), write byte data and I2C block write.
+    const I2C_FUNC_SMBUS_EMULATION: libc::c_ulong = 0x0001_0000 | 0x0002_0000 | 0x0004_0000 | 0x0010_0000 | 0x0800_0000;
+    /// Largest number of data bytes of an SMBus block transaction.
+    const SMBUS_BLOCK_MAX: usize = 32;
+    /// `I2C_TIMEOUT` ioctl request, setting the adapter timeout in units of 10 ms, see `linux/i2c-dev.h`.
+    const I2C_TIMEOUT: u64 = 0x0702;
+
+    /// I2C device of the platform.
+    pub enum PlatformDevice {
+        Bus(BusDevice),
+        /// EEPROM simulated by an image file.
+        Image(ImageDevice),
+    }
+
+    /// Device on an I2C bus.
+    pub struct BusDevice {
+        device: LinuxI2CDevice,
+        /// Functionality of the adapter, queried once when opening the device, zero if the adapter did not report it.
+        functionality: libc::c_ulong,
+        /// Whether accesses are emulated with SMBus transactions, as the adapter lacks plain I2C transfers.
+        smbus: bool,
+    }
+
+    /// Open the device at `address` on the bus at `path`, e.g. `/dev/i2c-3`, falling back to SMBus transactions if
+    /// the adapter supports them but not plain I2C transfers.
+    pub fn open(path: &str, address: u16) -> Result<PlatformDevice, LinuxI2CError> {
+        let device = LinuxI2CDevice::new(path, address)?;
+        let mut functionality: libc::c_ulong = 0;
+
+        // SAFETY: `I2C_FUNCS` writes a single `unsigned long` into the pointed-to value.
+        if unsafe { libc::ioctl(device.as_raw_fd(), I2C_FUNCS as _, &mut functionality as *mut libc::c_ulong) } < 0 {
+            functionality = 0;
+        }
+
+        let smbus = functionality & I2C_FUNC_I2C == 0 && functionality & I2C_FUNC_SMBUS_EMULATION == I2C_FUNC_SMBUS_EMULATION;
+
+        Ok(PlatformDevice::Bus(BusDevice { device, functionality, smbus }))
+    }
+
+    /// Open the EEPROM simulated in the file at `path` with write cycles of `write_cycle`, see `ImageDevice::simulate`.
+    pub fn simulate(path: &str, write_cycle: Duration) -> Result<PlatformDevice, LinuxI2CError> {
+        Ok(PlatformDevice::Image(ImageDevice::simulate(path, write_cycle)?))
+    }
+
+    impl PlatformDevice {
+        /// Set the time the adapter waits for a transfer to complete before failing it, if it supports that. The
+        /// timeout applies to all users of the adapter until changed.
+        pub fn set_timeout(&self, timeout: Duration) -> bool {
+            let units = timeout.as_millis().div_ceil(10).max(1) as libc::c_ulong;
+
+            // SAFETY: `I2C_TIMEOUT` takes its argument by value.
+            matches!(self, PlatformDevice::Bus(bus) if unsafe { libc::ioctl(bus.device.as_raw_fd(), I2C_TIMEOUT as _, units) >= 0 })
+        }
+
+        /// Whether the adapter supports plain I2C transfers, as reported when the device was opened. Simulated
+        /// EEPROMs do.
+        pub fn supports_i2c(&self) -> bool {
+            match self {
+                PlatformDevice::Bus(bus) => bus.functionality & I2C_FUNC_I2C != 0,
+                PlatformDevice::Image(_) => true,
+            }
+        }
+    }
+
+    impl BusDevice {
+        fn write_unguarded(&mut self, data: &[u8]) -> Result<(), LinuxI2CError> {
+            if !self.smbus {
+                return I2CDevice::write(&mut self.device, data);
+            }
+
+            match data {
+                [] => self.device.smbus_write_quick(false),
+                [value] => self.device.smbus_write_byte(*value),
+                [command, value] => self.device.smbus_write_byte_data(*command, *value),
+                [_, values @ ..] if values.len() > SMBUS_BLOCK_MAX => Err(LinuxI2CError::Errno(libc::EMSGSIZE)),
+                [command, values @ ..] => self.device.smbus_write_i2c_block_data(*command, values),
+            }
+        }
+
+        fn write_read_unguarded(&mut self, data: &[u8], buffer: &mut [u8]) -> Result<(), LinuxI2CError> {
+            if !self.smbus {
+                return self.device.transfer(&mut [LinuxI2CMessage::write(data), LinuxI2CMessage::read(buffer)]).map(drop);
+            }
+
+            self.write_unguarded(data)?;
+
+            for byte in buffer {
+                *byte = self.device.smbus_read_byte()?;
+            }
+
+            Ok(())
+        }
+    }
+
+    impl AsRawFd for PlatformDevice {
+        fn as_raw_fd(&self) -> RawFd {
+            match self {
+                PlatformDevice::Bus(bus) => bus.device.as_raw_fd(),
+                PlatformDevice::Image(image) => image.file().as_raw_fd(),
+            }
+        }
+    }
+
+    impl Device for PlatformDevice {
+        type Error = LinuxI2CError;
+
+        fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            match self {
+                PlatformDevice::Bus(bus) => watchdog::transfer(|| bus.write_unguarded(data)),
+                PlatformDevice::Image(image) => Ok(image.write(data)?),
+            }
+        }
+
+        fn write_read(&mut self, data: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+            match self {
+                PlatformDevice::Bus(bus) => watchdog::transfer(|| bus.write_read_unguarded(data, buffer)),
+                PlatformDevice::Image(image) => Ok(image.write_read(data, buffer)?),
+            }
+        }
+
+        /// Adapter drivers report a NACK as `ENXIO` or `EREMOTEIO`, see the kernel's `i2c/fault-codes.rst`.
+        fn is_nack(error: &Self::Error) -> bool {
+            matches!(Self::errno(error), Some(libc::ENXIO | libc::EREMOTEIO))
+        }
+
+        fn errno(error: &Self::Error) -> Option<i32> {
+            match error {
+                LinuxI2CError::Errno(errno) => Some(*errno),
+                LinuxI2CError::Io(error) => error.raw_os_error(),
+            }
+        }
+
+        fn max_write_size(&self) -> Option<usize> {
+            match self {
+                // The command byte comes on top of the block.
+                PlatformDevice::Bus(bus) => bus.smbus.then_some(SMBUS_BLOCK_MAX + 1),
+                PlatformDevice::Image(_) => None,
+            }
+        }
+    }
+}
+
+/// Backend for platforms without I2C support, which fails to open any device but EEPROM images.
+#[cfg(not(target_os = "linux"))]
+mod unsupported {
+    use std::io;
+    use super::image::ImageDevice;
+    use super::Device;
+
+    /// I2C device of the platform, which can only be an EEPROM image.
+    pub enum PlatformDevice {
+        Image(ImageDevice),
+    }
+
+    /// Fail to open the device at `path`, as the platform has no I2C backend.
+    pub fn open(path: &str, _address: u16) -> io::Result<PlatformDevice> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, format!("cannot open '{path}': I2C devices are only supported on Linux")))
+    }
+
+    /// Open the EEPROM simulated in the file at `path` with write cycles of `write_cycle`, see `ImageDevice::simulate`.
+    pub fn simulate(path: &str, write_cycle: std::time::Duration) -> io::Result<PlatformDevice> {
+        ImageDevice::simulate(path, write_cycle).map(PlatformDevice::Image)
+    }
+
+    impl PlatformDevice {
+        pub fn set_timeout(&self, _timeout: std::time::Duration) -> bool {
+            false
+        }
+
+        /// Whether the adapter supports plain I2C transfers, which EEPROM images always do.
+        pub fn supports_i2c(&self) -> bool {
+            true
+        }
+    }
+
+    impl Device for PlatformDevice {
+        type Error = io::Error;
+
+        fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let PlatformDevice::Image(image) = self;
+
+            image.write(data)
+        }
+
+        fn write_read(&mut self, data: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+            let PlatformDevice::Image(image) = self;
+
+            image.write_read(data, buffer)
+        }
+
+        fn is_nack(error: &Self::Error) -> bool {
+            error.raw_os_error() == Some(libc::ENXIO)
+        }
+
+        fn errno(error: &Self::Error) -> Option<i32> {
+            error.raw_os_error()
+        }
+
+        fn max_write_size(&self) -> Option<usize> {
+            None
+        }
+    }
+}
+
+/// EEPROM simulated by a file holding an image of its memory, to run the tool end to end without hardware, e.g. for
+/// dry runs and tests of scripts. It is opened instead of an I2C device with `simulate`.
+///
+/// It behaves like a 24xx part with 32-byte pages and 16-bit addresses: writes roll over within their page, reads
+/// roll over at the end of the memory. Every write is saved to the file right away. After a write, the part does not
+/// acknowledge anything until its write cycle is over, as a real one, which ACK polling waits for. The I2C address is
+/// ignored.
+pub mod image {
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use std::time::{Duration, Instant};
+    use crate::eeprom::EEPROM_SIZE;
+
+    /// Size of the pages of the simulated part.
+    const PAGE_SIZE: usize = 32;
+
+    /// Write cycle of the simulated part, the longest the MK24C64 takes.
+    pub const WRITE_CYCLE: Duration = Duration::from_millis(5);
+
+    pub struct ImageDevice {
+        file: File,
+        memory: Vec<u8>,
+        /// Address the next read starts at.
+        pointer: usize,
+        write_cycle: Duration,
+        /// End of the write cycle in progress, if any.
+        busy_until: Option<Instant>,
+    }
+
+    impl ImageDevice {
+        /// Open the image at `path`, whose size is that of the simulated EEPROM, without write cycles.
+        pub fn open(path: &str) -> io::Result<ImageDevice> {
+            let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+            let mut memory = Vec::new();
+
+            file.read_to_end(&mut memory)?;
+
+            if memory.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("EEPROM image '{path}' is empty")));
+            }
+
+            Ok(ImageDevice { file, memory, pointer: 0, write_cycle: Duration::ZERO, busy_until: None })
+        }
+
+        /// Open the MK24C64 simulated in the file at `path`, with write cycles of `write_cycle`, creating it blank
+        /// (all 0xFF) if it does not exist.
+        pub fn simulate(path: &str, write_cycle: Duration) -> io::Result<ImageDevice> {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => file.write_all(&[0xFF; EEPROM_SIZE as usize])?,
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(error) => return Err(error),
+            }
+
+            let image = ImageDevice::open(path)?;
+
+            if image.memory.len() != EEPROM_SIZE as usize {
+                let message = format!("simulated EEPROM '{path}' holds {} bytes instead of {EEPROM_SIZE}", image.memory.len());
+
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+
+            Ok(ImageDevice { write_cycle, ..image })
+        }
+
+        pub fn file(&self) -> &File {
+            &self.file
+        }
+
+        /// Write `data`: a 2-byte address followed by the bytes to write there. Shorter writes, such as selecting a
+        /// mux channel, change nothing.
+        pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+            if self.busy_until.is_some_and(|busy_until| Instant::now() < busy_until) {
+                return Err(io::Error::from_raw_os_error(libc::ENXIO));
+            }
+
+            let [high, low, data @ ..] = data else {
+                return Ok(());
+            };
+
+            let address = u16::from_be_bytes([*high, *low]) as usize % self.memory.len();
+            let page = address - address % PAGE_SIZE;
+            let page_end = (page + PAGE_SIZE).min(self.memory.len());
+
+            for (index, byte) in data.iter().enumerate() {
+                self.memory[page + (address - page + index) % (page_end - page)] = *byte;
+            }
+
+            self.pointer = address;
+
+            if !data.is_empty() {
+                self.busy_until = Some(Instant::now() + self.write_cycle).filter(|_| !self.write_cycle.is_zero());
+                self.file.seek(SeekFrom::Start(page as u64))?;
+                self.file.write_all(&self.memory[page..page_end])?;
+            }
+
+            Ok(())
+        }
+
+        /// Set the address from the 2 bytes of `data`, then read `buffer.len()` bytes from there.
+        pub fn write_read(&mut self, data: &[u8], buffer: &mut [u8]) -> io::Result<()> {
+            self.write(data)?;
+
+            for byte in buffer {
+                *byte = self.memory[self.pointer];
+                self.pointer = (self.pointer + 1) % self.memory.len();
+            }
+
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn reads_and_writes_the_image() {
+            let path = std::env::temp_dir().join(format!("vki2cfile-image-{}.bin", std::process::id()));
+            std::fs::write(path.as_path(), [0xFF; 128]).unwrap();
+
+            let mut image = ImageDevice::open(path.to_str().unwrap()).unwrap();
+            image.write(&[0x00, 0x1E, 1, 2, 3, 4]).unwrap();
+
+            // The write rolls over to the start of its page.
+            let mut buffer = [0; 4];
+            image.write_read(&[0x00, 0x1E], &mut buffer[..2]).unwrap();
+            image.write_read(&[0x00, 0x00], &mut buffer[2..]).unwrap();
+            assert_eq!(buffer, [1, 2, 3, 4]);
+
+            // Reads roll over at the end of the memory.
+            image.write_read(&[0x00, 0x7F], &mut buffer[..2]).unwrap();
+            assert_eq!(buffer[..2], [0xFF, 3]);
+
+            let saved = std::fs::read(path.as_path()).unwrap();
+            assert_eq!(saved[0x1E..0x20], [1, 2]);
+            assert_eq!(saved[..2], [3, 4]);
+
+            std::fs::remove_file(path).unwrap();
+        }
+
+        #[test]
+        fn simulated_eeprom_is_busy_during_write_cycles() {
+            let path = std::env::temp_dir().join(format!("vki2cfile-simulated-{}.bin", std::process::id()));
+            let _ = std::fs::remove_file(path.as_path());
+
+            let mut image = ImageDevice::simulate(path.to_str().unwrap(), Duration::from_millis(50)).unwrap();
+            assert_eq!(std::fs::read(path.as_path()).unwrap(), [0xFF; EEPROM_SIZE as usize]);
+
+            image.write(&[0x00, 0x20, 1]).unwrap();
+            assert_eq!(image.write(&[0x00, 0x20]).unwrap_err().raw_os_error(), Some(libc::ENXIO));
+
+            std::thread::sleep(Duration::from_millis(60));
+            let mut buffer = [0; 2];
+            image.write_read(&[0x00, 0x20], &mut buffer).unwrap();
+            assert_eq!(buffer, [1, 0xFF]);
+
+            std::fs::write(path.as_path(), [0xFF; 16]).unwrap();
+            assert!(ImageDevice::simulate(path.to_str().unwrap(), WRITE_CYCLE).is_err());
+
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
+
+/// In-memory EEPROM for tests and examples, behaving like a 24xx part with 32-byte pages. Available to other crates
+/// with the `testing` feature.
+#[cfg(any(test, feature = "testing"))]
+pub mod mock {
+    use super::Device;
+
+    pub struct MockEeprom {
+        pub memory: Vec<u8>,
+        /// Number of data writes after which every write fails, simulating e.g. a power loss.
+        pub writes_left: Option<usize>,
+        /// Number of data writes failing once `writes_left` runs out, after which writes succeed again, simulating a
+        /// transient fault. All of them fail if `None`.
+        pub failing_writes: Option<usize>,
+        /// Number of data writes acknowledged but not programmed, simulating pages failing to program.
+        pub dropped_writes: usize,
+        /// Largest write accepted, address included, simulating an SMBus-only adapter. Longer writes fail.
+        pub max_write_size: Option<usize>,
+        /// Largest read accepted, simulating an adapter limiting the size of messages. Longer reads fail.
+        pub max_read_size: Option<usize>,
+        /// Bytes written by another writer, each given as the number of the read (counting from 1) before which it is
+        /// written, its address and its value.
+        pub concurrent_writes: Vec<(usize, usize, u8)>,
+        /// Bits flipped in bytes read, simulating noise on the bus, each given as the number of the read (counting from
+        /// 1) it happens in, the address of the byte and the bits flipped.
+        pub noisy_reads: Vec<(usize, usize, u8)>,
+        /// Number of transactions not acknowledged after each data write, simulating the device being busy with its
+        /// write cycle.
+        pub write_cycle_nacks: usize,
+        /// Number of reads made so far.
+        pub reads: usize,
+        /// Number of data writes issued so far, failed ones included.
+        pub writes: usize,
+        pointer: usize,
+        /// Number of transactions left to not acknowledge for the write cycle in progress.
+        busy: usize,
+    }
+
+    impl MockEeprom {
+        pub fn new(size: usize) -> Self {
+            MockEeprom { memory: vec![0xFF; size], writes_left: None, failing_writes: None, dropped_writes: 0, max_write_size: None, max_read_size: None, concurrent_writes: Vec::new(), noisy_reads: Vec::new(), write_cycle_nacks: 0, reads: 0, writes: 0, pointer: 0, busy: 0 }
+        }
+    }
+
+    impl Device for MockEeprom {
+        type Error = std::io::Error;
+
+        fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            if self.busy > 0 {
+                self.busy -= 1;
+                return Err(std::io::Error::from_raw_os_error(libc::ENXIO));
+            }
+
+            if self.max_write_size.is_some_and(|max_write_size| data.len() > max_write_size) {
+                return Err(std::io::Error::from_raw_os_error(libc::EMSGSIZE));
+            }
+
+            // An empty write only polls for an acknowledgement, a single byte only sets the address.
+            let (address, bytes) = match data {
+                [] => return Ok(()),
+                [address] => {
+                    self.pointer = *address as usize % self.memory.len();
+                    return Ok(());
+                }
+                _ => data.split_at(2),
+            };
+
+            self.pointer = u16::from_be_bytes([address[0], address[1]]) as usize % self.memory.len();
+
+            if bytes.is_empty() {
+                return Ok(());
+            }
+
+            self.writes += 1;
+
+            match (&mut self.writes_left, &mut self.failing_writes) {
+                (Some(0), Some(0)) => {}
+                (Some(0), Some(failing_writes)) => {
+                    *failing_writes -= 1;
+                    return Err(std::io::Error::from_raw_os_error(libc::EIO));
+                }
+                (Some(0), None) => return Err(std::io::Error::from_raw_os_error(libc::EIO)),
+                (Some(writes_left), _) => *writes_left -= 1,
+                (None, _) => {}
+            }
+
+            if self.dropped_writes > 0 {
+                self.dropped_writes -= 1;
+                return Ok(());
+            }
+
+            // Bytes past the end of the page wrap around to its start.
+            let page_start = self.pointer / 32 * 32;
+
+            for (index, &byte) in bytes.iter().enumerate() {
+                self.memory[page_start + (self.pointer + index) % 32] = byte;
+            }
+
+            self.busy = self.write_cycle_nacks;
+            Ok(())
+        }
+
+        fn write_read(&mut self, data: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+            if self.max_read_size.is_some_and(|max_read_size| buffer.len() > max_read_size) {
+                return Err(std::io::Error::from_raw_os_error(libc::EOPNOTSUPP));
+            }
+
+            self.write(data)?;
+            self.reads += 1;
+
+            for &(_, address, value) in self.concurrent_writes.iter().filter(|(read, _, _)| *read == self.reads) {
+                self.memory[address] = value;
+            }
+
+            for byte in buffer {
+                let noise = self.noisy_reads.iter()
+                    .filter(|&&(read, address, _)| read == self.reads && address == self.pointer)
+                    .fold(0, |noise, &(_, _, bits)| noise | bits);
+
+                *byte = self.memory[self.pointer] ^ noise;
+                self.pointer = (self.pointer + 1) % self.memory.len();
+            }
+
+            Ok(())
+        }
+
+        fn is_nack(error: &Self::Error) -> bool {
+            error.raw_os_error() == Some(libc::ENXIO)
+        }
+
+        fn errno(error: &Self::Error) -> Option<i32> {
+            error.raw_os_error()
+        }
+
+        fn max_write_size(&self) -> Option<usize> {
+            self.max_write_size
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn short_writes_poll_or_set_the_address() {
+            let mut device = MockEeprom::new(256);
+            device.memory[0x42] = 0x5A;
+            device.write_cycle_nacks = 1;
+
+            device.write(&[0x00, 0x10, 1]).unwrap();
+            assert!(MockEeprom::is_nack(&device.write(&[]).unwrap_err()));
+            device.write(&[]).unwrap();
+
+            let mut buffer = [0; 1];
+            device.write_read(&[0x42], &mut buffer).unwrap();
+            assert_eq!(buffer, [0x5A]);
+            assert_eq!(device.memory[0x10], 1);
+        }
+    }
+}
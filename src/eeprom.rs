@@ -0,0 +1,3100 @@
+//! EEPROM holding a file after its metadata: a plain file, or files in slots, or in the halves of an A/B layout, with
+//! an optional history ring of the metadata replaced.
+//!
+//! Options are passed in rather than taken from the command line, and every failure is returned as an `Error` rather
+//! than reported: the command-line tool maps them to its exit codes. Warnings, e.g. on falling back to the other half
+//! of an A/B layout, and details of the transfers go through the `log` macros, and the progress of writes is printed
+//! on stdout with `Options::announce`.
+//!
+//! ```
+//! use vki2cfile::device::mock::MockEeprom;
+//! use vki2cfile::eeprom::{Eeprom, Options, WriteOptions, EEPROM_SIZE};
+//!
+//! let mut eeprom = Eeprom::new(MockEeprom::new(EEPROM_SIZE as usize), Options::default());
+//!
+//! eeprom.write_file(b"{\"gain\": 1.5}", &WriteOptions::default())?;
+//!
+//! assert_eq!(eeprom.read_content()?, b"{\"gain\": 1.5}");
+//! assert_eq!(eeprom.verify(&Default::default())?.content_size, 13);
+//! # Ok::<(), vki2cfile::eeprom::Error>(())
+//! ```
+
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+use sha2::{Digest as _, Sha256};
+use crate::content_type::ContentType;
+use crate::device::Device;
+use crate::geometry::Geometry;
+use crate::history::{self, History, HISTORY_ENTRY_SIZE, HISTORY_SIZE};
+use crate::metadata::{self, FileInfo, Format, Layout, ParseError, CONTENT_TYPE_MASK, FLAG_AB, FLAG_COMPRESSED, FLAG_DIGEST, FLAG_DIRTY, FLAG_ENCRYPTED, FLAG_EXTERNAL_CRC, FLAG_FULL_CRC, FLAG_HISTORY, FLAG_SLOTS, METADATA_SIZE, MODULE_FLAGS};
+use crate::polling::{self, PollError};
+use crate::slots::{Slot, SlotTable, SLOT_TABLE_SIZE};
+use crate::stats::{self, Direction};
+use crate::transaction_log::{self, Region};
+use crate::{ab, i2c_error, interrupt, pages, retry};
+
+/// Total size of the EEPROM in bytes.
+pub const EEPROM_SIZE: u16 = 8192;
+/// Default offset to the address of the first byte in EEPROM where the metadata resides.
+pub const METADATA_OFFSET: u16 = 0;
+/// Default offset to the address of the first byte in EEPROM where the content resides.
+pub const DEFAULT_CONTENT_OFFSET: u16 = 32;
+
+/// CRC algorithm of the content and metadata.
+pub const CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_USB);
+
+/// Direction of a transfer with the EEPROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Read,
+    Write,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Operation::Read => "read",
+            Operation::Write => "write",
+        })
+    }
+}
+
+/// Failure of an access to the EEPROM. All but `InvalidLayout` name the EEPROM they happened on, its `target` (see
+/// `Eeprom::with_target`), and those caused by another error, e.g. of I2C, keep it as their source, see `report`. The
+/// variants from `Busy` on are failures of the command-line tool around the EEPROM, which the library does not return.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The I2C bus device at `path` does not exist.
+    #[error("Failed to open EEPROM at address 0x{address:02x} on {path}: the I2C bus device does not exist.")]
+    DeviceNotFound { path: String, address: u16, #[source] source: Box<dyn std::error::Error + Send + Sync> },
+    /// The EEPROM could not be opened for any other reason.
+    #[error("Failed to open EEPROM at address 0x{address:02x} on {path}.")]
+    DeviceOpen { path: String, address: u16, #[source] source: Box<dyn std::error::Error + Send + Sync> },
+    /// A transfer with the EEPROM at `offset` failed, even after the retries configured.
+    #[error("Failed to {op} EEPROM ({target}) at 0x{offset:04x}.")]
+    Transfer { target: String, op: Operation, offset: u16, #[source] source: Box<dyn std::error::Error + Send + Sync> },
+    /// The metadata in EEPROM cannot be parsed, or describes a file that cannot fit.
+    #[error("Invalid file metadata in EEPROM ({target}): {reason}.")]
+    MetadataInvalid { target: String, reason: String },
+    /// The content in EEPROM does not match the CRC in its metadata, with `detail` telling noise from corruption.
+    #[error("File in EEPROM ({target}) does not exist or is corrupted: CRC of file content 0x{computed:04x} does not match CRC 0x{stored:04x} in its metadata.{detail}")]
+    CrcMismatch { target: String, stored: u16, computed: u16, detail: String },
+    /// The file `file` to write is larger than the space for it.
+    #[error("File {file} ({size} bytes) is too large for EEPROM ({target}). Max allowable size is {max} bytes.")]
+    ContentTooLarge { target: String, file: String, size: usize, max: usize },
+    /// The file in EEPROM does not have the payload version tag required by `ReadOptions::require_payload_version`.
+    #[error("File in EEPROM ({target}) has payload version '{found}', but '{required}' is required.")]
+    PayloadVersionMismatch { target: String, found: String, required: String },
+    /// The metadata in EEPROM is marked as being written, i.e. the previous write was interrupted.
+    #[error("Previous write into EEPROM ({target}) was interrupted, the file in it is incomplete. Write it again.")]
+    WriteInterrupted { target: String },
+    /// The metadata in EEPROM is all 0xFF, i.e. the EEPROM was never written.
+    #[error("EEPROM ({target}) is blank (factory default).")]
+    Blank { target: String },
+    /// A write was stopped by SIGINT or SIGTERM before writing the page at `address`, see the `interrupt` module.
+    #[error("Write into EEPROM ({target}) was interrupted before address 0x{address:04x}.")]
+    Interrupted { target: String, address: u16 },
+    /// The file in EEPROM is empty, though its metadata is valid.
+    #[error("File in EEPROM ({target}) is empty or does not exists.")]
+    Empty { target: String },
+    /// The content in EEPROM does not match its digest, its full CRC or its slot table, with `reason` telling which.
+    #[error("File in EEPROM ({target}) is corrupted: {reason}.")]
+    Corrupted { target: String, reason: String },
+    /// Data other than a file to write, `what`, is larger than the space for it.
+    #[error("Size of {what} ({size} bytes) is too large for EEPROM ({target}). Max allowable size is {max} bytes.")]
+    DataTooLarge { target: String, what: String, size: usize, max: usize },
+    /// The file `file` to write into slot `index` would overlap slot `other`.
+    #[error("File {file} does not fit into slot {index} of EEPROM ({target}): it would overlap slot {other}.")]
+    SlotOverlap { target: String, file: String, index: usize, other: usize },
+    /// A slot other than 0 was accessed in an EEPROM holding `layout`, a plain file or an A/B layout.
+    #[error("EEPROM ({target}) holds {layout}, which can only be accessed as slot 0.")]
+    NotSlotted { target: String, layout: &'static str },
+    /// Slot `index` in EEPROM holds no file.
+    #[error("Slot {index} in EEPROM ({target}) is empty.")]
+    SlotEmpty { target: String, index: usize },
+    /// Writing a slot other than 0 would overwrite the valid plain file in EEPROM with the slot table.
+    #[error("EEPROM ({target}) holds a plain file, which would be overwritten by the slot table. Read it out and write it back with --slot 0 first.")]
+    HoldsPlainFile { target: String },
+    /// Neither half of the A/B layout in EEPROM holds a file.
+    #[error("Neither half of the A/B layout in EEPROM ({target}) holds a file.")]
+    NoActiveHalf { target: String },
+    /// The half of the A/B layout just written does not read back as written. The previous file stays active, unless
+    /// the write was `converting` the EEPROM to the A/B layout, which leaves it marked as being written.
+    #[error("Half {half} of EEPROM ({target}) does not read back as written, {}.", if *.converting { "and the EEPROM was being converted to the A/B layout: write the file again" } else { "the previous file stays active" })]
+    HalfNotWritten { target: String, half: &'static str, converting: bool },
+    /// The file in EEPROM, with `flags`, is not a plain file, so it cannot do `action`.
+    #[error("File in EEPROM ({target}) is not a plain file (flags 0x{flags:04x}), it cannot {action}.")]
+    NotPlainFile { target: String, flags: u16, action: &'static str },
+    /// The EEPROM already holds `what`, which `--force` is required to overwrite.
+    #[error("EEPROM ({target}) already holds {what}. Pass --force to overwrite it.")]
+    AlreadyWritten { target: String, what: String },
+    /// The EEPROM holds no `what`, e.g. no serial number or no record with a given key.
+    #[error("No {what} in EEPROM ({target}).")]
+    NotFound { target: String, what: String },
+    /// The EEPROM is locked against writes, see `check_unlocked`.
+    #[error("EEPROM ({target}) is locked against writes. Run `vki2cfile unlock` first, or pass --force to write anyway.")]
+    Locked { target: String },
+    /// The file in EEPROM is empty and its metadata has no CRC, which `ReadOptions::strict_size` rejects.
+    #[error("File in EEPROM ({target}) is empty but its metadata has no CRC proving it was written (e.g. it is all zero), so with --strict-size the EEPROM is taken to be unprovisioned.")]
+    Unprovisioned { target: String },
+    /// The file in EEPROM is stored in a way this crate cannot decode, with `reason` telling how.
+    #[error("File in EEPROM ({target}) {reason}. Pass --force-raw to read the stored bytes as-is.")]
+    Unsupported { target: String, reason: String },
+    /// The file in EEPROM does not start with the magic `expected`, in hex, of `ReadOptions::expect_magic`.
+    #[error("File in EEPROM ({target}) does not start with the expected magic {expected}.")]
+    MagicMismatch { target: String, expected: String },
+    /// Every byte of the file in EEPROM is `value`, as read from a missing or wrong device, see
+    /// `ReadOptions::sanity_check`.
+    #[error("File content in EEPROM ({target}) is suspicious: all bytes are 0x{value:02X}, check that the right device is being read.")]
+    StuckContent { target: String, value: u8 },
+    /// What was written does not read back as written, with `reason` telling where.
+    #[error("Verification failed on EEPROM ({target}): {reason}.")]
+    Verification { target: String, reason: String },
+    /// A write cannot be resumed with `WriteOptions::resume`, with `reason` telling why.
+    #[error("Cannot resume the write into EEPROM ({target}): {reason}. Write it again without --resume.")]
+    CannotResume { target: String, reason: String },
+    /// The metadata in EEPROM changed during each of the `attempts` to read it, see `Eeprom::read_consistent`.
+    #[error("EEPROM ({target}) contents changed during read, {attempts} times in a row: another writer may be using it.")]
+    Unstable { target: String, attempts: u32 },
+    /// An access of `size` bytes at `offset` would go past the end of the EEPROM at `end`.
+    #[error("Cannot access {size} bytes at 0x{offset:04x} of EEPROM ({target}): only {} bytes are available before its end at 0x{end:04x}.", .end.saturating_sub(*.offset))]
+    OutOfRange { target: String, offset: usize, size: usize, end: usize },
+    /// The EEPROM did not acknowledge within `timeout` after a write, see `WriteCycle::Poll`.
+    #[error("EEPROM ({target}) did not acknowledge within {timeout:?} after a write.")]
+    AckTimeout { target: String, timeout: Duration, #[source] source: Box<dyn std::error::Error + Send + Sync> },
+    /// Polling the EEPROM for the end of its write cycle failed other than by a timeout.
+    #[error("Failed to poll EEPROM ({target}) for the end of its write cycle.")]
+    Poll { target: String, #[source] source: Box<dyn std::error::Error + Send + Sync> },
+    /// The EUI at `offset` reads as all `value`, i.e. there is none there, see `Eeprom::read_eui`.
+    #[error("No EUI at 0x{offset:04x} of EEPROM ({target}): all its bytes are 0x{value:02X}. Check the offset and that the part has a factory-programmed EUI.")]
+    NoEui { target: String, offset: u16, value: u8 },
+    /// The layout passed in `Options::geometry` is invalid, see `check_layout`, with `reason` the message to report.
+    #[error("{reason}")]
+    InvalidLayout { reason: String },
+    /// What was asked of the EEPROM cannot be done as asked, e.g. options that do not go together, with `reason`
+    /// telling why.
+    #[error("Invalid request for EEPROM ({target}): {reason}.")]
+    InvalidRequest { target: String, reason: String },
+    /// The bus of the EEPROM is locked by another instance of the tool, with `lock_path` its lock file.
+    #[error("Device of EEPROM ({target}) is in use by another instance of this tool (lock file '{lock_path}').")]
+    Busy { target: String, lock_path: String },
+    /// The lock file `lock_path` of the bus of the EEPROM could not be taken.
+    #[error("Failed to lock the device of EEPROM ({target}) with lock file '{lock_path}'.")]
+    Lock { target: String, lock_path: String, #[source] source: std::io::Error },
+    /// The mux at `mux` the EEPROM is behind could not be opened, or its `channel` selected.
+    #[error("Failed to {} mux at {mux} in front of EEPROM ({target}).", match .channel { Some(channel) => format!("select channel {channel} of the"), None => "open the".to_string() })]
+    Mux { target: String, mux: String, channel: Option<u8>, #[source] source: Box<dyn std::error::Error + Send + Sync> },
+    /// The transaction log at `path` could not be opened.
+    #[error("Failed to open transaction log '{path}' of EEPROM ({target}).")]
+    TransactionLog { target: String, path: String, #[source] source: std::io::Error },
+    /// The file at `path` to write into the EEPROM could not be read.
+    #[error("Failed to read from file '{path}' to write into EEPROM ({target}).")]
+    SourceFile { target: String, path: String, #[source] source: std::io::Error },
+    /// The file at `path` to write into the EEPROM held `size` bytes, but `read` bytes were read from it.
+    #[error("File '{path}' to write into EEPROM ({target}) changed size while being read: {read} bytes were read instead of {size}.")]
+    SourceChanged { target: String, path: String, size: u64, read: u64 },
+    /// The destination at `path` of a read of the EEPROM exists, and may not be overwritten.
+    #[error("Destination file '{path}' of the read of EEPROM ({target}) exists, pass --force to overwrite it (or --backup to keep a copy).")]
+    DestinationExists { target: String, path: String },
+    /// The content read from the EEPROM could not be saved when trying to do `action`, with `recovery` telling where
+    /// it went instead, if anywhere.
+    #[error("Failed to save the content read from EEPROM ({target}): could not {action}.{}", .recovery.as_ref().map_or(String::new(), |recovery| format!(" {recovery}")))]
+    Destination { target: String, action: String, recovery: Option<String>, #[source] source: std::io::Error },
+    /// The self-test of the EEPROM failed, with `outcome` telling what became of the data in it.
+    #[error("Self-test of EEPROM ({target}) failed, {outcome}.")]
+    SelfTest { target: String, outcome: String, #[source] source: Box<Error> },
+    /// The benchmark of the EEPROM failed, with `outcome` telling what became of the data in it.
+    #[error("Benchmark of EEPROM ({target}) failed, {outcome}.")]
+    Benchmark { target: String, outcome: String, #[source] source: Box<Error> },
+    /// The data in the EEPROM could not be written back after `test`, the self-test or the benchmark.
+    #[error("Failed to write the original data back into EEPROM ({target}) after the {test}, it may be corrupted.")]
+    Restore { target: String, test: &'static str, #[source] source: Box<Error> },
+    /// The command line asks for something that cannot be done, with `reason` telling why.
+    #[error("Invalid command line for EEPROM ({target}): {reason}.")]
+    Usage { target: String, reason: String },
+}
+
+/// Render `error` followed by its chain of sources, each on a `caused by:` line.
+pub fn report(error: &dyn std::error::Error) -> String {
+    let mut report = error.to_string();
+    let mut source = error.source();
+
+    while let Some(error) = source {
+        report += &format!("\ncaused by: {error}");
+        source = error.source();
+    }
+
+    report
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// How to wait for the device to complete its internal write cycle after a write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteCycle {
+    /// Poll the device until it acknowledges again, for up to the given timeout, see the `polling` module.
+    Poll(Duration),
+    /// Sleep for a fixed duration.
+    Delay(Duration),
+    /// Sleep for `min` at first, increasing the delay up to `max` whenever a page does not read back as written, see
+    /// `Eeprom::adaptive_delay`.
+    Adaptive { min: Duration, max: Duration },
+}
+
+/// Delay after each write when the adapter does not support ACK polling.
+pub const DEFAULT_WRITE_DELAY: Duration = Duration::from_millis(10);
+
+/// Typical duration of a write cycle, used to estimate how long writes take with ACK polling.
+const TYPICAL_WRITE_CYCLE: Duration = Duration::from_millis(5);
+
+/// Default number of times a page that does not read back as written is written again.
+pub const DEFAULT_PAGE_RETRIES: u32 = 2;
+
+/// Number of pages written between two updates of the progress of a write, see `Eeprom::write_progress`.
+const PROGRESS_INTERVAL: usize = 8;
+
+/// Largest transfer dumped at the trace level, larger ones would drown the rest.
+const MAX_DUMP_SIZE: usize = 64;
+
+/// Default number of times a failed I2C transfer is retried.
+pub const DEFAULT_IO_RETRIES: u32 = 3;
+
+/// Default maximum number of bytes read in a single transfer.
+pub const DEFAULT_READ_CHUNK: u16 = 1024;
+
+/// Default number of times a read is started over when the metadata changed during it.
+pub const DEFAULT_READ_RETRIES: u32 = 2;
+
+/// Default number of times the content is read again when it does not match its CRC.
+pub const DEFAULT_CRC_RETRIES: u32 = 2;
+
+/// Layout of the EEPROM and how to access it.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Size and layout of the EEPROM and how its memory is addressed, see `check_layout`.
+    pub geometry: Geometry,
+    /// Encoder of v1 metadata blocks.
+    pub metadata_layout: Layout,
+    /// How writes wait for the device, see `Eeprom::set_write_cycle`.
+    pub write_cycle: WriteCycle,
+    /// Delay after reading the metadata.
+    pub read_delay: Duration,
+    /// Number of times a failed I2C transfer is retried.
+    pub io_retries: u32,
+    /// Delays between retries of failed I2C transfers.
+    pub retry_backoff: retry::Backoff,
+    /// Write each byte in its own transaction, for parts and adapters that misbehave with page writes.
+    pub single_byte_writes: bool,
+    /// Maximum number of bytes read in a single transfer.
+    pub read_chunk: u16,
+    /// Halve `read_chunk` whenever the adapter rejects a read as too large, see `Eeprom::read_eeprom_chunks`.
+    pub probe_read_chunk: bool,
+    /// Number of times a read is started over when the metadata changed during it, see `Eeprom::read_consistent`.
+    pub read_retries: u32,
+    /// Number of times the content is read again when it does not match its CRC.
+    pub crc_retries: u32,
+    /// Read content not matching its CRC once more, to tell noise on the bus from corrupted content in the error.
+    pub diagnose: bool,
+    /// Number of reads of the content to take the majority of when it fails its CRC, see `Eeprom::vote_content`.
+    pub read_votes: u32,
+    /// Read each page back once written and write it again, up to `page_retries` times, if it differs.
+    pub verify_pages: bool,
+    /// Number of times a page that does not read back as written is written again.
+    pub page_retries: u32,
+    /// Print how long each write is expected to take, and the outcome of quick verifications and diff writes, on
+    /// stdout.
+    pub announce: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            geometry: Geometry::default(),
+            metadata_layout: Layout::default(),
+            write_cycle: WriteCycle::Delay(DEFAULT_WRITE_DELAY),
+            read_delay: Duration::ZERO,
+            io_retries: DEFAULT_IO_RETRIES,
+            retry_backoff: retry::Backoff::default(),
+            single_byte_writes: false,
+            read_chunk: DEFAULT_READ_CHUNK,
+            probe_read_chunk: false,
+            read_retries: DEFAULT_READ_RETRIES,
+            crc_retries: DEFAULT_CRC_RETRIES,
+            diagnose: false,
+            read_votes: 1,
+            verify_pages: false,
+            page_retries: DEFAULT_PAGE_RETRIES,
+            announce: false,
+        }
+    }
+}
+
+/// Which file to read and how to check it, see `Eeprom::read_file`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReadOptions {
+    /// Slot of the file, slot 0 if `None`.
+    pub slot: Option<u8>,
+    /// Return the content even if it does not match its CRC or digest.
+    pub ignore_crc: bool,
+    /// Return an empty file, or an empty content for a blank EEPROM, instead of failing.
+    pub allow_empty: bool,
+    /// Only return an empty file if its metadata has a CRC proving it was written (v3), failing on a blank EEPROM or
+    /// on metadata of size 0 that cannot be told apart from an unprovisioned part.
+    pub strict_size: bool,
+    /// Return the stored bytes as-is even if the flags say they need processing that cannot be undone here (e.g.
+    /// decryption), or are unknown.
+    pub force_raw: bool,
+    /// Warn if the content is all 0x00 or all 0xFF, which usually means the wrong device was read.
+    pub sanity_check: bool,
+    /// Fail instead of warning when the sanity check flags the content.
+    pub strict: bool,
+    /// Bytes the file must start with, stripped from the content returned.
+    pub expect_magic: Vec<u8>,
+    /// Payload version tag the file must have, if any. Files without a tag have an empty one.
+    pub require_payload_version: Option<String>,
+}
+
+/// How to store a file, see `Eeprom::write_file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Format of the metadata.
+    pub format: Format,
+    /// Store the SHA-256 of the content in a trailer after it (requires v2 metadata).
+    pub digest: bool,
+    /// Mark the content as gzip-compressed, which it must already be (requires v2 metadata).
+    pub compressed: bool,
+    /// Mark the content as encrypted (requires v2 metadata).
+    pub encrypted: bool,
+    /// Slot to write the content into, leaving the other slots untouched, or `None` for a single plain file.
+    pub slot: Option<u8>,
+    /// Address of the slot if it is new (a multiple of 32), right after the last used slot if `None`.
+    pub slot_offset: Option<u16>,
+    /// Type of the content of the slot, free for the user to define.
+    pub slot_type: u8,
+    /// Bytes the content starts with, e.g. a magic expected by a bootloader, left out when sniffing its type.
+    pub magic: Vec<u8>,
+    /// Size to pad the content up to with `pad_byte`. The padding is part of the content, covered by its CRC.
+    pub pad_to: Option<u16>,
+    pub pad_byte: u8,
+    /// Version tag of the content format, stored with v2 metadata and later. Files without a tag have an empty one.
+    pub payload_version: String,
+    /// Record the metadata of the files replaced in a history ring at the end of the EEPROM (requires v2 metadata).
+    pub history: bool,
+    /// Append the content to the file stored instead of replacing it.
+    pub append: bool,
+    /// Write the content into the inactive half of an A/B layout (requires v2 metadata), see `Eeprom::write_ab`.
+    pub ab: bool,
+    /// Store a CRC of the metadata followed by the content in a trailer after them (requires v2 metadata).
+    pub full_crc: bool,
+    /// CRC to store in the metadata instead of computing one over the content, for firmware owning the CRC semantics
+    /// (requires v2 metadata). It is then not checked when reading the file.
+    pub crc: Option<u16>,
+    /// Read the content stored first, and only write the pages that differ from it.
+    pub diff_write: bool,
+    /// Resume an interrupted write of the same content with the same options, see `Eeprom::resume_point`.
+    pub resume: bool,
+    /// Page of the content to start writing from, leaving the earlier ones as they are in EEPROM. Pages are single bytes
+    /// with `Options::single_byte_writes`, as counted by `Eeprom::page_count`.
+    pub start_page: Option<usize>,
+    /// Refuse to replace a valid, non-empty file (or the one in the slot written) unless `force` is set.
+    pub safe: bool,
+    /// Write even if the EEPROM is locked, or holds a valid file with `safe`.
+    pub force: bool,
+    /// Hint of the kind of content to store in the metadata (requires v2 metadata).
+    pub content_type: Option<ContentType>,
+    /// Store a hint of the kind of content inferred from its first bytes, unless `content_type` is given.
+    pub sniff_content_type: bool,
+    /// Also compare the content byte by byte in `Eeprom::is_up_to_date`, as different files may have the same CRC.
+    pub deep: bool,
+    /// Description of the content in errors, e.g. the quoted path of the file it was read from.
+    pub source: String,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            format: Format::default(),
+            digest: false,
+            compressed: false,
+            encrypted: false,
+            slot: None,
+            slot_offset: None,
+            slot_type: 0,
+            magic: Vec::new(),
+            pad_to: None,
+            pad_byte: 0,
+            payload_version: String::new(),
+            history: false,
+            append: false,
+            ab: false,
+            full_crc: false,
+            crc: None,
+            diff_write: false,
+            resume: false,
+            start_page: None,
+            safe: false,
+            force: false,
+            content_type: None,
+            sniff_content_type: false,
+            deep: false,
+            source: "to write".to_string(),
+        }
+    }
+}
+
+/// Check that `geometry` is valid, see `Geometry::check`, and, if the metadata `flags` have a history ring (enabled by a
+/// write or already present), that its content and reserve fit before the ring.
+pub fn check_layout(geometry: &Geometry, flags: u16) -> Result<()> {
+    geometry.check().map_err(|reason| Error::InvalidLayout { reason })?;
+
+    if flags & FLAG_HISTORY == 0 {
+        return Ok(());
+    }
+
+    let history_offset = history_offset(geometry);
+
+    if geometry.content_offset >= history_offset {
+        return Err(Error::InvalidLayout { reason: format!("Invalid content offset: {} leaves no room for content before the history ring at {history_offset}.", geometry.content_offset) });
+    }
+
+    if geometry.content_offset as usize + geometry.reserve as usize >= history_offset as usize {
+        return Err(Error::InvalidLayout { reason: format!("Invalid reserve: {} bytes leave no room for content before the history ring at {history_offset}.", geometry.reserve) });
+    }
+
+    Ok(())
+}
+
+/// Run `transfer`, a single I2C transaction, on `device`, retrying it as configured by `options` if it fails,
+/// describing its failures as of `target`, and count the retries in `retried`.
+pub fn with_retries<D: Device, T>(device: &mut D, options: &Options, target: &str, retried: &mut u64, transfer: impl FnMut(&mut D) -> Result<T, D::Error>) -> Result<T, D::Error> {
+    let retries = options.io_retries;
+
+    retry::retry(device, retries, &options.retry_backoff, transfer, |retry, error| {
+        *retried += 1;
+
+        log::info!("I2C transfer failed: {}. Retrying ({retry}/{retries}).", describe_error::<D>(error, target));
+    })
+}
+
+/// Describe `error`, of an I2C transfer with `target`, explaining its likely cause, see the `i2c_error` module.
+pub fn describe_error<D: Device>(error: &D::Error, target: &str) -> String {
+    i2c_error::describe(error, D::errno(error), target)
+}
+
+/// Check whether `error` is the adapter rejecting a transfer as too large for it.
+fn is_too_large<D: Device>(error: &D::Error) -> bool {
+    matches!(D::errno(error), Some(libc::EOPNOTSUPP | libc::EMSGSIZE))
+}
+
+/// Description of the EEPROM in errors when the device it is accessed through was not named, see `Eeprom::with_target`.
+pub const UNNAMED_TARGET: &str = "unnamed device";
+
+/// EEPROM accessed through `device` with `options`, along with what is learned about it and counted while accessing
+/// it.
+pub struct Eeprom<D: Device> {
+    device: D,
+    options: Options,
+    /// Description of the device, e.g. "address 0x50 on /dev/i2c-3", naming it in errors.
+    target: String,
+    /// Total time spent waiting for the device to complete its write cycles.
+    stall_time: Duration,
+    /// Delay after each write learned so far with adaptive timing, see `Eeprom::adaptive_delay`.
+    adaptive_delay: Duration,
+    /// Number of I2C transfers retried so far.
+    retried_transfers: u64,
+    /// Number of bytes of content corrected by majority vote so far, see `Eeprom::vote_content`.
+    corrected_bytes: u64,
+}
+
+impl<D: Device> Eeprom<D> {
+    pub fn new(device: D, options: Options) -> Self {
+        Eeprom { device, options, target: UNNAMED_TARGET.to_string(), stall_time: Duration::ZERO, adaptive_delay: Duration::ZERO, retried_transfers: 0, corrected_bytes: 0 }
+    }
+
+    /// Name the device in errors with `target`, e.g. "address 0x50 on /dev/i2c-3".
+    pub fn with_target(self, target: impl Into<String>) -> Self {
+        Eeprom { target: target.into(), ..self }
+    }
+
+    /// Description of the device the EEPROM is accessed through, naming it in errors.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Device the EEPROM is accessed through.
+    pub fn device(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    /// Wait for write cycles as `write_cycle` says from now on.
+    pub fn set_write_cycle(&mut self, write_cycle: WriteCycle) {
+        self.options.write_cycle = write_cycle;
+    }
+
+    /// Read each page back once written, writing it again up to `page_retries` times if it differs, from now on.
+    pub fn set_verify_pages(&mut self, verify_pages: bool, page_retries: u32) {
+        self.options.verify_pages = verify_pages;
+        self.options.page_retries = page_retries;
+    }
+
+    /// Total time spent waiting for the device to complete its write cycles.
+    pub fn stall_time(&self) -> Duration {
+        self.stall_time
+    }
+
+    /// Number of I2C transfers retried so far.
+    pub fn retried_transfers(&self) -> u64 {
+        self.retried_transfers
+    }
+
+    /// Read the content of the file, slot 0 of slots or the active half of an A/B layout, checked against its CRC and
+    /// digest.
+    pub fn read_content(&mut self) -> Result<Vec<u8>> {
+        self.read_file(&ReadOptions::default())
+    }
+
+    /// Check that the content of the file described by `read` matches its CRC and digest, and return its metadata, as
+    /// if it were a plain file.
+    pub fn verify(&mut self, read: &ReadOptions) -> Result<FileInfo> {
+        self.read_consistent(|eeprom| {
+            let metadata = eeprom.read_metadata()?;
+            let (offset, metadata) = eeprom.select_slot(metadata, read.slot)?;
+
+            check_payload_version(&metadata, read.require_payload_version.as_deref(), &eeprom.target)?;
+            let content = eeprom.read_content_at(offset, &metadata)?;
+
+            validate_content(&metadata, &content, &eeprom.target)?;
+            Ok(metadata)
+        })
+    }
+
+    /// Erase the whole EEPROM to 0xFF, after which it reads as blank.
+    ///
+    /// ```
+    /// # use vki2cfile::device::mock::MockEeprom;
+    /// # use vki2cfile::eeprom::{Eeprom, Error, Options, EEPROM_SIZE};
+    /// let mut eeprom = Eeprom::new(MockEeprom::new(EEPROM_SIZE as usize), Options::default());
+    ///
+    /// eeprom.write_file(b"old", &Default::default())?;
+    /// eeprom.erase()?;
+    ///
+    /// assert!(matches!(eeprom.read_metadata(), Err(Error::Blank { .. })));
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn erase(&mut self) -> Result<()> {
+        self.write_pages(0, &vec![0xFF; self.options.geometry.size as usize])
+    }
+
+    /// Erase the file in slot `index`, removing it from the slot table and setting its bytes to 0xFF, and return the
+    /// metadata describing the updated table. The other slots are left untouched. An EEPROM holding a single plain file,
+    /// or an A/B layout, only has slot 0, which is erased with the whole EEPROM. A locked EEPROM is only erased with
+    /// `force`, see `check_unlocked`.
+    ///
+    /// The updated table is committed before the content of the slot is erased, so that a failed erase never leaves a
+    /// slot in the table whose content no longer matches it.
+    pub fn erase_slot(&mut self, index: u8, force: bool) -> Result<FileInfo> {
+        let metadata = self.read_metadata_or_empty()?;
+
+        check_unlocked(&metadata, force, &self.target)?;
+
+        let Some(mut table) = self.read_slot_table(&metadata)? else {
+            if index != 0 {
+                let layout = if metadata.has_ab() { "an A/B layout" } else { "a plain file" };
+
+                return Err(Error::NotSlotted { target: self.target.clone(), layout });
+            }
+
+            self.erase()?;
+            return Ok(FileInfo::default());
+        };
+
+        let Some(slot) = table.slots[index as usize].take() else {
+            return Err(Error::SlotEmpty { target: self.target.clone(), index: index as usize });
+        };
+
+        let table_offset = self.options.geometry.metadata_offset + METADATA_SIZE as u16;
+        let table_buffer = table.to_bytes();
+        let erased = FileInfo { content_crc: CRC.checksum(&table_buffer), ..metadata.clone() };
+        let previous_block = self.read_metadata_buffer()?;
+
+        self.mark_dirty(&metadata)?;
+
+        let result = self.write_pages(table_offset, &table_buffer)
+            .and_then(|()| self.commit_metadata(&metadata, &erased));
+        self.roll_back_metadata(&previous_block, result)?;
+
+        self.write_pages(slot.offset, &vec![0xFF; slot.size as usize])?;
+
+        Ok(erased)
+    }
+
+    /// Read the EUI of `size` bytes at `offset` in EEPROM, addressed with a single byte if `short_address`.
+    pub fn read_eui(&mut self, offset: u16, size: usize, short_address: bool) -> Result<Vec<u8>> {
+        let mut eui = vec![0; size];
+
+        if short_address && offset as usize + size > 0x100 {
+            return Err(Error::InvalidRequest { target: self.target.clone(), reason: format!("an EUI of {size} bytes at 0x{offset:04x} is out of reach of single byte addresses") });
+        }
+
+        let result = match short_address {
+            true => with_retries(&mut self.device, &self.options, &self.target, &mut self.retried_transfers, |device| device.write_read(&[offset as u8], &mut eui))
+                .map_err(|error| self.transfer_error(Operation::Read, offset, error)),
+            false => self.read_eeprom(offset, &mut eui),
+        };
+
+        result?;
+
+        if let Some(value) = stuck_at_value(&eui) {
+            return Err(Error::NoEui { target: self.target.clone(), offset, value });
+        }
+
+        Ok(eui)
+    }
+
+    /// Describe `error`, of an I2C transfer with the EEPROM, explaining its likely cause.
+    pub fn describe(&self, error: &D::Error) -> String {
+        describe_error::<D>(error, &self.target)
+    }
+
+    /// Failure to `op` the EEPROM at `offset` with `error`, explained as its source.
+    fn transfer_error(&self, op: Operation, offset: u16, error: D::Error) -> Error {
+        let errno = D::errno(&error);
+
+        Error::Transfer { target: self.target.clone(), op, offset, source: i2c_error::explained(error, errno, &self.target) }
+    }
+
+    /// Most bytes written in a single transaction: a page, or a byte with `--page-write-mode single`.
+    fn write_size(&self) -> u16 {
+        match self.options.single_byte_writes {
+            true => 1,
+            false => self.options.geometry.page_size,
+        }
+    }
+
+    /// Maximum size of content that can be stored in the EEPROM memory.
+    pub fn max_content_size(&self) -> u16 {
+        self.options.geometry.max_content_size()
+    }
+
+    /// Addresses of the halves of the A/B layout, given the flags of its metadata.
+    pub fn ab_halves(&self, flags: u16) -> [std::ops::Range<u16>; 2] {
+        [0, 1].map(|index| ab::half(self.options.geometry.content_offset, content_end(&self.options.geometry, flags), index))
+    }
+
+    /// Number of write transactions needed to write `size` bytes from `offset`.
+    pub fn page_count(&self, offset: u16, size: usize) -> usize {
+        pages::chunks(offset, size, self.write_size()).count()
+    }
+
+    /// Print how long writing `size` bytes of content in `pages` pages, along with `other_writes` writes of metadata
+    /// and the like, is expected to take, given the write cycle of each write.
+    fn print_write_estimate(&self, size: usize, pages: usize, other_writes: usize) {
+        if !self.options.announce {
+            return;
+        }
+
+        let page_duration = match self.options.write_cycle {
+            WriteCycle::Poll(_) => TYPICAL_WRITE_CYCLE,
+            WriteCycle::Delay(delay) => delay,
+            WriteCycle::Adaptive { .. } => self.adaptive_delay(),
+        };
+
+        println!("Writing {size} bytes in {pages} pages, ~{:.1}s.", (page_duration * (pages + other_writes) as u32).as_secs_f64());
+    }
+
+    /// Number of bytes of content corrected by majority vote so far, if `--read-votes` asks for votes.
+    pub fn corrected_bytes(&self) -> Option<u64> {
+        (self.options.read_votes > 1).then_some(self.corrected_bytes)
+    }
+
+    /// Current delay after each write with adaptive timing: the minimum delay until a page fails to read back as
+    /// written.
+    pub fn adaptive_delay(&self) -> Duration {
+        match self.options.write_cycle {
+            WriteCycle::Adaptive { min, .. } => self.adaptive_delay.max(min),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Increase the delay after each write with adaptive timing, doubling it (by at least 1 ms) up to its maximum.
+    /// Returns `false` if it is already the maximum, or if adaptive timing is not used.
+    pub fn increase_adaptive_delay(&mut self) -> bool {
+        let WriteCycle::Adaptive { max, .. } = self.options.write_cycle else {
+            return false;
+        };
+        let delay = self.adaptive_delay();
+
+        if delay >= max {
+            return false;
+        }
+
+        self.adaptive_delay = (delay * 2).max(delay + Duration::from_millis(1)).min(max);
+
+        true
+    }
+
+    /// Wait for the device to complete the internal write cycle following a write.
+    fn wait_for_write_cycle(&mut self) -> Result<()> {
+        let stall_time = match self.options.write_cycle {
+            WriteCycle::Delay(delay) => {
+                std::thread::sleep(delay);
+                delay
+            }
+            WriteCycle::Adaptive { .. } => {
+                let delay = self.adaptive_delay();
+                std::thread::sleep(delay);
+                delay
+            }
+            WriteCycle::Poll(timeout) => match polling::wait_for_ack(&mut self.device, &self.options.geometry, timeout) {
+                Ok(elapsed) => elapsed,
+                Err(PollError::Timeout(error)) => {
+                    let errno = D::errno(&error);
+
+                    return Err(Error::AckTimeout { target: self.target.clone(), timeout, source: i2c_error::explained(error, errno, &self.target) });
+                }
+                Err(PollError::Bus(error)) => {
+                    let errno = D::errno(&error);
+
+                    return Err(Error::Poll { target: self.target.clone(), source: i2c_error::explained(error, errno, &self.target) });
+                }
+            },
+        };
+
+        self.stall_time += stall_time;
+
+        Ok(())
+    }
+
+    /// Run `transfer`, a single I2C transaction of `bytes` bytes (not counting the address) at `offset` in `direction`,
+    /// with retries, counting it for `--stats` and logging it for `--transaction-log`. Transactions before the content
+    /// are logged as on the metadata.
+    fn transaction<T>(&mut self, direction: Direction, offset: u16, bytes: usize, transfer: impl FnMut(&mut D) -> Result<T, D::Error>) -> Result<T, D::Error> {
+        let retried = self.retried_transfers;
+        let start = Instant::now();
+        let result = stats::measure(direction, bytes, || with_retries(&mut self.device, &self.options, &self.target, &mut self.retried_transfers, transfer));
+        let duration = start.elapsed();
+        let region = if offset < self.options.geometry.content_offset { Region::Metadata } else { Region::Content };
+        let error = result.as_ref().err().map(|error| self.describe(error));
+        let retries = self.retried_transfers - retried;
+
+        log::debug!(
+            "{} of {bytes} bytes at 0x{offset:04x} ({region:?}) {} in {duration:?} after {retries} retries",
+            match direction { Direction::Read => "Read", Direction::Write => "Write" },
+            error.as_deref().map_or("succeeded".to_string(), |error| format!("failed: {error}")),
+        );
+
+        transaction_log::log(direction, region, offset, bytes, retries, error.as_deref());
+        result
+    }
+
+    /// Read `buffer.len()` bytes from EEPROM, starting at `offset`, in transfers of at most `--read-chunk` bytes, each
+    /// setting the address pointer again.
+    pub fn read_eeprom(&mut self, offset: u16, buffer: &mut [u8]) -> Result<()> {
+        self.read_eeprom_chunks(offset, buffer, self.options.read_chunk as usize, |_| {})
+    }
+
+    /// Read `buffer.len()` bytes from EEPROM starting at `offset`, in transfers of at most `read_chunk` bytes, passing
+    /// each chunk to `on_chunk` as soon as it is read.
+    ///
+    /// Without `--read-chunk`, a read the adapter rejects as too large is retried with half the chunk size, which is
+    /// then used for the rest of the invocation.
+    ///
+    /// Reads past the end of the EEPROM fail rather than being made: the device would roll its address over to 0 and
+    /// silently return the bytes at the start instead.
+    pub fn read_eeprom_chunks(&mut self, offset: u16, buffer: &mut [u8], mut read_chunk: usize, mut on_chunk: impl FnMut(&[u8])) -> Result<()> {
+        let _progress = stats::progress(Direction::Read, buffer.len());
+        let size = buffer.len();
+        let mut start = 0;
+
+        let eeprom_size = self.options.geometry.size;
+
+        if offset as usize + size > eeprom_size as usize {
+            return Err(Error::OutOfRange { target: self.target.clone(), offset: offset as usize, size, end: eeprom_size as usize });
+        }
+
+        while start < size {
+            let chunk = &mut buffer[start..(start + read_chunk).min(size)];
+            let chunk_size = chunk.len();
+            let offset = offset + start as u16;
+            let address = self.options.geometry.address(offset);
+
+            stats::throttle(chunk_size);
+
+            match self.transaction(Direction::Read, offset, chunk_size, |device| device.write_read(&address, chunk)) {
+                Ok(()) => dump(Direction::Read, offset, chunk),
+                Err(error) if self.options.probe_read_chunk && chunk_size > 1 && is_too_large::<D>(&error) => {
+                    // Halving the chunk size rather than the size of a short last chunk keeps it a power of two.
+                    while read_chunk >= chunk_size {
+                        read_chunk /= 2;
+                    }
+
+                    self.options.read_chunk = read_chunk as u16;
+
+                    log::info!("Adapter rejected a read of {chunk_size} bytes, reading {read_chunk} bytes at a time.");
+
+                    continue;
+                }
+                Err(error) => return Err(self.transfer_error(Operation::Read, offset, error)),
+            }
+
+            on_chunk(chunk);
+            start += chunk_size;
+        }
+
+        Ok(())
+    }
+
+    /// Fail with `Error::Interrupted` before `address` if SIGINT or SIGTERM was received, see the `interrupt` module.
+    fn check_interrupt(&self, address: u16) -> Result<()> {
+        if interrupt::requested() {
+            return Err(Error::Interrupted { target: self.target.clone(), address });
+        }
+
+        Ok(())
+    }
+
+    /// Write `data` into EEPROM starting at `offset`, one transaction per page (or part of a page) written, or per
+    /// byte with `--page-write-mode single`.
+    pub fn write_pages(&mut self, offset: u16, data: &[u8]) -> Result<()> {
+        let single_byte = self.options.single_byte_writes;
+        let _progress = stats::progress(Direction::Write, data.len());
+
+        for (offset, range) in pages::chunks(offset, data.len(), self.write_size()) {
+            self.check_interrupt(offset)?;
+
+            // Always write up to the end of the 32-byte block even if the actual payload size is smaller, but never past
+            // the end of the page. This helps circumvent some bugs with the device itself. These additional bytes don't
+            // matter since we are never going to read them. A retry rewrites the whole chunk. Single-byte writes are not
+            // padded, for parts and adapters that only handle writes of a single byte.
+            let end = offset as usize + range.len();
+            let padded_end = if single_byte { end } else { padding_end(&self.options.geometry, end) };
+            let mut buffer = self.options.geometry.address(offset);
+            let address_size = buffer.len();
+
+            buffer.extend(&data[range.clone()]);
+            buffer.resize(address_size + padded_end - offset as usize, 0);
+
+            let page_retries = self.options.page_retries;
+            let mut attempt = 0;
+            // Adaptive timing learns from reading each page back.
+            let adaptive = matches!(self.options.write_cycle, WriteCycle::Adaptive { .. });
+
+            loop {
+                stats::throttle(buffer.len() - address_size);
+                self.transaction(Direction::Write, offset, buffer.len() - address_size, |device| device.write(&buffer))
+                    .map_err(|error| self.transfer_error(Operation::Write, offset, error))?;
+                dump(Direction::Write, offset, &buffer[address_size..]);
+
+                self.wait_for_write_cycle()?;
+
+                if !self.options.verify_pages && !adaptive {
+                    break;
+                }
+
+                let mut readback = vec![0; range.len()];
+
+                let failure = match self.read_eeprom(offset, readback.as_mut_slice()) {
+                    Ok(()) => match readback.iter().zip(&data[range.clone()]).position(|(read, written)| read != written) {
+                        Some(index) => format!("byte at address 0x{:04x} is 0x{:02x} instead of 0x{:02x}", offset as usize + index, readback[index], data[range.start + index]),
+                        None => break,
+                    },
+                    // The device still being busy with its write cycle means the delay is too short.
+                    Err(error) if adaptive => format!("it cannot be read back: {error}"),
+                    Err(error) => return Err(error),
+                };
+
+                if self.increase_adaptive_delay() {
+                    log::info!("Page written at address 0x{offset:04x} does not read back as written, increasing the write delay to {:?}.", self.adaptive_delay());
+
+                    continue;
+                }
+
+                if attempt == page_retries {
+                    return Err(Error::Verification { target: self.target.clone(), reason: format!("page written at address 0x{offset:04x} does not read back as written after {} attempts: {failure}", attempt + 1) });
+                }
+
+                attempt += 1;
+
+                log::info!("Page written at address 0x{offset:04x} does not read back as written, writing it again ({attempt}/{page_retries}).");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write `data` into EEPROM starting at `offset` like `write_pages`, but skipping the pages whose bytes are the
+    /// same in `previous`, the data currently stored there, and return the number of pages written. Pages past the end
+    /// of `previous` are always written.
+    pub fn write_changed_pages(&mut self, offset: u16, previous: &[u8], data: &[u8]) -> Result<usize> {
+        let mut written = 0;
+
+        for (address, range) in pages::chunks(offset, data.len(), self.options.geometry.page_size) {
+            if previous.get(range.clone()) != Some(&data[range.clone()]) {
+                self.write_pages(address, &data[range])?;
+                written += 1;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Write the file metadata into EEPROM.
+    pub fn write_metadata(&mut self, metadata: &FileInfo) -> Result<()> {
+        let Ok(metadata_block) = <[u8; METADATA_SIZE]>::try_from(metadata.to_bytes_with(self.options.metadata_layout)) else {
+            // Sanity check that the serialized size is the same as the struct size.
+            return Err(Error::MetadataInvalid { target: self.target.clone(), reason: "internal error, unexpected metadata size".to_string() });
+        };
+
+        self.write_metadata_block(&metadata_block)
+    }
+
+    /// Write a raw file metadata block into EEPROM.
+    fn write_metadata_block(&mut self, metadata_block: &[u8; METADATA_SIZE]) -> Result<()> {
+        let mut metadata_buffer = self.options.geometry.address(self.options.geometry.metadata_offset);
+
+        metadata_buffer.extend(metadata_block);
+
+        // The metadata is only committed once this write succeeds, possibly after retries.
+        self.transaction(Direction::Write, self.options.geometry.metadata_offset, METADATA_SIZE, |device| device.write(metadata_buffer.as_slice()))
+            .map_err(|error| self.transfer_error(Operation::Write, self.options.geometry.metadata_offset, error))?;
+        dump(Direction::Write, self.options.geometry.metadata_offset, metadata_block);
+
+        self.wait_for_write_cycle()
+    }
+
+    /// Read the raw file metadata block from EEPROM.
+    fn read_metadata_buffer(&mut self) -> Result<[u8; METADATA_SIZE]> {
+        let mut metadata_buffer = [0; METADATA_SIZE];
+
+        self.read_eeprom(self.options.geometry.metadata_offset, metadata_buffer.as_mut_slice())?;
+
+        std::thread::sleep(self.options.read_delay);
+
+        Ok(metadata_buffer)
+    }
+
+    /// Run `read`, reading the metadata block before and after it, and run it again up to `--read-retries` times if
+    /// the block changed in between, which means another writer (e.g. firmware on the board) changed the EEPROM while
+    /// it was read. The result of `read`, even a failure, is only returned once the block did not change, as a torn
+    /// read would otherwise look like corruption.
+    pub fn read_consistent<T>(&mut self, mut read: impl FnMut(&mut Self) -> Result<T>) -> Result<T> {
+        let retries = self.options.read_retries;
+
+        for attempt in 0..=retries {
+            let before = self.read_metadata_buffer()?;
+            let result = read(self);
+
+            if self.read_metadata_buffer()? == before {
+                return result;
+            }
+
+            if attempt < retries {
+                log::info!("EEPROM metadata changed during read, reading again ({}/{retries}).", attempt + 1);
+            }
+        }
+
+        Err(Error::Unstable { target: self.target.clone(), attempts: retries + 1 })
+    }
+
+    /// Read and parse the file metadata from EEPROM, treating metadata that cannot be parsed (e.g. a blank EEPROM)
+    /// as an empty file.
+    pub fn read_metadata_or_empty(&mut self) -> Result<FileInfo> {
+        Ok(FileInfo::parse_with(&self.read_metadata_buffer()?, self.options.metadata_layout).ok()
+            .filter(|metadata| metadata.content_size <= self.max_content_size())
+            .unwrap_or_default())
+    }
+
+    /// Read and parse the file metadata from EEPROM.
+    pub fn read_metadata(&mut self) -> Result<FileInfo> {
+        let metadata_buffer = self.read_metadata_buffer()?;
+
+        let metadata = FileInfo::parse_with(&metadata_buffer, self.options.metadata_layout).map_err(|error| match error {
+            ParseError::Blank => Error::Blank { target: self.target.clone() },
+            ParseError::Invalid(reason) => self.metadata_invalid(format!(
+                "{reason}. Raw metadata bytes: {}. To read the content stored after it as-is, pass read --ignore-metadata --size <SIZE>",
+                to_hex(&metadata_buffer),
+            )),
+        })?;
+
+        if metadata.is_dirty() {
+            return Err(Error::WriteInterrupted { target: self.target.clone() });
+        }
+
+        check_layout(&self.options.geometry, metadata.flags)?;
+
+        if metadata.content_size > self.max_content_size() {
+            return Err(self.metadata_invalid(format!("file size exceeds maximum possible ({} > {})", metadata.content_size, self.max_content_size())));
+        }
+
+        if metadata.has_digest() && metadata.content_size as usize + DIGEST_SIZE > self.max_content_size() as usize {
+            return Err(self.metadata_invalid(format!("no room left for the digest of the file ({} + {DIGEST_SIZE} > {})", metadata.content_size, self.max_content_size())));
+        }
+
+        if metadata.has_full_crc() && metadata.content_size as usize + trailer_size(&metadata) > self.max_content_size() as usize {
+            return Err(self.metadata_invalid(format!("no room left for the full CRC of the file ({} + {} > {})", metadata.content_size, trailer_size(&metadata), self.max_content_size())));
+        }
+
+        Ok(metadata)
+    }
+
+    fn metadata_invalid(&self, reason: String) -> Error {
+        Error::MetadataInvalid { target: self.target.clone(), reason }
+    }
+
+    /// Read the file content starting at `offset` in EEPROM, along with its digest if the metadata says one is stored.
+    pub fn read_content_at(&mut self, offset: u16, metadata: &FileInfo) -> Result<Content> {
+        self.read_content_at_with(offset, metadata, |_, _| {})
+    }
+
+    /// Same as `read_content_at`, passing the content (but not the trailer) to `on_content` chunk by chunk as it is read,
+    /// along with the position of the chunk in the content. If the content is read again or corrected by majority
+    /// vote, see `vote_content`, it is passed again from position 0.
+    ///
+    /// Content not matching its CRC is read again up to `--crc-retries` times, until it does, or until two reads in a
+    /// row give the same bytes, which means the content stored is corrupted rather than the read. With `--diagnose`,
+    /// it is read once more to compare.
+    pub fn read_content_at_with(&mut self, offset: u16, metadata: &FileInfo, mut on_content: impl FnMut(usize, &[u8])) -> Result<Content> {
+        let retries = self.options.crc_retries;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut attempt = 0;
+
+        let mut content = loop {
+            let mut content = self.read_content_once(offset, metadata, &mut on_content)?;
+
+            if content.crc == metadata.content_crc || content.bytes.is_empty() || metadata.has_external_crc() {
+                return Ok(content);
+            }
+
+            content.differences = previous.as_deref().map(|previous| differences(previous, &content.bytes));
+
+            if attempt == retries || content.differences.as_ref().is_some_and(Vec::is_empty) {
+                break content;
+            }
+
+            log::info!(
+                "Content CRC 0x{:04x} does not match 0x{:04x} in its metadata, reading it again ({}/{retries}).",
+                content.crc, metadata.content_crc, attempt + 1,
+            );
+
+            previous = Some(content.bytes);
+            attempt += 1;
+        };
+
+        if self.options.diagnose {
+            let again = self.read_content_once(offset, metadata, &mut |_, _| {})?;
+
+            content.differences = Some(differences(&content.bytes, &again.bytes));
+        }
+
+        Ok(content)
+    }
+
+    /// Read the file content once, see `read_content_at_with`.
+    fn read_content_once(&mut self, offset: u16, metadata: &FileInfo, on_content: &mut impl FnMut(usize, &[u8])) -> Result<Content> {
+        let trailer_size = if metadata.has_digest() { DIGEST_SIZE } else { 0 };
+        let mut content_buffer = vec![0; metadata.content_size as usize + trailer_size];
+        let content_size = metadata.content_size as usize;
+        let mut digest = CRC.digest();
+        let mut position = 0;
+
+        // The CRC covers the content but not the digest trailer read along with it.
+        self.read_eeprom_chunks(offset, content_buffer.as_mut_slice(), self.options.read_chunk as usize, |chunk| {
+            let size = chunk.len().min(content_size - position);
+
+            digest.update(&chunk[..size]);
+            on_content(position, &chunk[..size]);
+            position += size;
+        })?;
+
+        let mut crc = digest.finalize();
+        let votes = self.options.read_votes;
+
+        if crc != metadata.content_crc && !metadata.has_external_crc() && votes > 1 {
+            let corrected = self.vote_content(offset, content_buffer.as_mut_slice(), votes)?;
+
+            self.corrected_bytes += corrected as u64;
+
+            if corrected > 0 {
+                log::info!("Corrected {corrected} bytes of content by majority vote of {votes} reads.");
+
+                crc = CRC.checksum(&content_buffer[..content_size]);
+                on_content(0, &content_buffer[..content_size]);
+            }
+        }
+
+        let trailer = metadata.has_digest()
+            .then(|| content_buffer.split_off(metadata.content_size as usize).try_into().unwrap());
+
+        Ok(Content { bytes: content_buffer, crc, digest: trailer, differences: None })
+    }
+
+    /// Read `buffer.len()` bytes from EEPROM starting at `offset` `votes - 1` more times, `buffer` holding the first
+    /// read, and replace each byte with the value most reads agree on. Returns the number of bytes changed.
+    ///
+    /// Reads go one chunk at a time, so that the votes only take a few chunks of memory whatever the size of the
+    /// content. The majority is found with the Boyer-Moore vote, exact when more than half of the reads agree, which is
+    /// all that matters as the CRC is checked afterwards.
+    pub fn vote_content(&mut self, offset: u16, buffer: &mut [u8], votes: u32) -> Result<usize> {
+        let read_chunk = (self.options.read_chunk as usize).max(1);
+        let mut reread = vec![0; read_chunk.min(buffer.len())];
+        let mut corrected = 0;
+
+        for (index, chunk) in buffer.chunks_mut(read_chunk).enumerate() {
+            let reread = &mut reread[..chunk.len()];
+            let mut candidates = chunk.to_vec();
+            let mut counts = vec![1u32; chunk.len()];
+
+            for _ in 1..votes {
+                self.read_eeprom(offset + (index * read_chunk) as u16, reread)?;
+
+                for ((candidate, count), &byte) in candidates.iter_mut().zip(counts.iter_mut()).zip(reread.iter()) {
+                    match *count {
+                        0 => (*candidate, *count) = (byte, 1),
+                        _ if *candidate == byte => *count += 1,
+                        _ => *count -= 1,
+                    }
+                }
+            }
+
+            for (byte, candidate) in chunk.iter_mut().zip(candidates) {
+                if *byte != candidate {
+                    *byte = candidate;
+                    corrected += 1;
+                }
+            }
+        }
+
+        Ok(corrected)
+    }
+
+    /// Write `metadata`, replacing the metadata of the same file, then its full CRC if it has one, which covers the
+    /// metadata.
+    pub fn update_metadata(&mut self, metadata: &FileInfo) -> Result<()> {
+        self.write_metadata(metadata)?;
+
+        if !metadata.has_full_crc() {
+            return Ok(());
+        }
+
+        let content = self.read_content_at(self.options.geometry.content_offset, metadata)?;
+        let mut stored = content.bytes;
+
+        stored.extend(content.digest.iter().flatten());
+
+        let crc = full_crc(&metadata.to_bytes_with(self.options.metadata_layout), stored.as_slice());
+
+        self.write_pages(self.options.geometry.content_offset + stored.len() as u16, &crc.to_le_bytes())
+    }
+
+    /// Compute the full CRC of the file described by `metadata` from the metadata block and content in EEPROM, and
+    /// check it against the one stored after them, if any. Returns the CRC computed and whether one was stored.
+    pub fn check_full_crc(&mut self, metadata: &FileInfo) -> Result<(u16, bool)> {
+        let metadata_block = self.read_metadata_buffer()?;
+        let content = self.read_content_at(self.options.geometry.content_offset, metadata)?;
+        let mut stored = content.bytes;
+
+        stored.extend(content.digest.iter().flatten());
+
+        let crc = full_crc(&metadata_block, stored.as_slice());
+
+        if !metadata.has_full_crc() {
+            return Ok((crc, false));
+        }
+
+        let mut crc_buffer = [0; FULL_CRC_SIZE];
+
+        self.read_eeprom(self.options.geometry.content_offset + stored.len() as u16, crc_buffer.as_mut_slice())?;
+
+        let stored_crc = u16::from_le_bytes(crc_buffer);
+
+        if stored_crc != crc {
+            return Err(Error::Corrupted { target: self.target.clone(), reason: format!("full CRC 0x{crc:04x} does not match the one stored (0x{stored_crc:04x}), the metadata or the file was modified") });
+        }
+
+        Ok((crc, true))
+    }
+
+    /// Address in EEPROM right after the last byte used by the metadata and the file(s) it describes, or by the first
+    /// page if the metadata is invalid.
+    pub fn used_end(&mut self) -> Result<usize> {
+        let metadata = self.read_metadata_or_empty()?;
+        let mut end = self.options.geometry.content_offset as usize + metadata.content_size as usize + trailer_size(&metadata);
+
+        if let Ok(Some(table)) = self.read_slot_table(&metadata) {
+            end = end.max(table.end().unwrap_or(0));
+        }
+
+        Ok(end.min(content_end(&self.options.geometry, metadata.flags) as usize))
+    }
+
+    /// Number of bytes left for content in EEPROM after the file(s) described by the metadata: after the used space,
+    /// or in a half after the active file with an A/B layout.
+    pub fn free_size(&mut self) -> Result<usize> {
+        let metadata = self.read_metadata_or_empty()?;
+
+        if metadata.has_ab() {
+            let headers = self.read_ab_halves(&metadata)?.map(|half| half.map(|(header, _)| header));
+            let used = ab::active(&headers).and_then(|index| headers[index]).map_or(0, |header| header.size as usize);
+
+            return Ok((self.ab_halves(metadata.flags)[0].len() - ab::HEADER_SIZE).saturating_sub(used));
+        }
+
+        Ok(content_end(&self.options.geometry, metadata.flags) as usize - self.used_end()?)
+    }
+
+    /// Read the history ring.
+    pub fn read_history(&mut self) -> Result<History> {
+        let mut history_buffer = [0; HISTORY_SIZE];
+
+        self.read_eeprom(history_offset(&self.options.geometry), history_buffer.as_mut_slice())?;
+
+        Ok(History::from_bytes(&history_buffer))
+    }
+
+    /// Record `previous`, the metadata about to be replaced by `metadata`, in the history ring if `metadata` enables it.
+    ///
+    /// The ring is cleared when it gets enabled, as its space may have held content until then.
+    fn record_history(&mut self, previous: &FileInfo, metadata: &FileInfo) -> Result<()> {
+        if !metadata.has_history() {
+            return Ok(());
+        }
+
+        if !previous.has_history() {
+            self.write_pages(history_offset(&self.options.geometry), &[0; HISTORY_SIZE])?;
+        }
+
+        // A write in progress does not describe a file.
+        if previous.content_size == 0 || previous.is_dirty() {
+            return Ok(());
+        }
+
+        let (index, sequence) = self.read_history()?.next();
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let entry = history::Entry {
+            sequence,
+            timestamp,
+            flags: previous.flags,
+            content_crc: previous.content_crc,
+            content_size: previous.content_size,
+            label: previous.payload_version.clone(),
+        };
+
+        self.write_pages(history_offset(&self.options.geometry) + (index * HISTORY_ENTRY_SIZE) as u16, &entry.to_bytes())
+    }
+
+    /// Mark the metadata `previous` as being written, before overwriting any of the content it describes. The mark is
+    /// cleared by `commit_metadata`, so that a write interrupted in between is reported as such when reading.
+    ///
+    /// The mark needs v2 or later metadata, v1 metadata being converted to v3 until the commit. A write stopped by a
+    /// signal before the mark fails here, leaving the EEPROM untouched.
+    pub fn mark_dirty(&mut self, previous: &FileInfo) -> Result<()> {
+        self.check_interrupt(self.options.geometry.metadata_offset)?;
+
+        self.write_metadata(&FileInfo { format: if previous.format.is_v2_or_later() { previous.format } else { Format::V3 }, flags: previous.flags | FLAG_DIRTY, ..previous.clone() })
+    }
+
+    /// Commit `metadata`, replacing `previous`: record `previous` in the history ring if enabled, then write `metadata`.
+    ///
+    /// This must be the last step of a write, once everything `metadata` describes is written. A write interrupted
+    /// before then leaves the previous metadata in place, which fails validation against the partly overwritten content
+    /// instead of new metadata passing off content that was never fully written as valid.
+    fn commit_metadata(&mut self, previous: &FileInfo, metadata: &FileInfo) -> Result<()> {
+        self.record_history(previous, metadata)?;
+        self.write_metadata(metadata)
+    }
+
+    /// Pass on `result`, the outcome of a write that modifies content in place after `mark_dirty`, restoring
+    /// `previous_block`, the raw metadata block from before the write, if it failed. The content the previous metadata
+    /// describes is then valid again as far as the write left it untouched, instead of the EEPROM being left marked as
+    /// being written.
+    fn roll_back_metadata<T>(&mut self, previous_block: &[u8; METADATA_SIZE], result: Result<T>) -> Result<T> {
+        if result.is_err() {
+            match self.write_metadata_block(previous_block) {
+                Ok(()) => log::warn!("Write failed, restored the previous metadata in EEPROM."),
+                Err(error) => log::warn!("Write failed, and restoring the previous metadata in EEPROM failed too, leaving it marked as being written: {error}"),
+            }
+        }
+
+        result
+    }
+
+    /// Read the slot table following the metadata, or `None` if the EEPROM holds a single plain file.
+    pub fn read_slot_table(&mut self, metadata: &FileInfo) -> Result<Option<SlotTable>> {
+        if !metadata.has_slots() {
+            return Ok(None);
+        }
+
+        let mut table_buffer = [0; SLOT_TABLE_SIZE];
+
+        self.read_eeprom(self.options.geometry.metadata_offset + METADATA_SIZE as u16, table_buffer.as_mut_slice())?;
+
+        if metadata.content_size as usize != SLOT_TABLE_SIZE || CRC.checksum(&table_buffer) != metadata.content_crc {
+            return Err(Error::Corrupted { target: self.target.clone(), reason: "the CRC or size of its slot table does not match its metadata".to_string() });
+        }
+
+        Ok(Some(SlotTable::from_bytes(&table_buffer)))
+    }
+
+    /// Find where the file in the given slot is stored, and describe it as if it were a plain file.
+    ///
+    /// An EEPROM holding a single plain file, or an A/B layout, is treated as having only slot 0.
+    pub fn select_slot(&mut self, metadata: FileInfo, slot: Option<u8>) -> Result<(u16, FileInfo)> {
+        let index = slot.unwrap_or(0) as usize;
+
+        if metadata.has_ab() {
+            if index != 0 {
+                return Err(Error::NotSlotted { target: self.target.clone(), layout: "an A/B layout" });
+            }
+
+            return self.select_ab_half(metadata);
+        }
+
+        let Some(table) = self.read_slot_table(&metadata)? else {
+            if index != 0 {
+                return Err(Error::NotSlotted { target: self.target.clone(), layout: "a single plain file" });
+            }
+
+            return Ok((self.options.geometry.content_offset, metadata));
+        };
+
+        let Some(slot) = table.slots[index] else {
+            return Err(Error::SlotEmpty { target: self.target.clone(), index });
+        };
+
+        Ok((slot.offset, FileInfo {
+            flags: 0,
+            content_crc: slot.crc,
+            content_size: slot.size,
+            ..metadata
+        }))
+    }
+
+    /// Fail if a write would replace a valid, non-empty file: the one stored in `slot` if given and the EEPROM holds
+    /// slots, the one described by `metadata` otherwise (which may be the slot table). A slot table that does not match
+    /// its metadata fails too.
+    fn check_not_overwriting(&mut self, metadata: &FileInfo, slot: Option<u8>) -> Result<()> {
+        let (offset, file) = match (metadata.has_slots(), slot) {
+            (true, Some(index)) => {
+                let table = self.read_slot_table(metadata)?;
+
+                match table.and_then(|table| table.slots[index as usize]) {
+                    Some(slot) => (slot.offset, FileInfo { flags: 0, content_crc: slot.crc, content_size: slot.size, ..metadata.clone() }),
+                    None => return Ok(()),
+                }
+            }
+            _ => (self.options.geometry.content_offset, metadata.clone()),
+        };
+
+        if file.content_size == 0 {
+            return Ok(());
+        }
+
+        let content = self.read_content_at(offset, &file)?;
+
+        if validate_content(&file, &content, &self.target).is_ok() {
+            return Err(Error::AlreadyWritten { target: self.target.clone(), what: format!("a valid file ({} bytes)", file.content_size) });
+        }
+
+        Ok(())
+    }
+
+    /// Write `content` into a slot, leaving the content of the other slots untouched, and return the metadata
+    /// describing the updated slot table.
+    ///
+    /// The slot content is written first, then the slot table and finally the metadata describing the table is
+    /// committed, the previous metadata being restored if any of these fails.
+    fn write_slot(&mut self, write: &WriteOptions, content: &[u8]) -> Result<FileInfo> {
+        let index = write.slot.unwrap_or(0) as usize;
+        let table_offset = self.options.geometry.metadata_offset + METADATA_SIZE as u16;
+
+        let metadata = self.read_metadata_or_empty()?;
+
+        let mut table = match self.read_slot_table(&metadata)? {
+            Some(table) => table,
+            None => {
+                // Converting to the slotted layout overwrites the start of a plain file, which is only fine if it is
+                // the file being replaced or if there is no valid file at all.
+                if index != 0 && metadata.content_size != 0 {
+                    let plain_content = self.read_content_at(self.options.geometry.content_offset, &metadata)?;
+
+                    if plain_content.crc == metadata.content_crc || metadata.has_external_crc() {
+                        return Err(Error::HoldsPlainFile { target: self.target.clone() });
+                    }
+                }
+
+                SlotTable::default()
+            }
+        };
+
+        let first_offset = (table_offset as usize + SLOT_TABLE_SIZE).next_multiple_of(32);
+        let offset = match (table.slots[index], write.slot_offset) {
+            (Some(slot), _) => slot.offset as usize,
+            (None, Some(offset)) => offset as usize,
+            (None, None) => table.end().unwrap_or(first_offset).next_multiple_of(32),
+        };
+
+        if offset % 32 != 0 || offset < first_offset {
+            return Err(Error::InvalidRequest { target: self.target.clone(), reason: format!("invalid slot address {offset}, it must be a multiple of 32 and at least {first_offset}") });
+        }
+
+        let end = offset + content.len();
+        let flags = FLAG_SLOTS | module_flags(&metadata, write);
+        check_layout(&self.options.geometry, flags)?;
+        let content_end = content_end(&self.options.geometry, flags);
+
+        if end > content_end as usize {
+            return Err(Error::ContentTooLarge { target: self.target.clone(), file: write.source.clone(), size: content.len(), max: content_end as usize - offset });
+        }
+
+        if let Some(other) = table.overlapping(index, offset, end) {
+            return Err(Error::SlotOverlap { target: self.target.clone(), file: write.source.clone(), index, other });
+        }
+
+        // Content, plus the dirty mark, slot table and metadata.
+        self.print_write_estimate(content.len(), self.page_count(offset as u16, content.len()), self.page_count(table_offset, SLOT_TABLE_SIZE) + 2);
+
+        let previous_block = self.read_metadata_buffer()?;
+
+        self.mark_dirty(&metadata)?;
+
+        let result = self.write_pages(offset as u16, content);
+        self.roll_back_metadata(&previous_block, result)?;
+
+        table.slots[index] = Some(Slot {
+            offset: offset as u16,
+            size: content.len() as u16,
+            crc: CRC.checksum(content),
+            kind: write.slot_type,
+        });
+
+        let table_buffer = table.to_bytes();
+        let previous = metadata.clone();
+        let metadata = FileInfo {
+            format: write.format,
+            flags,
+            content_crc: CRC.checksum(&table_buffer),
+            content_size: SLOT_TABLE_SIZE as u16,
+            ..metadata
+        };
+
+        let result = self.write_pages(table_offset, &table_buffer)
+            .and_then(|()| self.commit_metadata(&previous, &metadata));
+        self.roll_back_metadata(&previous_block, result)?;
+
+        Ok(metadata)
+    }
+
+    /// Read the headers of both halves of the A/B layout described by `metadata`, along with whether the content of
+    /// each matches its header.
+    pub fn read_ab_halves(&mut self, metadata: &FileInfo) -> Result<[Option<(ab::Header, bool)>; 2]> {
+        let mut halves = [None, None];
+
+        for (index, half) in self.ab_halves(metadata.flags).into_iter().enumerate() {
+            let mut header_buffer = [0; ab::HEADER_SIZE];
+
+            self.read_eeprom(half.start, header_buffer.as_mut_slice())?;
+
+            let Some(header) = ab::Header::from_bytes(&header_buffer).filter(|header| header.size as usize <= half.len() - ab::HEADER_SIZE) else {
+                continue;
+            };
+
+            let content = self.read_content_at(half.start + ab::HEADER_SIZE as u16, &FileInfo { content_size: header.size, ..FileInfo::default() })?;
+
+            halves[index] = Some((header, content.crc == header.crc));
+        }
+
+        Ok(halves)
+    }
+
+    /// Find the active half of the A/B layout described by `metadata`, falling back to the other half with a warning
+    /// if the content of the active one is corrupted, and describe its file as if it were a plain file.
+    fn select_ab_half(&mut self, metadata: FileInfo) -> Result<(u16, FileInfo)> {
+        let halves = self.read_ab_halves(&metadata)?;
+
+        let Some(active) = ab::active(&halves.map(|half| half.map(|(header, _)| header))) else {
+            return Err(Error::NoActiveHalf { target: self.target.clone() });
+        };
+
+        let index = match halves[1 - active] {
+            Some((_, true)) if halves[active].is_some_and(|(_, valid)| !valid) => {
+                log::warn!("Active half {} in EEPROM is corrupted, falling back to half {}.", ab::HALF_NAMES[active], ab::HALF_NAMES[1 - active]);
+                1 - active
+            }
+            _ => active,
+        };
+
+        let Some((header, _)) = halves[index] else {
+            unreachable!();
+        };
+
+        Ok((self.ab_halves(metadata.flags)[index].start + ab::HEADER_SIZE as u16, FileInfo {
+            flags: metadata.flags & !FLAG_AB,
+            content_crc: header.crc,
+            content_size: header.size,
+            ..metadata
+        }))
+    }
+
+    /// Write `content` into the inactive half of the A/B layout, converting the EEPROM to that layout if needed, and
+    /// return the metadata describing the file written as if it were a plain file, as `select_slot` does.
+    ///
+    /// The content is written and read back first, then committed by writing the header of its half. Converting to
+    /// the layout overwrites the plain file and rewrites the metadata, which is only committed once both are written,
+    /// like for a plain file.
+    fn write_ab(&mut self, write: &WriteOptions, content: &[u8]) -> Result<FileInfo> {
+        let previous = self.read_metadata_or_empty()?;
+        let flags = FLAG_AB | module_flags(&previous, write);
+        check_layout(&self.options.geometry, flags)?;
+        let halves = self.ab_halves(flags);
+        let max_file_size = halves[0].len() - ab::HEADER_SIZE;
+
+        if content.len() > max_file_size {
+            return Err(Error::ContentTooLarge { target: self.target.clone(), file: write.source.clone(), size: content.len(), max: max_file_size });
+        }
+
+        // The halves move if the history ring gets enabled.
+        let converting = !previous.has_ab() || previous.is_dirty() || content_end(&self.options.geometry, previous.flags) != content_end(&self.options.geometry, flags);
+        let headers = match converting {
+            true => [None, None],
+            false => self.read_ab_halves(&previous)?.map(|half| half.map(|(header, _)| header)),
+        };
+        let active = ab::active(&headers);
+        let index = active.map_or(0, |active| 1 - active);
+        let header = ab::Header {
+            size: content.len() as u16,
+            crc: CRC.checksum(content),
+            sequence: active.and_then(|active| headers[active]).map_or(1, |header| header.sequence.wrapping_add(1)),
+        };
+        let half = &halves[index];
+
+        // Content and header, plus the dirty mark, the header of the other half and the metadata when converting.
+        self.print_write_estimate(content.len(), self.page_count(half.start + ab::HEADER_SIZE as u16, content.len()), if converting { 4 } else { 1 });
+
+        if converting {
+            self.mark_dirty(&previous)?;
+            // The other half may hold bytes of a previous file that happen to form a valid header.
+            self.write_pages(halves[1 - index].start, &[0; ab::HEADER_SIZE])?;
+        }
+
+        self.write_pages(half.start + ab::HEADER_SIZE as u16, content)?;
+
+        let mut readback = vec![0; content.len()];
+
+        self.read_eeprom(half.start + ab::HEADER_SIZE as u16, readback.as_mut_slice())?;
+
+        if readback != content {
+            return Err(Error::HalfNotWritten { target: self.target.clone(), half: ab::HALF_NAMES[index], converting });
+        }
+
+        self.write_pages(half.start, &header.to_bytes())?;
+
+        let metadata = match converting {
+            true => {
+                let metadata = FileInfo {
+                    format: write.format,
+                    flags,
+                    reserved: if previous.format == write.format { previous.reserved.clone() } else { Vec::new() },
+                    serial: previous.serial.clone(),
+                    payload_version: previous.payload_version.clone(),
+                    content_crc: CRC.checksum(&[]),
+                    content_size: 0,
+                };
+
+                self.commit_metadata(&previous, &metadata)?;
+                metadata
+            }
+            false => previous,
+        };
+
+        Ok(FileInfo { flags: metadata.flags & !FLAG_AB, content_crc: header.crc, content_size: header.size, ..metadata })
+    }
+
+    /// Append `content` to the file stored in EEPROM, and return the metadata describing the combined file.
+    ///
+    /// Only the pages from the one holding the end of the current file are written, the part of that page belonging to
+    /// the current file being rewritten unchanged. The metadata is updated last, and restored if the write fails.
+    fn append_file(&mut self, write: &WriteOptions, content: &[u8]) -> Result<FileInfo> {
+        let metadata = self.read_metadata_or_empty()?;
+        let metadata = if metadata.content_size == 0 { FileInfo { format: write.format, ..metadata } } else { metadata };
+
+        if metadata.flags & !(MODULE_FLAGS | CONTENT_TYPE_MASK) != 0 {
+            return Err(Error::NotPlainFile { target: self.target.clone(), flags: metadata.flags, action: "be appended to" });
+        }
+
+        let flags = module_flags(&metadata, write) | metadata.flags & CONTENT_TYPE_MASK;
+        check_layout(&self.options.geometry, flags)?;
+        let free_size = (content_end(&self.options.geometry, flags) - self.options.geometry.content_offset - metadata.content_size) as usize;
+
+        if content.len() > free_size {
+            return Err(Error::ContentTooLarge { target: self.target.clone(), file: write.source.clone(), size: content.len(), max: free_size });
+        }
+
+        let current = self.read_content_at(self.options.geometry.content_offset, &metadata)?;
+
+        if current.crc != metadata.content_crc {
+            return Err(crc_mismatch(&metadata, &current, &self.target));
+        }
+
+        let mut combined = current.bytes;
+
+        combined.extend(content);
+
+        // Writes start with the one holding the end of the current file, or the first byte appended.
+        let page_start = pages::chunks(self.options.geometry.content_offset, combined.len(), self.write_size())
+            .find(|(_, range)| range.end > metadata.content_size as usize)
+            .map_or(combined.len(), |(_, range)| range.start);
+
+        let previous = metadata.clone();
+        let metadata = FileInfo {
+            flags,
+            content_crc: CRC.checksum(combined.as_slice()),
+            content_size: combined.len() as u16,
+            ..metadata
+        };
+
+        // Content, plus the dirty mark and metadata.
+        self.print_write_estimate(content.len(), self.page_count(self.options.geometry.content_offset + page_start as u16, combined.len() - page_start), 2);
+
+        let previous_block = self.read_metadata_buffer()?;
+
+        self.mark_dirty(&previous)?;
+
+        let result = self.write_pages(self.options.geometry.content_offset + page_start as u16, &combined[page_start..])
+            .and_then(|()| self.commit_metadata(&previous, &metadata));
+        self.roll_back_metadata(&previous_block, result)?;
+
+        Ok(metadata)
+    }
+
+    /// Read the file described by `read` out of EEPROM, checked and with its magic stripped as requested.
+    pub fn read_file(&mut self, read: &ReadOptions) -> Result<Vec<u8>> {
+        self.read_file_with(read, |_, _| {})
+    }
+
+    /// Same as `read_file`, passing the content with the magic stripped to `on_content` chunk by chunk as it is read,
+    /// before it is checked, along with the position of the chunk in the content. The position goes back to 0 when
+    /// the read starts over, see `read_consistent`.
+    pub fn read_file_with(&mut self, read: &ReadOptions, mut on_content: impl FnMut(usize, &[u8])) -> Result<Vec<u8>> {
+        self.read_consistent(|eeprom| eeprom.read_file_once(read, &mut on_content))
+    }
+
+    /// Read the file described by `read` once, see `read_file_with`.
+    fn read_file_once(&mut self, read: &ReadOptions, on_content: &mut impl FnMut(usize, &[u8])) -> Result<Vec<u8>> {
+        let metadata = match self.read_metadata() {
+            Err(Error::Blank { .. }) if read.allow_empty && !read.strict_size => FileInfo::default(),
+            result => result?,
+        };
+
+        if read.strict_size && metadata.content_size == 0 && metadata.format != Format::V3 {
+            return Err(Error::Unprovisioned { target: self.target.clone() });
+        }
+        let (offset, metadata) = self.select_slot(metadata, read.slot)?;
+
+        check_payload_version(&metadata, read.require_payload_version.as_deref(), &self.target)?;
+
+        if !read.allow_empty && !read.strict_size && metadata.content_size == 0 {
+            return Err(Error::Empty { target: self.target.clone() });
+        }
+
+        if !read.force_raw {
+            let unknown_flags = metadata::unknown_flags(metadata.flags);
+
+            if unknown_flags != 0 {
+                return Err(Error::Unsupported { target: self.target.clone(), reason: format!("has unknown flags set (0x{unknown_flags:04x}), reading it requires a newer vki2cfile") });
+            }
+
+            if metadata.flags & FLAG_ENCRYPTED != 0 {
+                return Err(Error::Unsupported { target: self.target.clone(), reason: "is encrypted, which this tool cannot decrypt".to_string() });
+            }
+
+            if metadata.flags & FLAG_COMPRESSED != 0 {
+                return Err(Error::Unsupported { target: self.target.clone(), reason: "is gzip-compressed, which this tool cannot decompress".to_string() });
+            }
+        }
+
+        let magic_size = read.expect_magic.len();
+        let content = self.read_content_at_with(offset, &metadata, |position, chunk| {
+            let skipped = magic_size.saturating_sub(position).min(chunk.len());
+
+            on_content((position + skipped).saturating_sub(magic_size), &chunk[skipped..]);
+        })?;
+
+        if !read.ignore_crc {
+            validate_content(&metadata, &content, &self.target)?;
+        }
+
+        let mut content_buffer = content.bytes;
+
+        if !content_buffer.starts_with(&read.expect_magic) {
+            return Err(Error::MagicMismatch { target: self.target.clone(), expected: to_hex(&read.expect_magic) });
+        }
+
+        content_buffer.drain(..magic_size);
+
+        if read.sanity_check {
+            if let Some(value) = stuck_at_value(content_buffer.as_slice()) {
+                let error = Error::StuckContent { target: self.target.clone(), value };
+
+                if read.strict {
+                    return Err(error);
+                }
+
+                log::warn!("{error}");
+            }
+        }
+
+        Ok(content_buffer)
+    }
+
+    /// Read `size` bytes from `offset` in EEPROM as-is, e.g. from the start as written by `write_raw`. Fails with
+    /// `Error::OutOfRange` if they go past the end of the EEPROM, rather than returning fewer bytes.
+    pub fn read_raw(&mut self, offset: u16, size: u16) -> Result<Vec<u8>> {
+        let mut content_buffer = vec![0; size as usize];
+
+        self.read_eeprom(offset, content_buffer.as_mut_slice())?;
+
+        Ok(content_buffer)
+    }
+
+    /// Write `content` as-is from the start of the EEPROM, without any metadata.
+    pub fn write_raw(&mut self, write: &WriteOptions, content: &[u8]) -> Result<()> {
+        let previous = self.read_metadata_or_empty()?;
+
+        check_unlocked(&previous, write.force, &self.target)?;
+
+        if write.safe && !write.force {
+            self.check_not_overwriting(&previous, None)?;
+        }
+
+        let eeprom_size = self.options.geometry.size;
+
+        if content.len() > eeprom_size as usize {
+            return Err(Error::ContentTooLarge { target: self.target.clone(), file: write.source.clone(), size: content.len(), max: eeprom_size as usize });
+        }
+
+        self.print_write_estimate(content.len(), self.page_count(0, content.len()), 0);
+
+        self.write_pages(0, content)
+    }
+
+    /// Read back the file just written, described by `expected`, and check that the metadata in EEPROM (of `slot`, if
+    /// any) describes it and that its content is valid. As the CRC in `expected` was computed from the source, this
+    /// checks that the content matches the source.
+    pub fn verify_written(&mut self, slot: Option<u8>, expected: &FileInfo) -> Result<()> {
+        let metadata = self.read_metadata()?;
+        let (offset, metadata) = self.select_slot(metadata, slot)?;
+
+        if metadata.content_size != expected.content_size || metadata.content_crc != expected.content_crc {
+            return Err(Error::Verification {
+                target: self.target.clone(),
+                reason: format!("metadata describes {} bytes with CRC 0x{:04x} instead of the {} bytes with CRC 0x{:04x} written", metadata.content_size, metadata.content_crc, expected.content_size, expected.content_crc),
+            });
+        }
+
+        let content = self.read_content_at(offset, &metadata)?;
+
+        validate_content(&metadata, &content, &self.target).map_err(|error| Error::Verification { target: self.target.clone(), reason: error.to_string() })
+    }
+
+    /// Read back `content`, just written with `write --raw`, and check that it matches.
+    pub fn verify_raw(&mut self, content: &[u8]) -> Result<()> {
+        let mut readback = vec![0; content.len()];
+
+        self.read_eeprom(0, readback.as_mut_slice())?;
+
+        compare_readback(&self.target, 0, readback.as_slice(), content)
+    }
+
+    /// Quickly check a write, reading back the metadata block (if `metadata` is given) and comparing it with
+    /// `metadata`, and the pages of `content` at `offset` picked by `spot_checked_pages` from `seed`.
+    pub fn quick_verify(&mut self, metadata: Option<&FileInfo>, offset: u16, content: &[u8], seed: u64) -> Result<()> {
+        let chunks: Vec<_> = pages::chunks(offset, content.len(), self.options.geometry.page_size).collect();
+        let checked = spot_checked_pages(chunks.len(), seed);
+
+        if self.options.announce {
+            let pages: Vec<String> = checked.iter().map(usize::to_string).collect();
+            println!("Quick verification of {}pages {} of {} (seed {seed}).", if metadata.is_some() { "the metadata and " } else { "" }, pages.join(", "), chunks.len());
+        }
+
+        if let Some(metadata) = metadata {
+            let readback = self.read_metadata_buffer()?;
+            compare_readback(&self.target, self.options.geometry.metadata_offset, &readback, &metadata.to_bytes_with(self.options.metadata_layout))?;
+        }
+
+        for (address, range) in checked.into_iter().map(|page| chunks[page].clone()) {
+            let mut readback = vec![0; range.len()];
+
+            self.read_eeprom(address, readback.as_mut_slice())?;
+
+            compare_readback(&self.target, address, readback.as_slice(), &content[range])?;
+        }
+
+        if self.options.announce {
+            println!("Quick verification passed.");
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the EEPROM already holds `content`, the bytes of the file described by `write` (starting with its
+    /// magic, if any), stored with the same options, for `write --if-changed`. The size and CRC in the metadata are
+    /// compared, and the content in EEPROM as well with `--deep` or when the CRC stored is not computed over the content.
+    pub fn is_up_to_date(&mut self, write: &WriteOptions, content: &[u8]) -> Result<bool> {
+        let stored = self.read_metadata_or_empty()?;
+        let flags = content_flags(write, after_magic(write, content, &self.target)?);
+        let mut content = content.to_vec();
+
+        if let Some(pad_to) = write.pad_to {
+            content.resize(content.len().max(pad_to as usize), write.pad_byte);
+        }
+
+        let same_options = stored.format == write.format
+            && stored.flags & !MODULE_FLAGS == flags
+            && (!write.history || stored.flags & FLAG_HISTORY != 0)
+            && stored.payload_version == write.payload_version;
+
+        if !same_options || stored.content_size as usize != content.len() || stored.content_crc != write.crc.unwrap_or_else(|| CRC.checksum(content.as_slice())) {
+            return Ok(false);
+        }
+
+        // A CRC given by the user says nothing about the content, so only comparing the bytes tells whether it changed.
+        if !write.deep && write.crc.is_none() {
+            return Ok(true);
+        }
+
+        let stored_content = self.read_content_at(self.options.geometry.content_offset, &stored)?;
+
+        Ok(stored_content.bytes == content && validate_content(&stored, &stored_content, &self.target).is_ok())
+    }
+
+    /// Write `content`, the bytes of the file described by `write` (starting with its magic, if any), into EEPROM and
+    /// return the metadata written.
+    ///
+    /// The content is held in memory, bounded by the space for content: it is only copied to be padded or to have its
+    /// digest or full CRC trailer appended.
+    pub fn write_file(&mut self, content: &[u8], write: &WriteOptions) -> Result<FileInfo> {
+        let mut content = Cow::Borrowed(content);
+        // Keep the fields describing the module rather than the file.
+        let previous = self.read_metadata_or_empty()?;
+
+        check_unlocked(&previous, write.force, &self.target)?;
+
+        if write.safe && !write.force {
+            self.check_not_overwriting(&previous, write.slot)?;
+        }
+
+        let file = after_magic(write, &content, &self.target)?;
+        let flags = content_flags(write, file);
+
+        if (flags != 0 || write.slot.is_some() || !write.payload_version.is_empty() || write.history || write.ab) && write.format == Format::V1 {
+            return Err(Error::InvalidRequest {
+                target: self.target.clone(),
+                reason: "storing a digest, a full CRC, an external CRC, content flags, a content type, slots, a payload version, a history or an A/B layout requires the v2 metadata format".to_string(),
+            });
+        }
+
+        if write.compressed && !file.starts_with(&[0x1f, 0x8b]) {
+            return Err(Error::InvalidRequest { target: self.target.clone(), reason: format!("file {} is not gzip-compressed", write.source) });
+        }
+
+        if let Some(pad_to) = write.pad_to {
+            if content.len() > pad_to as usize {
+                return Err(Error::ContentTooLarge { target: self.target.clone(), file: write.source.clone(), size: content.len(), max: pad_to as usize });
+            }
+
+            content.to_mut().resize(pad_to as usize, write.pad_byte);
+        }
+
+        let file_size = content.len();
+
+        if write.slot.is_some() {
+            return self.write_slot(write, &content);
+        }
+
+        if write.append {
+            return self.append_file(write, &content);
+        }
+
+        if write.ab {
+            return self.write_ab(write, &content);
+        }
+
+        let flags = flags | module_flags(&previous, write);
+        check_layout(&self.options.geometry, flags)?;
+        let max_file_size = (content_end(&self.options.geometry, flags) - self.options.geometry.content_offset) as usize - trailer_size(&FileInfo { flags, ..FileInfo::default() });
+
+        if file_size > max_file_size {
+            return Err(Error::ContentTooLarge { target: self.target.clone(), file: write.source.clone(), size: file_size, max: max_file_size });
+        }
+
+        let metadata = FileInfo {
+            format: write.format,
+            flags,
+            reserved: if previous.format == write.format { previous.reserved.clone() } else { Vec::new() },
+            serial: previous.serial.clone(),
+            payload_version: write.payload_version.clone(),
+            content_crc: write.crc.unwrap_or_else(|| CRC.checksum(&content)),
+            content_size: file_size as u16,
+        };
+
+        // The digest trailer is written right after the content, as if it were part of it.
+        if write.digest {
+            let digest = Sha256::digest(&content);
+            content.to_mut().extend(digest);
+        }
+
+        // So is the full CRC, after the digest trailer.
+        if write.full_crc {
+            let crc = full_crc(&metadata.to_bytes_with(self.options.metadata_layout), &content);
+            content.to_mut().extend(crc.to_le_bytes());
+        }
+
+        let mut written = 0;
+
+        if write.resume {
+            match self.resume_point(&previous, &metadata, &content)? {
+                Some(resumed) => written = resumed,
+                None => return Ok(metadata),
+            }
+        }
+
+        if let Some(start_page) = write.start_page {
+            let pages = self.page_count(self.options.geometry.content_offset, content.len());
+
+            match pages::chunks(self.options.geometry.content_offset, content.len(), self.write_size()).nth(start_page) {
+                Some((_, range)) => written = range.start,
+                None => return Err(Error::InvalidRequest { target: self.target.clone(), reason: format!("start page {start_page} is past the end of the file, which spans {pages} pages") }),
+            }
+        }
+
+        let remaining = self.page_count(self.options.geometry.content_offset + written as u16, content.len() - written);
+
+        // Content, plus the progress updates and the metadata.
+        self.print_write_estimate(content.len() - written, remaining, remaining.div_ceil(PROGRESS_INTERVAL) + 2);
+
+        // The content currently stored, as far as the previous metadata describes it.
+        let stored = match write.diff_write {
+            true => {
+                let mut stored = vec![0; (previous.content_size as usize + trailer_size(&previous)).min(content.len())];
+
+                self.read_eeprom(self.options.geometry.content_offset, stored.as_mut_slice())?;
+
+                Some(stored)
+            }
+            false => None,
+        };
+
+        // A signal received while the content was prepared stops the write before anything is marked as being written.
+        self.check_interrupt(self.options.geometry.content_offset + written as u16)?;
+        self.write_progress(&metadata, &content[..written])?;
+
+        let chunks: Vec<_> = pages::chunks(self.options.geometry.content_offset + written as u16, content.len() - written, self.options.geometry.page_size).collect();
+        let mut pages_written = 0;
+        // A single display for all the groups of pages.
+        let progress = stats::progress(Direction::Write, content.len() - written);
+
+        for group in chunks.chunks(PROGRESS_INTERVAL) {
+            let end = written + group.iter().map(|(_, range)| range.len()).sum::<usize>();
+
+            let result = match &stored {
+                Some(stored) => self.write_changed_pages(self.options.geometry.content_offset + written as u16, stored.get(written..).unwrap_or_default(), &content[written..end]),
+                None => self.write_pages(self.options.geometry.content_offset + written as u16, &content[written..end]).map(|()| group.len()),
+            };
+
+            match result {
+                Ok(pages) => pages_written += pages,
+                Err(error) => {
+                    // Record the pages written before the interruption, leaving the metadata marked as being written.
+                    drop(progress);
+
+                    if let Error::Interrupted { address, .. } = error {
+                        self.write_progress(&metadata, &content[..(address - self.options.geometry.content_offset) as usize])?;
+                        log::warn!("Wrote {} of {} bytes. Resume the write with --resume.", address - self.options.geometry.content_offset, content.len());
+                    }
+
+                    return Err(error);
+                }
+            }
+
+            self.write_progress(&metadata, &content[..end])?;
+            written = end;
+        }
+
+        drop(progress);
+
+        self.commit_metadata(&previous, &metadata)?;
+
+        if write.diff_write && self.options.announce {
+            println!("Wrote {pages_written} pages, skipped {} unchanged.", chunks.len() - pages_written);
+        }
+
+        Ok(metadata)
+    }
+
+    /// Mark the metadata as being written with the file described by `metadata`, recording the size and CRC of
+    /// `written`, the part of its content (including any digest trailer) already written, so that an interrupted write
+    /// can be resumed with `write --resume`.
+    fn write_progress(&mut self, metadata: &FileInfo, written: &[u8]) -> Result<()> {
+        let marker = FileInfo {
+            format: if metadata.format.is_v2_or_later() { metadata.format } else { Format::V3 },
+            flags: metadata.flags | FLAG_DIRTY,
+            content_crc: CRC.checksum(written),
+            content_size: written.len() as u16,
+            ..metadata.clone()
+        };
+
+        self.write_metadata(&marker)
+    }
+
+    /// Find how much of `content` an interrupted write of the file described by `metadata` already wrote, from
+    /// `previous`, the progress it recorded. Returns `None` if that write already completed.
+    fn resume_point(&mut self, previous: &FileInfo, metadata: &FileInfo, content: &[u8]) -> Result<Option<usize>> {
+        if !previous.is_dirty() {
+            if previous.content_size == metadata.content_size && previous.content_crc == metadata.content_crc {
+                let stored = self.read_content_at(self.options.geometry.content_offset, previous)?;
+
+                if stored.bytes == content[..stored.bytes.len()] && validate_content(previous, &stored, &self.target).is_ok() {
+                    return Ok(None);
+                }
+            }
+
+            return Err(Error::CannotResume { target: self.target.clone(), reason: "it does not hold an interrupted write of this file".to_string() });
+        }
+
+        let written = previous.content_size as usize;
+
+        if previous.flags != metadata.flags | FLAG_DIRTY || written > content.len() || CRC.checksum(&content[..written]) != previous.content_crc {
+            return Err(Error::CannotResume { target: self.target.clone(), reason: "the interrupted write in it is not of this file (or was made with other options)".to_string() });
+        }
+
+        Ok(Some(written))
+    }
+}
+
+
+/// Check that `readback`, read from `address` of the EEPROM `target`, matches `expected`, failing with the address of
+/// the first byte that differs.
+pub fn compare_readback(target: &str, address: u16, readback: &[u8], expected: &[u8]) -> Result<()> {
+    match readback.iter().zip(expected).position(|(read, expected)| read != expected) {
+        Some(index) => Err(Error::Verification {
+            target: target.to_string(),
+            reason: format!("byte at address 0x{:04x} is 0x{:02x} instead of 0x{:02x}", address as usize + index, readback[index], expected[index]),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Number of pages checked by `write --quick-verify` besides the first and last pages of content.
+const SPOT_CHECKED_PAGES: usize = 2;
+
+/// Indices of the pages checked by `write --quick-verify` among `page_count` pages, in order: the first and last ones,
+/// and `SPOT_CHECKED_PAGES` picked pseudo-randomly from `seed`.
+fn spot_checked_pages(page_count: usize, seed: u64) -> Vec<usize> {
+    if page_count == 0 {
+        return Vec::new();
+    }
+
+    let mut pages = vec![0, page_count - 1];
+    let mut state = seed.max(1);
+
+    for _ in 0..SPOT_CHECKED_PAGES {
+        state = retry::xorshift64(state);
+        pages.push((state % page_count as u64) as usize);
+    }
+
+    pages.sort_unstable();
+    pages.dedup();
+    pages
+}
+
+
+/// Size of the trailers stored after the content of the file described by `metadata`.
+pub fn trailer_size(metadata: &FileInfo) -> usize {
+    (if metadata.has_digest() { DIGEST_SIZE } else { 0 }) + if metadata.has_full_crc() { FULL_CRC_SIZE } else { 0 }
+}
+
+/// Size of the trailer holding the SHA-256 digest of the content, stored after it if `FLAG_DIGEST` is set.
+pub const DIGEST_SIZE: usize = 32;
+
+/// Size of the trailer holding the full CRC, stored after the content and digest trailer if `FLAG_FULL_CRC` is set.
+const FULL_CRC_SIZE: usize = 2;
+
+/// CRC of the whole used part of the EEPROM: `metadata_block`, followed by `stored`, the content and digest trailer.
+fn full_crc(metadata_block: &[u8], stored: &[u8]) -> u16 {
+    let mut digest = CRC.digest();
+
+    digest.update(metadata_block);
+    digest.update(stored);
+    digest.finalize()
+}
+
+/// File content read from EEPROM by `Eeprom::read_content_at`.
+pub struct Content {
+    pub bytes: Vec<u8>,
+    /// CRC of `bytes`, computed while reading them.
+    pub crc: u16,
+    /// Digest stored in the trailer after the content, if the metadata says one is stored.
+    pub digest: Option<[u8; DIGEST_SIZE]>,
+    /// Offsets of the bytes that differed when the content, not matching its CRC, was read again, if it was.
+    pub differences: Option<Vec<usize>>,
+}
+
+/// Offsets of the bytes differing between `first` and `second`, two reads of the same content.
+pub fn differences(first: &[u8], second: &[u8]) -> Vec<usize> {
+    first.iter().zip(second).enumerate()
+        .filter(|(_, (first, second))| first != second)
+        .map(|(offset, _)| offset)
+        .collect()
+}
+
+/// Largest number of differing offsets listed in the error for a CRC mismatch.
+const MAX_DIFFERENCES_LISTED: usize = 16;
+
+/// Error for `content` not matching the CRC in `metadata`, with what can be told of the cause.
+pub fn crc_mismatch(metadata: &FileInfo, content: &Content, target: &str) -> Error {
+    let mut detail = String::new();
+
+    if let Some(value) = stuck_at_value(&content.bytes) {
+        detail += &format!(" The content is all 0x{value:02X}, as on an erased or never-written part.");
+    }
+
+    match content.differences.as_deref() {
+        Some([]) => detail += " Reading it again gave the same bytes, so the content stored is stale or corrupted rather than read wrong.",
+        Some(differences) => {
+            let listed: Vec<String> = differences.iter().take(MAX_DIFFERENCES_LISTED).map(usize::to_string).collect();
+            let more = match differences.len().saturating_sub(MAX_DIFFERENCES_LISTED) {
+                0 => String::new(),
+                more => format!(" and {more} more"),
+            };
+
+            detail += &format!(" Reading it again gave different bytes at offsets {}{more}, which points to noise on the bus.", listed.join(", "));
+        }
+        None => {}
+    }
+
+    Error::CrcMismatch { target: target.to_string(), stored: metadata.content_crc, computed: content.crc, detail }
+}
+
+/// Validate the content against the CRC (unless external) and, if present, the digest stored in EEPROM.
+pub fn validate_content(metadata: &FileInfo, content: &Content, target: &str) -> Result<()> {
+    if content.crc != metadata.content_crc && !metadata.has_external_crc() {
+        return Err(crc_mismatch(metadata, content, target));
+    }
+
+    if content.digest.is_some_and(|digest| Sha256::digest(content.bytes.as_slice()).as_slice() != digest) {
+        return Err(Error::Corrupted { target: target.to_string(), reason: "SHA-256 of file content does not match the digest stored after it".to_string() });
+    }
+
+    Ok(())
+}
+
+/// Check that the file has the required payload version tag, if any.
+pub fn check_payload_version(metadata: &FileInfo, required: Option<&str>, target: &str) -> Result<()> {
+    match required.filter(|&required| required != metadata.payload_version) {
+        Some(required) => Err(Error::PayloadVersionMismatch {
+            target: target.to_string(),
+            found: metadata.payload_version.clone(),
+            required: required.to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Bytes of `content`, the file written by `write` into the EEPROM `target`, after its magic, or an error if it does not
+/// start with it.
+fn after_magic<'a>(write: &WriteOptions, content: &'a [u8], target: &str) -> Result<&'a [u8]> {
+    content.strip_prefix(write.magic.as_slice()).ok_or_else(|| Error::InvalidRequest {
+        target: target.to_string(),
+        reason: format!("the content to write does not start with its magic {}", to_hex(&write.magic)),
+    })
+}
+
+/// Flags describing `file`, the content of the file written by `write` (after its magic, if any).
+fn content_flags(write: &WriteOptions, file: &[u8]) -> u16 {
+    let mut flags = 0;
+
+    if let Some(content_type) = write.content_type {
+        flags |= content_type.to_flags();
+    } else if write.sniff_content_type {
+        flags |= ContentType::sniff(file).to_flags();
+    }
+
+    if write.digest {
+        flags |= FLAG_DIGEST;
+    }
+
+    if write.compressed {
+        flags |= FLAG_COMPRESSED;
+    }
+
+    if write.encrypted {
+        flags |= FLAG_ENCRYPTED;
+    }
+
+    if write.full_crc {
+        flags |= FLAG_FULL_CRC;
+    }
+
+    if write.crc.is_some() {
+        flags |= FLAG_EXTERNAL_CRC;
+    }
+
+    flags
+}
+
+/// Flags describing the module to keep when replacing the file described by `previous`, plus the ones enabled by
+/// `write`.
+fn module_flags(previous: &FileInfo, write: &WriteOptions) -> u16 {
+    previous.flags & MODULE_FLAGS | if write.history { FLAG_HISTORY } else { 0 }
+}
+
+/// Address of the first byte of the history ring in an EEPROM of `geometry`, when the metadata has `FLAG_HISTORY` set.
+pub fn history_offset(geometry: &Geometry) -> u16 {
+    geometry.size - HISTORY_SIZE as u16
+}
+
+/// Address in EEPROM right after the space available for content, which excludes the reserve, and the history ring
+/// if there is one.
+pub fn content_end(geometry: &Geometry, flags: u16) -> u16 {
+    if flags & FLAG_HISTORY != 0 { history_offset(geometry) - geometry.reserve } else { geometry.content_end() }
+}
+
+/// End of the padding of a write ending at `end`: the end of its 32-byte block, but neither past the end of its page
+/// nor into the reserve, which starts before the history ring if there is one.
+fn padding_end(geometry: &Geometry, end: usize) -> usize {
+    let reserve_starts = [Some(geometry.content_end()), history_offset(geometry).checked_sub(geometry.reserve)];
+    let padded_end = end.next_multiple_of(32).min(end.next_multiple_of(geometry.page_size as usize));
+
+    reserve_starts.into_iter()
+        .flatten()
+        .map(usize::from)
+        .filter(|&start| geometry.reserve != 0 && end <= start)
+        .fold(padded_end, usize::min)
+}
+
+/// Fail if the EEPROM `target` is locked, unless `force` is set.
+pub fn check_unlocked(metadata: &FileInfo, force: bool, target: &str) -> Result<()> {
+    if metadata.is_locked() && !force {
+        return Err(Error::Locked { target: target.to_string() });
+    }
+
+    Ok(())
+}
+
+
+/// If every byte in `content` is 0x00 or every byte is 0xFF, return that value. This is what a misrouted
+/// bus or a blank part typically reads back as.
+pub fn stuck_at_value(content: &[u8]) -> Option<u8> {
+    [0x00, 0xFF].into_iter()
+        .find(|&value| !content.is_empty() && content.iter().all(|&byte| byte == value))
+}
+
+/// Log the bytes of a transfer of at most `MAX_DUMP_SIZE` bytes at `offset` as a hex dump, at the trace level.
+fn dump(direction: Direction, offset: u16, bytes: &[u8]) {
+    if !log::log_enabled!(log::Level::Trace) || bytes.len() > MAX_DUMP_SIZE {
+        return;
+    }
+
+    let direction = match direction {
+        Direction::Read => "read",
+        Direction::Write => "written",
+    };
+
+    log::trace!("{} bytes {direction} at 0x{offset:04x}:{}", bytes.len(), hex_dump(offset, bytes));
+}
+
+/// Lines of 16 bytes in hex, each starting with the address of its first byte.
+fn hex_dump(offset: u16, bytes: &[u8]) -> String {
+    bytes.chunks(16).enumerate()
+        .map(|(index, line)| format!("\n  {:04x}: {}", offset as usize + index * 16, line.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(" ")))
+        .collect()
+}
+
+/// Format bytes as a lowercase hex string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::mock::MockEeprom;
+
+    fn eeprom() -> Eeprom<MockEeprom> {
+        Eeprom::new(MockEeprom::new(EEPROM_SIZE as usize), Options { write_cycle: WriteCycle::Delay(Duration::ZERO), ..Options::default() })
+    }
+
+    #[test]
+    fn interrupted_write_never_reads_back_as_valid() {
+        let old: Vec<u8> = (0..200).map(|index| index as u8).collect();
+        let new: Vec<u8> = (0..300).map(|index| (index * 7 + 3) as u8).collect();
+
+        // Dirty mark, content pages and progress updates, then the metadata.
+        let pages = new.len().div_ceil(32);
+
+        for writes in 0..=pages + pages.div_ceil(PROGRESS_INTERVAL) + 1 {
+            let mut eeprom = eeprom();
+            eeprom.write_file(&old, &WriteOptions::default()).unwrap();
+
+            eeprom.device.writes_left = Some(writes);
+            assert!(eeprom.write_file(&new, &WriteOptions::default()).is_err());
+            eeprom.device.writes_left = None;
+
+            // The metadata still describes the old file or marks the write as in progress, so the new file is never
+            // passed off as valid.
+            let metadata = eeprom.read_metadata_or_empty().unwrap();
+            assert!(metadata.is_dirty() || metadata.content_size as usize == old.len(), "interrupted after {writes} writes");
+
+            match eeprom.read_file(&ReadOptions::default()) {
+                Ok(content) => assert_eq!(content, old, "interrupted after {writes} writes"),
+                Err(error) => assert!(matches!(error, Error::WriteInterrupted { .. }), "interrupted after {writes} writes: {error}"),
+            }
+        }
+    }
+
+    #[test]
+    fn page_failing_to_program_is_written_again() {
+        let mut eeprom = eeprom();
+        let content = vec![0x5A; 100];
+
+        eeprom.options.verify_pages = true;
+
+        // The dirty mark, then the first content page.
+        eeprom.device.dropped_writes = 2;
+        eeprom.write_file(&content, &WriteOptions::default()).unwrap();
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), content);
+
+        // The dirty mark, then every attempt at the first content page.
+        eeprom.device.dropped_writes = 2 + DEFAULT_PAGE_RETRIES as usize;
+        let error = eeprom.write_file(&[0xA5; 100], &WriteOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("address 0x0020"), "{error}");
+    }
+
+    #[test]
+    fn interrupted_write_resumes() {
+        let content: Vec<u8> = (0..1000).map(|index| (index * 13) as u8).collect();
+        let pages = content.len().div_ceil(32);
+
+        for writes in 1..pages + pages.div_ceil(PROGRESS_INTERVAL) + 2 {
+            let mut eeprom = eeprom();
+
+            eeprom.device.writes_left = Some(writes);
+            assert!(eeprom.write_file(&content, &WriteOptions::default()).is_err());
+            eeprom.device.writes_left = None;
+
+            // Another file can only be resumed over if nothing was written yet.
+            if eeprom.read_metadata_or_empty().unwrap().content_size != 0 {
+                let mut other = content.clone();
+                other[0] ^= 0xFF;
+
+                let error = eeprom.write_file(&other, &WriteOptions { resume: true, ..WriteOptions::default() }).unwrap_err();
+                assert!(error.to_string().contains("without --resume"), "interrupted after {writes} writes: {error}");
+            }
+
+            eeprom.write_file(&content, &WriteOptions { resume: true, ..WriteOptions::default() }).unwrap();
+            assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), content);
+
+            // Resuming a completed write does not write anything.
+            eeprom.device.writes_left = Some(0);
+            eeprom.write_file(&content, &WriteOptions { resume: true, ..WriteOptions::default() }).unwrap();
+        }
+    }
+
+    #[test]
+    fn failed_append_restores_previous_metadata() {
+        let old: Vec<u8> = (0..100).map(|index| index as u8).collect();
+        let appended = vec![0xA5; 200];
+
+        // Dirty mark, content pages, then the metadata.
+        for writes in 1..=eeprom().page_count(DEFAULT_CONTENT_OFFSET + 96, 204) + 1 {
+            for failing_writes in [Some(DEFAULT_IO_RETRIES as usize + 1), None] {
+                let mut eeprom = eeprom();
+                eeprom.write_file(&old, &WriteOptions::default()).unwrap();
+
+                eeprom.device.writes_left = Some(writes);
+                eeprom.device.failing_writes = failing_writes;
+                assert!(eeprom.write_file(&appended, &WriteOptions { append: true, ..WriteOptions::default() }).is_err());
+                eeprom.device.writes_left = None;
+
+                match failing_writes {
+                    // The fault is gone by the time the metadata is restored.
+                    Some(_) => assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), old, "failed after {writes} writes"),
+                    None => assert!(matches!(eeprom.read_file(&ReadOptions::default()), Err(Error::WriteInterrupted { .. })), "failed after {writes} writes"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn failed_slot_write_keeps_other_slots_readable() {
+        let mut eeprom = eeprom();
+        eeprom.write_file(&[0x11; 100], &WriteOptions { slot: Some(0), ..WriteOptions::default() }).unwrap();
+        eeprom.write_file(&[0x22; 100], &WriteOptions { slot: Some(1), ..WriteOptions::default() }).unwrap();
+
+        eeprom.device.writes_left = Some(2);
+        eeprom.device.failing_writes = Some(DEFAULT_IO_RETRIES as usize + 1);
+        assert!(eeprom.write_file(&[0x33; 100], &WriteOptions { slot: Some(1), ..WriteOptions::default() }).is_err());
+        eeprom.device.writes_left = None;
+
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), [0x11; 100]);
+    }
+
+    #[test]
+    fn erasing_a_slot_keeps_the_others() {
+        let mut eeprom = eeprom();
+        let slot = |index| WriteOptions { slot: Some(index), ..WriteOptions::default() };
+        eeprom.write_file(&[0x11; 100], &slot(0)).unwrap();
+        let metadata = eeprom.write_file(&[0x22; 100], &slot(1)).unwrap();
+        let offset = eeprom.read_slot_table(&metadata).unwrap().unwrap().slots[1].unwrap().offset as usize;
+
+        eeprom.erase_slot(1, false).unwrap();
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), [0x11; 100]);
+        assert!(matches!(eeprom.read_file(&ReadOptions { slot: Some(1), ..ReadOptions::default() }), Err(Error::SlotEmpty { .. })));
+        assert!(eeprom.device.memory[offset..][..100].iter().all(|&byte| byte == 0xFF));
+        assert!(matches!(eeprom.erase_slot(1, false), Err(Error::SlotEmpty { .. })));
+
+        let metadata = eeprom.read_metadata().unwrap();
+        eeprom.update_metadata(&FileInfo { flags: metadata.flags | metadata::FLAG_LOCKED, ..metadata }).unwrap();
+        assert!(matches!(eeprom.erase_slot(0, false), Err(Error::Locked { .. })));
+        eeprom.erase_slot(0, true).unwrap();
+        assert!(matches!(eeprom.read_file(&ReadOptions::default()), Err(Error::SlotEmpty { .. })));
+    }
+
+    #[test]
+    fn safe_slot_write_refuses_a_corrupt_slot_table() {
+        let mut eeprom = eeprom();
+        let safe = |index| WriteOptions { slot: Some(index), safe: true, ..WriteOptions::default() };
+        eeprom.write_file(&[0x11; 100], &safe(1)).unwrap();
+        assert!(matches!(eeprom.write_file(&[0x22; 100], &safe(1)), Err(Error::AlreadyWritten { .. })));
+
+        eeprom.device.memory[METADATA_OFFSET as usize + METADATA_SIZE] ^= 0xFF;
+        assert!(matches!(eeprom.write_file(&[0x22; 100], &safe(1)), Err(Error::Corrupted { .. })));
+    }
+
+    #[test]
+    fn diff_write_only_writes_changed_pages_and_new_ones() {
+        let old: Vec<u8> = (0..200).map(|index| index as u8).collect();
+        let mut new = old.clone();
+        new[100] ^= 0xFF;
+        new.extend([0xFF; 100]);
+
+        let mut eeprom = eeprom();
+        eeprom.write_file(&old, &WriteOptions::default()).unwrap();
+
+        // The page holding byte 100, the 4 pages reaching past the end of the old file (even though the EEPROM holds
+        // 0xFF there already), 3 progress updates and the metadata.
+        eeprom.device.writes_left = Some(1 + 4 + 3 + 1);
+        eeprom.write_file(&new, &WriteOptions { diff_write: true, ..WriteOptions::default() }).unwrap();
+        eeprom.device.writes_left = None;
+
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), new);
+    }
+
+    #[test]
+    fn unchanged_file_is_up_to_date() {
+        let mut eeprom = eeprom();
+        let content = b"calibration".repeat(10);
+        let if_changed = WriteOptions { deep: true, ..WriteOptions::default() };
+
+        assert!(!eeprom.is_up_to_date(&if_changed, &content).unwrap());
+
+        eeprom.write_file(&content, &WriteOptions::default()).unwrap();
+        assert!(eeprom.is_up_to_date(&if_changed, &content).unwrap());
+        assert!(!eeprom.is_up_to_date(&if_changed, &content[1..]).unwrap());
+        assert!(!eeprom.is_up_to_date(&WriteOptions { payload_version: "2".to_string(), ..WriteOptions::default() }, &content).unwrap());
+
+        // Only a deep comparison notices content that differs but still has the CRC in the metadata.
+        eeprom.device.memory[DEFAULT_CONTENT_OFFSET as usize] ^= 0xFF;
+        assert!(eeprom.is_up_to_date(&WriteOptions::default(), &content).unwrap());
+        assert!(!eeprom.is_up_to_date(&if_changed, &content).unwrap());
+    }
+
+    #[test]
+    fn append_with_single_byte_writes_only_writes_the_appended_bytes() {
+        let mut eeprom = Eeprom::new(MockEeprom::new(EEPROM_SIZE as usize), Options { write_cycle: WriteCycle::Delay(Duration::ZERO), single_byte_writes: true, ..Options::default() });
+        eeprom.write_file(&[0x11; 50], &WriteOptions::default()).unwrap();
+
+        let writes = eeprom.device.writes;
+        eeprom.write_file(&[0x22; 5], &WriteOptions { append: true, ..WriteOptions::default() }).unwrap();
+
+        // The appended bytes, plus the dirty mark and the metadata.
+        assert_eq!(eeprom.device.writes - writes, 5 + 2);
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), [&[0x11; 50][..], &[0x22; 5]].concat());
+    }
+
+    #[test]
+    fn content_must_start_with_its_magic() {
+        let mut eeprom = eeprom();
+        let magic = WriteOptions { magic: b"VKCAL".to_vec(), sniff_content_type: true, ..WriteOptions::default() };
+
+        for content in [&b"VK"[..], b"XXCAL{}"] {
+            assert!(matches!(eeprom.write_file(content, &magic), Err(Error::InvalidRequest { .. })));
+            assert!(matches!(eeprom.is_up_to_date(&magic, content), Err(Error::InvalidRequest { .. })));
+        }
+
+        let metadata = eeprom.write_file(b"VKCAL{}", &magic).unwrap();
+        assert_eq!(ContentType::from_flags(metadata.flags), Some(ContentType::Json));
+    }
+
+    #[test]
+    fn content_type_is_stored_and_kept_by_append() {
+        let mut eeprom = eeprom();
+
+        let metadata = eeprom.write_file(b"{\"gain\": 1.5}", &WriteOptions { sniff_content_type: true, ..WriteOptions::default() }).unwrap();
+        assert_eq!(ContentType::from_flags(metadata.flags), Some(ContentType::Json));
+
+        let metadata = eeprom.write_file(b"\n", &WriteOptions { append: true, ..WriteOptions::default() }).unwrap();
+        assert_eq!(ContentType::from_flags(metadata.flags), Some(ContentType::Json));
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), b"{\"gain\": 1.5}\n");
+
+        let metadata = eeprom.write_file(b"{}", &WriteOptions { content_type: Some(ContentType::Binary), ..WriteOptions::default() }).unwrap();
+        assert_eq!(ContentType::from_flags(metadata.flags), Some(ContentType::Binary));
+
+        assert!(eeprom.write_file(b"{}", &WriteOptions { content_type: Some(ContentType::Json), format: Format::V1, ..WriteOptions::default() }).is_err());
+    }
+
+    #[test]
+    fn write_resumes_from_start_page() {
+        let content: Vec<u8> = (0..300).map(|index| (index * 5) as u8).collect();
+        let mut eeprom = eeprom();
+
+        // The dirty mark, then the first 3 content pages.
+        eeprom.device.writes_left = Some(4);
+        assert!(eeprom.write_file(&content, &WriteOptions::default()).is_err());
+        eeprom.device.writes_left = None;
+
+        // The dirty mark, the remaining 7 content pages, a progress update and the metadata.
+        eeprom.device.writes_left = Some(10);
+        eeprom.write_file(&content, &WriteOptions { start_page: Some(3), ..WriteOptions::default() }).unwrap();
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), content);
+
+        let error = eeprom.write_file(&content, &WriteOptions { start_page: Some(10), ..WriteOptions::default() }).unwrap_err();
+        assert!(error.to_string().contains("spans 10 pages"), "{error}");
+    }
+
+    #[test]
+    fn start_page_counts_single_byte_writes() {
+        let content: Vec<u8> = (0..40).collect();
+        let mut eeprom = Eeprom::new(MockEeprom::new(EEPROM_SIZE as usize), Options { write_cycle: WriteCycle::Delay(Duration::ZERO), single_byte_writes: true, ..Options::default() });
+
+        // The dirty mark, then the first 30 bytes of content.
+        eeprom.device.writes_left = Some(31);
+        assert!(eeprom.write_file(&content, &WriteOptions::default()).is_err());
+        eeprom.device.writes_left = None;
+
+        eeprom.write_file(&content, &WriteOptions { start_page: Some(30), ..WriteOptions::default() }).unwrap();
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), content);
+
+        let error = eeprom.write_file(&content, &WriteOptions { start_page: Some(40), ..WriteOptions::default() }).unwrap_err();
+        assert!(error.to_string().contains("spans 40 pages"), "{error}");
+    }
+
+    #[test]
+    fn verification_after_write_catches_corrupted_content() {
+        let mut eeprom = eeprom();
+        let content = vec![0x3C; 100];
+
+        let metadata = eeprom.write_file(&content, &WriteOptions::default()).unwrap();
+        eeprom.verify_written(None, &metadata).unwrap();
+
+        eeprom.device.memory[DEFAULT_CONTENT_OFFSET as usize + 40] ^= 0xFF;
+        let error = eeprom.verify_written(None, &metadata).unwrap_err();
+        assert!(error.to_string().starts_with("Verification failed"), "{error}");
+    }
+
+    #[test]
+    fn quick_verification_checks_metadata_and_first_and_last_pages() {
+        assert_eq!(spot_checked_pages(0, 7), Vec::<usize>::new());
+        assert_eq!(spot_checked_pages(1, 7), [0]);
+        assert_eq!(spot_checked_pages(20, 7), spot_checked_pages(20, 7));
+        assert!(spot_checked_pages(20, 7).iter().all(|&page| page < 20));
+        assert_eq!(spot_checked_pages(20, 7).first(), Some(&0));
+        assert_eq!(spot_checked_pages(20, 7).last(), Some(&19));
+
+        let mut eeprom = eeprom();
+        let content: Vec<u8> = (0..300).map(|index| index as u8).collect();
+        let metadata = eeprom.write_file(&content, &WriteOptions::default()).unwrap();
+        eeprom.quick_verify(Some(&metadata), DEFAULT_CONTENT_OFFSET, &content, 7).unwrap();
+
+        eeprom.device.memory[DEFAULT_CONTENT_OFFSET as usize + 299] ^= 0xFF;
+        let error = eeprom.quick_verify(Some(&metadata), DEFAULT_CONTENT_OFFSET, &content, 7).unwrap_err();
+        assert!(error.to_string().contains(&format!("0x{:04x}", DEFAULT_CONTENT_OFFSET + 299)), "{error}");
+
+        eeprom.device.memory[DEFAULT_CONTENT_OFFSET as usize + 299] ^= 0xFF;
+        eeprom.device.memory[METADATA_OFFSET as usize + 4] ^= 0xFF;
+        assert!(eeprom.quick_verify(Some(&metadata), DEFAULT_CONTENT_OFFSET, &content, 7).is_err());
+    }
+
+    #[test]
+    fn bytes_free_excludes_content_and_trailers() {
+        let mut eeprom = eeprom();
+
+        eeprom.write_file(&[0x5A; 300], &WriteOptions::default()).unwrap();
+        assert_eq!(eeprom.free_size().unwrap(), eeprom.max_content_size() as usize - 300);
+
+        eeprom.write_file(&[0x5A; 300], &WriteOptions { digest: true, ..WriteOptions::default() }).unwrap();
+        assert_eq!(eeprom.free_size().unwrap(), eeprom.max_content_size() as usize - 300 - DIGEST_SIZE);
+
+        eeprom.write_file(&[0x5A; 300], &WriteOptions { ab: true, ..WriteOptions::default() }).unwrap();
+        assert_eq!(eeprom.free_size().unwrap(), eeprom.ab_halves(FLAG_AB)[0].len() - ab::HEADER_SIZE - 300);
+    }
+
+    #[test]
+    fn interrupted_ab_write_keeps_previous_file() {
+        let old: Vec<u8> = (0..200).map(|index| index as u8).collect();
+        let new: Vec<u8> = (0..300).map(|index| (index * 7 + 3) as u8).collect();
+
+        // Content pages, then the header.
+        for writes in 0..=new.len().div_ceil(32) + 1 {
+            let mut eeprom = eeprom();
+            eeprom.write_file(&[0x11; 50], &WriteOptions { ab: true, ..WriteOptions::default() }).unwrap();
+            eeprom.write_file(&old, &WriteOptions { ab: true, ..WriteOptions::default() }).unwrap();
+
+            eeprom.device.writes_left = Some(writes);
+            let result = eeprom.write_file(&new, &WriteOptions { ab: true, ..WriteOptions::default() });
+            eeprom.device.writes_left = None;
+
+            let content = eeprom.read_file(&ReadOptions::default()).unwrap();
+            assert_eq!(&content, if result.is_ok() { &new } else { &old }, "interrupted after {writes} writes");
+        }
+    }
+
+    #[test]
+    fn failed_conversion_to_ab_asks_for_a_rewrite() {
+        let mut eeprom = eeprom();
+        eeprom.write_file(&[0x11; 50], &WriteOptions::default()).unwrap();
+
+        // Noise on the read back of half A, the first written.
+        let address = eeprom.ab_halves(FLAG_AB)[0].start as usize + ab::HEADER_SIZE;
+        eeprom.device.noisy_reads = (1..=20).map(|read| (eeprom.device.reads + read, address, 0x01)).collect();
+
+        let error = eeprom.write_file(&[0x22; 100], &WriteOptions { ab: true, ..WriteOptions::default() }).unwrap_err();
+        assert!(matches!(error, Error::HalfNotWritten { converting: true, .. }), "{error}");
+        assert!(error.to_string().contains("write the file again"), "{error}");
+
+        eeprom.device.noisy_reads.clear();
+        assert!(matches!(eeprom.read_file(&ReadOptions::default()), Err(Error::WriteInterrupted { .. })));
+    }
+
+    #[test]
+    fn ab_read_falls_back_to_other_half_if_active_is_corrupted() {
+        let mut eeprom = eeprom();
+        let old = vec![0x3C; 100];
+
+        eeprom.write_file(&old, &WriteOptions { ab: true, ..WriteOptions::default() }).unwrap();
+        let metadata = eeprom.write_file(&[0xC3; 100], &WriteOptions { ab: true, ..WriteOptions::default() }).unwrap();
+        eeprom.verify_written(None, &metadata).unwrap();
+
+        // Half B, holding the second file.
+        eeprom.device.memory[ab::half(DEFAULT_CONTENT_OFFSET, EEPROM_SIZE, 1).start as usize + ab::HEADER_SIZE + 10] ^= 0xFF;
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), old);
+
+        // Writing a plain file replaces the A/B layout.
+        eeprom.write_file(&[0x5A; 10], &WriteOptions::default()).unwrap();
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), [0x5A; 10]);
+    }
+
+    #[test]
+    fn read_chunk_is_halved_until_the_adapter_accepts_it() {
+        let mut eeprom = Eeprom::new(MockEeprom::new(EEPROM_SIZE as usize), Options { probe_read_chunk: true, ..Options::default() });
+        let content: Vec<u8> = (0..1000).map(|index| (index * 31 + 7) as u8).collect();
+        eeprom.device.memory[64..1064].copy_from_slice(&content);
+        eeprom.device.max_read_size = Some(300);
+
+        let mut buffer = vec![0; content.len()];
+        let mut chunks: Vec<u8> = Vec::new();
+
+        eeprom.read_eeprom_chunks(64, &mut buffer, 1024, |chunk| chunks.extend(chunk)).unwrap();
+        assert_eq!(eeprom.options.read_chunk, 256);
+        assert_eq!(buffer, content);
+        assert_eq!(chunks, content);
+
+        // Without probing, the rejection is an error.
+        eeprom.options.probe_read_chunk = false;
+        assert!(eeprom.read_eeprom_chunks(64, &mut buffer, 1024, |_| {}).is_err());
+    }
+
+    #[test]
+    fn content_crc_computed_while_reading_matches_checksum() {
+        let mut eeprom = eeprom();
+        let content: Vec<u8> = (0..1000).map(|index| (index * 31 + 7) as u8).collect();
+
+        eeprom.device.memory[64..1064].copy_from_slice(&content);
+
+        for read_chunk in [1, 7, 32, 100, 999, 1000, 4096] {
+            let mut buffer = vec![0; content.len()];
+            let mut digest = CRC.digest();
+
+            eeprom.read_eeprom_chunks(64, &mut buffer, read_chunk, |chunk| digest.update(chunk)).unwrap();
+            assert_eq!(buffer, content);
+            assert_eq!(digest.finalize(), CRC.checksum(&content), "read chunk {read_chunk}");
+        }
+
+        // The CRC does not cover the digest trailer.
+        let metadata = FileInfo { flags: FLAG_DIGEST, content_size: 900, ..FileInfo::default() };
+        let read = eeprom.read_content_at(64, &metadata).unwrap();
+
+        assert_eq!(read.bytes, content[..900]);
+        assert_eq!(read.crc, CRC.checksum(&content[..900]));
+        assert_eq!(read.digest.unwrap(), content[900..932]);
+    }
+
+    #[test]
+    fn full_crc_covers_metadata_and_content() {
+        let mut eeprom = eeprom();
+        let content: Vec<u8> = (0..100).map(|index| index as u8).collect();
+
+        eeprom.write_file(&content, &WriteOptions { full_crc: true, digest: true, ..WriteOptions::default() }).unwrap();
+        let metadata = eeprom.read_metadata().unwrap();
+        assert!(eeprom.check_full_crc(&metadata).unwrap().1);
+
+        // Updating the metadata in place updates the full CRC too.
+        eeprom.update_metadata(&FileInfo { serial: "VK-1".to_string(), ..metadata }).unwrap();
+        let metadata = eeprom.read_metadata().unwrap();
+        assert!(eeprom.check_full_crc(&metadata).unwrap().1);
+
+        // Changing a byte of the metadata, then of the content, is detected.
+        eeprom.device.memory[METADATA_OFFSET as usize + 15] ^= 1;
+        assert!(eeprom.check_full_crc(&metadata).is_err());
+        eeprom.device.memory[METADATA_OFFSET as usize + 15] ^= 1;
+        eeprom.device.memory[DEFAULT_CONTENT_OFFSET as usize + 50] ^= 1;
+        assert!(eeprom.check_full_crc(&metadata).is_err());
+    }
+
+    #[test]
+    fn external_crc_is_stored_and_not_checked() {
+        let mut eeprom = eeprom();
+        let content = [0x5a; 100];
+        let external = |crc| WriteOptions { crc: Some(crc), ..WriteOptions::default() };
+
+        let metadata = eeprom.write_file(&content, &external(0xbeef)).unwrap();
+        assert_eq!(metadata.content_crc, 0xbeef);
+        assert!(metadata.has_external_crc());
+        assert_eq!(eeprom.read_content().unwrap(), content);
+        assert!(eeprom.is_up_to_date(&external(0xbeef), &content).unwrap());
+        assert!(!eeprom.is_up_to_date(&external(0xbeee), &content).unwrap());
+        assert!(!eeprom.is_up_to_date(&external(0xbeef), &[0xa5; 100]).unwrap());
+
+        let metadata = eeprom.write_file(&content, &external(0)).unwrap();
+        assert_eq!(metadata.content_crc, 0);
+        eeprom.device.memory[DEFAULT_CONTENT_OFFSET as usize + 50] ^= 1;
+        assert!(eeprom.read_content().is_ok());
+
+        assert!(eeprom.write_file(&content, &WriteOptions { format: Format::V1, ..external(0) }).is_err());
+    }
+
+    #[test]
+    fn blank_eeprom_reads_as_empty_only_if_allowed() {
+        let mut eeprom = eeprom();
+        let allow_empty = ReadOptions { allow_empty: true, ..ReadOptions::default() };
+
+        assert!(matches!(eeprom.read_file(&ReadOptions::default()), Err(Error::Blank { .. })));
+        assert_eq!(eeprom.read_file(&allow_empty).unwrap(), []);
+    }
+
+    #[test]
+    fn strict_size_only_reads_empty_files_with_metadata_crc() {
+        let mut eeprom = eeprom();
+        let strict_size = |allow_empty| ReadOptions { strict_size: true, allow_empty, ..ReadOptions::default() };
+
+        assert!(matches!(eeprom.read_file(&strict_size(true)), Err(Error::Blank { .. })));
+
+        eeprom.device.memory[METADATA_OFFSET as usize..][..METADATA_SIZE].fill(0);
+        let error = eeprom.read_file(&strict_size(true)).unwrap_err();
+        assert!(error.to_string().contains("unprovisioned"), "{error}");
+
+        eeprom.write_file(&[], &WriteOptions::default()).unwrap();
+        assert_eq!(eeprom.read_file(&strict_size(false)).unwrap(), []);
+    }
+
+    #[test]
+    fn read_starts_over_if_metadata_changes_during_it() {
+        let mut eeprom = eeprom();
+        eeprom.write_file(b"calibration", &WriteOptions { format: Format::V2, ..WriteOptions::default() }).unwrap();
+        let reserved = METADATA_OFFSET as usize + 13;
+
+        // Another writer changes the reserved bytes of the metadata while the content is read, after the metadata was
+        // read twice.
+        eeprom.device.concurrent_writes = vec![(eeprom.device.reads + 3, reserved, 0x42)];
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), b"calibration");
+        assert_eq!(eeprom.device.memory[reserved], 0x42);
+
+        // It keeps changing them.
+        eeprom.device.concurrent_writes = (1..100).map(|read| (eeprom.device.reads + read, reserved, read as u8)).collect();
+        let error = eeprom.read_file(&ReadOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("changed during read"), "{error}");
+    }
+
+    #[test]
+    fn content_is_read_again_unless_the_same_bytes_fail_twice() {
+        let mut eeprom = eeprom();
+        let content: Vec<u8> = (0..200).map(|index| index as u8).collect();
+
+        eeprom.write_file(&content, &WriteOptions::default()).unwrap();
+        let metadata = eeprom.read_metadata().unwrap();
+
+        eeprom.device.noisy_reads.push((eeprom.device.reads + 1, DEFAULT_CONTENT_OFFSET as usize, 0x01));
+        let read = eeprom.read_content_at(DEFAULT_CONTENT_OFFSET, &metadata).unwrap();
+        validate_content(&metadata, &read, UNNAMED_TARGET).unwrap();
+        assert_eq!(read.bytes, content);
+
+        eeprom.device.memory[DEFAULT_CONTENT_OFFSET as usize + 5] ^= 0xFF;
+        let read = eeprom.read_content_at(DEFAULT_CONTENT_OFFSET, &metadata).unwrap();
+        assert_eq!(read.differences, Some(vec![]));
+        assert!(validate_content(&metadata, &read, UNNAMED_TARGET).unwrap_err().to_string().contains("gave the same bytes"));
+    }
+
+    #[test]
+    fn crc_mismatch_tells_noise_from_corruption() {
+        let metadata = FileInfo { content_size: 40, content_crc: 0x1234, ..FileInfo::default() };
+        let mut content = Content { bytes: vec![0xFF; 40], crc: 0x4321, digest: None, differences: None };
+
+        let error = crc_mismatch(&metadata, &content, UNNAMED_TARGET).to_string();
+        assert!(error.contains("0x4321 does not match CRC 0x1234"), "{error}");
+        assert!(error.contains("all 0xFF"), "{error}");
+
+        content.bytes[0] = 0;
+        content.differences = Some((0..20).collect());
+        let error = crc_mismatch(&metadata, &content, UNNAMED_TARGET).to_string();
+        assert!(!error.contains("all 0x"), "{error}");
+        assert!(error.contains("offsets 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15 and 4 more"), "{error}");
+
+        content.differences = Some(vec![]);
+        assert!(crc_mismatch(&metadata, &content, UNNAMED_TARGET).to_string().contains("gave the same bytes"));
+        assert_eq!(differences(&[1, 2, 3], &[1, 0, 3]), [1]);
+    }
+
+    #[test]
+    fn reads_never_roll_over_past_the_end() {
+        let mut eeprom = eeprom();
+        let mut buffer = [0; 20];
+
+        eeprom.device.memory[..10].fill(0x42);
+        eeprom.read_eeprom(EEPROM_SIZE - 20, &mut buffer).unwrap();
+        assert_eq!(buffer, [0xFF; 20]);
+
+        // The mock rolls over to address 0 like the device, so this would read 0x42 bytes.
+        let error = eeprom.read_eeprom(EEPROM_SIZE - 10, &mut buffer).unwrap_err();
+        assert!(matches!(error, Error::OutOfRange { size: 20, end, .. } if end == EEPROM_SIZE as usize), "{error}");
+    }
+
+    #[test]
+    fn votes_correct_noisy_bytes() {
+        let mut eeprom = eeprom();
+        let content: Vec<u8> = (0..100).collect();
+
+        eeprom.device.memory[100..200].copy_from_slice(&content);
+
+        // The first read, passed in, and the second of the three reads each have a different byte flipped.
+        let mut buffer = content.clone();
+        buffer[10] ^= 0x04;
+        eeprom.device.noisy_reads.push((2, 150, 0x80));
+
+        assert_eq!(eeprom.vote_content(100, &mut buffer, 3).unwrap(), 1);
+        assert_eq!(buffer, content);
+        assert_eq!(eeprom.vote_content(100, &mut buffer, 1).unwrap(), 0);
+    }
+
+    #[test]
+    fn oversized_file_is_rejected_before_any_write() {
+        let mut eeprom = eeprom();
+        let max_file_size = eeprom.max_content_size() as usize;
+
+        let error = eeprom.write_file(&vec![0x42; max_file_size + 1], &WriteOptions::default()).unwrap_err();
+        assert!(matches!(error, Error::ContentTooLarge { .. }), "{error}");
+        assert_eq!(eeprom.device.writes, 0);
+        assert!(eeprom.device.memory.iter().all(|&byte| byte == 0xFF));
+
+        // A file that just fits is written.
+        eeprom.write_file(&vec![0x42; max_file_size], &WriteOptions::default()).unwrap();
+        assert!(eeprom.device.writes > 0);
+    }
+
+    #[test]
+    fn dumps_lines_of_16_bytes() {
+        let bytes: Vec<u8> = (0..20).collect();
+
+        assert_eq!(hex_dump(0x40, &bytes), "\n  0040: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n  0050: 10 11 12 13");
+        assert_eq!(hex_dump(0, &[]), "");
+    }
+
+    #[test]
+    fn padding_stays_out_of_the_reserve() {
+        let geometry = Geometry { reserve: 1000, ..Geometry::MK24C64 };
+        let history_offset = history_offset(&geometry) as usize;
+
+        assert_eq!(padding_end(&Geometry::MK24C64, 7192), 7200);
+        assert_eq!(padding_end(&Geometry { page_size: 16, ..Geometry::MK24C64 }, 7180), 7184);
+        assert_eq!(padding_end(&geometry, 7192), 7192);
+        assert_eq!(padding_end(&geometry, 7190), 7192);
+        assert_eq!(padding_end(&geometry, 7210), 7232);
+
+        // With a history ring, the reserve is right before it.
+        assert_eq!(padding_end(&geometry, history_offset - 1000 - 4), history_offset - 1000);
+    }
+
+    #[test]
+    fn layout_must_leave_room_for_metadata_and_align_content() {
+        check_layout(&Geometry::MK24C64, FLAG_HISTORY).unwrap();
+        check_layout(&Geometry { content_offset: 256, page_size: 64, ..Geometry::MK24C64 }, FLAG_HISTORY).unwrap();
+        check_layout(&Geometry { metadata_offset: 64, content_offset: 128, ..Geometry::MK24C64 }, FLAG_HISTORY).unwrap();
+
+        assert!(check_layout(&Geometry { metadata_offset: 16, ..Geometry::MK24C64 }, 0).is_err());
+        assert!(check_layout(&Geometry { content_offset: 48, ..Geometry::MK24C64 }, 0).is_err());
+        assert!(check_layout(&Geometry { content_offset: 0, ..Geometry::MK24C64 }, 0).is_err());
+        assert!(check_layout(&Geometry { content_offset: history_offset(&Geometry::MK24C64), ..Geometry::MK24C64 }, FLAG_HISTORY).is_err());
+    }
+
+    #[test]
+    fn reserve_only_needs_room_before_the_history_ring_with_history() {
+        let geometry = Geometry { reserve: history_offset(&Geometry::MK24C64), ..Geometry::MK24C64 };
+        check_layout(&geometry, 0).unwrap();
+        assert!(matches!(check_layout(&geometry, FLAG_HISTORY), Err(Error::InvalidLayout { .. })));
+
+        let mut eeprom = Eeprom::new(MockEeprom::new(EEPROM_SIZE as usize), Options { geometry, write_cycle: WriteCycle::Delay(Duration::ZERO), ..Options::default() });
+        eeprom.write_file(&[0x42; 64], &WriteOptions::default()).unwrap();
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), [0x42; 64]);
+
+        let history = WriteOptions { history: true, ..WriteOptions::default() };
+        assert!(matches!(eeprom.write_file(&[0x43; 64], &history), Err(Error::InvalidLayout { .. })));
+    }
+
+    #[test]
+    fn writes_wait_for_write_cycles() {
+        let mut device = MockEeprom::new(EEPROM_SIZE as usize);
+
+        device.write_cycle_nacks = 3;
+
+        let mut eeprom = Eeprom::new(device, Options { write_cycle: WriteCycle::Poll(polling::POLL_TIMEOUT), ..Options::default() });
+        eeprom.write_file(&[0x5A; 100], &WriteOptions::default()).unwrap();
+        assert_eq!(eeprom.read_content().unwrap(), [0x5A; 100]);
+
+        // Without polling, the next write comes while the device is still busy.
+        let mut eeprom = Eeprom::new(eeprom.into_device(), Options { write_cycle: WriteCycle::Delay(Duration::ZERO), io_retries: 0, ..Options::default() });
+        assert!(matches!(eeprom.write_file(&[0xA5; 100], &WriteOptions::default()), Err(Error::Transfer { op: Operation::Write, .. })));
+    }
+
+    #[test]
+    fn completed_write_reads_back() {
+        let mut eeprom = eeprom();
+        let content = b"calibration".repeat(10);
+
+        eeprom.write_file(&content, &WriteOptions { digest: true, ..WriteOptions::default() }).unwrap();
+
+        assert_eq!(eeprom.read_file(&ReadOptions::default()).unwrap(), content);
+    }
+
+    #[test]
+    fn digest_trailer_is_the_sha256_of_the_content() {
+        let vectors: [(&[u8], &str); 2] = [
+            (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq", "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+        ];
+
+        for (content, digest) in vectors {
+            let mut eeprom = eeprom();
+            eeprom.write_file(content, &WriteOptions { digest: true, ..WriteOptions::default() }).unwrap();
+
+            let start = DEFAULT_CONTENT_OFFSET as usize + content.len();
+            assert_eq!(to_hex(&eeprom.device.memory[start..start + DIGEST_SIZE]), digest);
+        }
+    }
+
+    #[test]
+    fn errors_name_the_eeprom() {
+        let target = || "address 0x50 on /dev/i2c-3".to_string();
+        let source = || Box::new(std::io::Error::from_raw_os_error(libc::ENXIO));
+        let errors = [
+            (Error::DeviceNotFound { path: "/dev/i2c-3".to_string(), address: 0x50, source: source() }, "Failed to open EEPROM at address 0x50 on /dev/i2c-3: the I2C bus device does not exist."),
+            (Error::DeviceOpen { path: "/dev/i2c-3".to_string(), address: 0x50, source: source() }, "Failed to open EEPROM at address 0x50 on /dev/i2c-3."),
+            (Error::Transfer { target: target(), op: Operation::Read, offset: 0x20, source: source() }, "Failed to read EEPROM (address 0x50 on /dev/i2c-3) at 0x0020."),
+            (Error::Transfer { target: target(), op: Operation::Write, offset: 0x20, source: source() }, "Failed to write EEPROM (address 0x50 on /dev/i2c-3) at 0x0020."),
+            (Error::MetadataInvalid { target: target(), reason: "unknown format".to_string() }, "Invalid file metadata in EEPROM (address 0x50 on /dev/i2c-3): unknown format."),
+            (Error::CrcMismatch { target: target(), stored: 0x1234, computed: 0x4321, detail: String::new() }, "File in EEPROM (address 0x50 on /dev/i2c-3) does not exist or is corrupted: CRC of file content 0x4321 does not match CRC 0x1234 in its metadata."),
+            (Error::ContentTooLarge { target: target(), file: "'cal.json'".to_string(), size: 9000, max: 8160 }, "File 'cal.json' (9000 bytes) is too large for EEPROM (address 0x50 on /dev/i2c-3). Max allowable size is 8160 bytes."),
+            (Error::PayloadVersionMismatch { target: target(), found: "cal-1".to_string(), required: "cal-2".to_string() }, "File in EEPROM (address 0x50 on /dev/i2c-3) has payload version 'cal-1', but 'cal-2' is required."),
+            (Error::WriteInterrupted { target: target() }, "Previous write into EEPROM (address 0x50 on /dev/i2c-3) was interrupted, the file in it is incomplete. Write it again."),
+            (Error::Blank { target: target() }, "EEPROM (address 0x50 on /dev/i2c-3) is blank (factory default)."),
+            (Error::Interrupted { target: target(), address: 0x60 }, "Write into EEPROM (address 0x50 on /dev/i2c-3) was interrupted before address 0x0060."),
+            (Error::Empty { target: target() }, "File in EEPROM (address 0x50 on /dev/i2c-3) is empty or does not exists."),
+            (Error::Corrupted { target: target(), reason: "SHA-256 of file content does not match".to_string() }, "File in EEPROM (address 0x50 on /dev/i2c-3) is corrupted: SHA-256 of file content does not match."),
+            (Error::DataTooLarge { target: target(), what: "user data".to_string(), size: 9, max: 8 }, "Size of user data (9 bytes) is too large for EEPROM (address 0x50 on /dev/i2c-3). Max allowable size is 8 bytes."),
+            (Error::SlotOverlap { target: target(), file: "'cal.json'".to_string(), index: 1, other: 2 }, "File 'cal.json' does not fit into slot 1 of EEPROM (address 0x50 on /dev/i2c-3): it would overlap slot 2."),
+            (Error::Locked { target: target() }, "EEPROM (address 0x50 on /dev/i2c-3) is locked against writes. Run `vki2cfile unlock` first, or pass --force to write anyway."),
+            (Error::OutOfRange { target: target(), offset: 0x1FF0, size: 32, end: 0x2000 }, "Cannot access 32 bytes at 0x1ff0 of EEPROM (address 0x50 on /dev/i2c-3): only 16 bytes are available before its end at 0x2000."),
+            (Error::NotSlotted { target: target(), layout: "an A/B layout" }, "EEPROM (address 0x50 on /dev/i2c-3) holds an A/B layout, which can only be accessed as slot 0."),
+            (Error::SlotEmpty { target: target(), index: 2 }, "Slot 2 in EEPROM (address 0x50 on /dev/i2c-3) is empty."),
+            (Error::HoldsPlainFile { target: target() }, "EEPROM (address 0x50 on /dev/i2c-3) holds a plain file, which would be overwritten by the slot table. Read it out and write it back with --slot 0 first."),
+            (Error::NoActiveHalf { target: target() }, "Neither half of the A/B layout in EEPROM (address 0x50 on /dev/i2c-3) holds a file."),
+            (Error::HalfNotWritten { target: target(), half: "B", converting: false }, "Half B of EEPROM (address 0x50 on /dev/i2c-3) does not read back as written, the previous file stays active."),
+            (Error::HalfNotWritten { target: target(), half: "A", converting: true }, "Half A of EEPROM (address 0x50 on /dev/i2c-3) does not read back as written, and the EEPROM was being converted to the A/B layout: write the file again."),
+            (Error::NotPlainFile { target: target(), flags: 0x0004, action: "be appended to" }, "File in EEPROM (address 0x50 on /dev/i2c-3) is not a plain file (flags 0x0004), it cannot be appended to."),
+            (Error::AlreadyWritten { target: target(), what: "a serial number".to_string() }, "EEPROM (address 0x50 on /dev/i2c-3) already holds a serial number. Pass --force to overwrite it."),
+            (Error::NotFound { target: target(), what: "serial number".to_string() }, "No serial number in EEPROM (address 0x50 on /dev/i2c-3)."),
+            (Error::Unprovisioned { target: target() }, "File in EEPROM (address 0x50 on /dev/i2c-3) is empty but its metadata has no CRC proving it was written (e.g. it is all zero), so with --strict-size the EEPROM is taken to be unprovisioned."),
+            (Error::Unsupported { target: target(), reason: "is encrypted".to_string() }, "File in EEPROM (address 0x50 on /dev/i2c-3) is encrypted. Pass --force-raw to read the stored bytes as-is."),
+            (Error::MagicMismatch { target: target(), expected: "cafe".to_string() }, "File in EEPROM (address 0x50 on /dev/i2c-3) does not start with the expected magic cafe."),
+            (Error::StuckContent { target: target(), value: 0xFF }, "File content in EEPROM (address 0x50 on /dev/i2c-3) is suspicious: all bytes are 0xFF, check that the right device is being read."),
+            (Error::Verification { target: target(), reason: "page at 0x0040 differs".to_string() }, "Verification failed on EEPROM (address 0x50 on /dev/i2c-3): page at 0x0040 differs."),
+            (Error::CannotResume { target: target(), reason: "the file differs".to_string() }, "Cannot resume the write into EEPROM (address 0x50 on /dev/i2c-3): the file differs. Write it again without --resume."),
+            (Error::Unstable { target: target(), attempts: 3 }, "EEPROM (address 0x50 on /dev/i2c-3) contents changed during read, 3 times in a row: another writer may be using it."),
+            (Error::AckTimeout { target: target(), timeout: Duration::from_millis(20), source: source() }, "EEPROM (address 0x50 on /dev/i2c-3) did not acknowledge within 20ms after a write."),
+            (Error::Poll { target: target(), source: source() }, "Failed to poll EEPROM (address 0x50 on /dev/i2c-3) for the end of its write cycle."),
+            (Error::NoEui { target: target(), offset: 0xFA, value: 0xFF }, "No EUI at 0x00fa of EEPROM (address 0x50 on /dev/i2c-3): all its bytes are 0xFF. Check the offset and that the part has a factory-programmed EUI."),
+            (Error::InvalidRequest { target: target(), reason: "start page 9 is past the end of the file".to_string() }, "Invalid request for EEPROM (address 0x50 on /dev/i2c-3): start page 9 is past the end of the file."),
+            (Error::Busy { target: target(), lock_path: "/run/lock/vki2cfile-i2c-3.lock".to_string() }, "Device of EEPROM (address 0x50 on /dev/i2c-3) is in use by another instance of this tool (lock file '/run/lock/vki2cfile-i2c-3.lock')."),
+            (Error::Lock { target: target(), lock_path: "/run/lock/vki2cfile-i2c-3.lock".to_string(), source: std::io::Error::from_raw_os_error(libc::EACCES) }, "Failed to lock the device of EEPROM (address 0x50 on /dev/i2c-3) with lock file '/run/lock/vki2cfile-i2c-3.lock'."),
+            (Error::Mux { target: target(), mux: "address 0x70 on /dev/i2c-3".to_string(), channel: None, source: source() }, "Failed to open the mux at address 0x70 on /dev/i2c-3 in front of EEPROM (address 0x50 on /dev/i2c-3)."),
+            (Error::Mux { target: target(), mux: "address 0x70 on /dev/i2c-3".to_string(), channel: Some(2), source: source() }, "Failed to select channel 2 of the mux at address 0x70 on /dev/i2c-3 in front of EEPROM (address 0x50 on /dev/i2c-3)."),
+            (Error::TransactionLog { target: target(), path: "log.csv".to_string(), source: std::io::Error::from_raw_os_error(libc::EACCES) }, "Failed to open transaction log 'log.csv' of EEPROM (address 0x50 on /dev/i2c-3)."),
+            (Error::SourceFile { target: target(), path: "cal.json".to_string(), source: std::io::Error::from_raw_os_error(libc::ENOENT) }, "Failed to read from file 'cal.json' to write into EEPROM (address 0x50 on /dev/i2c-3)."),
+            (Error::SourceChanged { target: target(), path: "cal.json".to_string(), size: 100, read: 90 }, "File 'cal.json' to write into EEPROM (address 0x50 on /dev/i2c-3) changed size while being read: 90 bytes were read instead of 100."),
+            (Error::DestinationExists { target: target(), path: "cal.json".to_string() }, "Destination file 'cal.json' of the read of EEPROM (address 0x50 on /dev/i2c-3) exists, pass --force to overwrite it (or --backup to keep a copy)."),
+            (Error::Destination { target: target(), action: "write to stdout".to_string(), recovery: None, source: std::io::Error::from_raw_os_error(libc::EPIPE) }, "Failed to save the content read from EEPROM (address 0x50 on /dev/i2c-3): could not write to stdout."),
+            (
+                Error::Destination { target: target(), action: "write to file 'cal.json'".to_string(), recovery: Some("The content read was written to stdout instead.".to_string()), source: std::io::Error::from_raw_os_error(libc::ENOSPC) },
+                "Failed to save the content read from EEPROM (address 0x50 on /dev/i2c-3): could not write to file 'cal.json'. The content read was written to stdout instead.",
+            ),
+            (Error::SelfTest { target: target(), outcome: "the original data was written back".to_string(), source: Box::new(Error::Blank { target: target() }) }, "Self-test of EEPROM (address 0x50 on /dev/i2c-3) failed, the original data was written back."),
+            (Error::Benchmark { target: target(), outcome: "the original data was written back".to_string(), source: Box::new(Error::Blank { target: target() }) }, "Benchmark of EEPROM (address 0x50 on /dev/i2c-3) failed, the original data was written back."),
+            (Error::Restore { target: target(), test: "self-test", source: Box::new(Error::Blank { target: target() }) }, "Failed to write the original data back into EEPROM (address 0x50 on /dev/i2c-3) after the self-test, it may be corrupted."),
+            (Error::Usage { target: target(), reason: "fast mode requires ACK polling".to_string() }, "Invalid command line for EEPROM (address 0x50 on /dev/i2c-3): fast mode requires ACK polling."),
+            // The only error about no EEPROM in particular.
+            (Error::InvalidLayout { reason: "Metadata overlaps content.".to_string() }, "Metadata overlaps content."),
+        ];
+
+        for (error, rendering) in errors {
+            assert_eq!(error.to_string(), rendering);
+        }
+    }
+
+    #[test]
+    fn reports_the_chain_of_causes() {
+        let mut device = MockEeprom::new(EEPROM_SIZE as usize);
+
+        device.write_cycle_nacks = 1;
+
+        let mut eeprom = Eeprom::new(device, Options { write_cycle: WriteCycle::Delay(Duration::ZERO), io_retries: 0, ..Options::default() })
+            .with_target("address 0x50 on /dev/i2c-3");
+        eeprom.write_pages(0x40, &[0x42; 8]).unwrap();
+
+        let error = eeprom.read_eeprom(0x40, &mut [0; 8]).unwrap_err();
+        let cause = std::io::Error::from_raw_os_error(libc::ENXIO);
+
+        assert_eq!(report(&error), format!(
+            "Failed to read EEPROM (address 0x50 on /dev/i2c-3) at 0x0040.\ncaused by: {}\ncaused by: {cause}",
+            i2c_error::explain(libc::ENXIO, "address 0x50 on /dev/i2c-3").unwrap(),
+        ));
+
+        let mut eeprom = Eeprom::new(eeprom.into_device(), Options::default());
+        assert_eq!(report(&eeprom.read_metadata().unwrap_err()), "EEPROM (unnamed device) is blank (factory default).");
+    }
+}
@@ -0,0 +1,121 @@
+//! Geometry of the EEPROM: its size, where the metadata and the content start, its pages and how its memory is
+//! addressed. Defaults to the MK24C64, and is otherwise built from the command line or by users of the library.
+
+use crate::eeprom::{DEFAULT_CONTENT_OFFSET, EEPROM_SIZE, METADATA_OFFSET};
+use crate::metadata::METADATA_SIZE;
+use crate::pages;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    /// Total size of the EEPROM in bytes.
+    pub size: u16,
+    /// Address of the first byte of the metadata.
+    pub metadata_offset: u16,
+    /// Address of the first byte of the content, a multiple of `page_size`.
+    pub content_offset: u16,
+    /// Size of the physical pages of the EEPROM, which a single write cannot cross.
+    pub page_size: u16,
+    /// Number of bytes the address of a byte in memory is sent as, most significant first: 2 on parts from 4 KiB up,
+    /// 1 on the smallest parts.
+    pub address_bytes: u8,
+    /// Bytes at the end of the EEPROM left out of the space for content.
+    pub reserve: u16,
+}
+
+impl Geometry {
+    /// Geometry of the MK24C64.
+    pub const MK24C64: Geometry = Geometry {
+        size: EEPROM_SIZE,
+        metadata_offset: METADATA_OFFSET,
+        content_offset: DEFAULT_CONTENT_OFFSET,
+        page_size: pages::DEFAULT_PAGE_SIZE,
+        address_bytes: 2,
+        reserve: 0,
+    };
+
+    /// Address right after the space for content, where the reserve starts.
+    pub fn content_end(&self) -> u16 {
+        self.size.saturating_sub(self.reserve)
+    }
+
+    /// Largest content that fits between the metadata and the reserve.
+    pub fn max_content_size(&self) -> u16 {
+        self.content_end().saturating_sub(self.content_offset)
+    }
+
+    /// Bytes to send to the EEPROM to address the byte at `offset`.
+    pub fn address(&self, offset: u16) -> Vec<u8> {
+        offset.to_be_bytes()[2 - self.address_bytes as usize..].to_vec()
+    }
+
+    /// Check that the metadata and the content fit in the EEPROM without overlapping, that the content starts on a
+    /// page boundary and ends before the reserve, and that every byte can be addressed.
+    pub fn check(&self) -> Result<(), String> {
+        let Geometry { size, metadata_offset, content_offset, page_size, address_bytes, reserve } = *self;
+
+        if !(1..=2).contains(&address_bytes) {
+            return Err(format!("Invalid address size: {address_bytes} bytes, only 1 or 2 are supported."));
+        }
+
+        if size as usize > 1 << (8 * address_bytes as usize) {
+            return Err(format!("Invalid EEPROM size: {size} bytes cannot be addressed with {address_bytes} address byte(s)."));
+        }
+
+        if metadata_offset as usize + METADATA_SIZE > content_offset as usize {
+            return Err(format!("Invalid metadata offset: metadata would overlap the content ({metadata_offset} + {METADATA_SIZE} > {content_offset})."));
+        }
+
+        if page_size == 0 || !content_offset.is_multiple_of(page_size) {
+            return Err(format!("Invalid content offset: {content_offset} is not a multiple of the page size ({page_size})."));
+        }
+
+        if content_offset >= size {
+            return Err(format!("Invalid content offset: {content_offset} leaves no room for content in the {size} bytes of the EEPROM."));
+        }
+
+        if content_offset as usize + reserve as usize >= size as usize {
+            return Err(format!("Invalid reserve: {reserve} bytes leave no room for content between {content_offset} and the end of the EEPROM at {size}."));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Geometry::MK24C64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_layouts() {
+        Geometry::MK24C64.check().unwrap();
+        Geometry { content_offset: 256, page_size: 64, ..Geometry::MK24C64 }.check().unwrap();
+        Geometry { metadata_offset: 64, content_offset: 128, ..Geometry::MK24C64 }.check().unwrap();
+        Geometry { size: 256, page_size: 8, address_bytes: 1, ..Geometry::MK24C64 }.check().unwrap();
+
+        assert!(Geometry { metadata_offset: 16, ..Geometry::MK24C64 }.check().is_err());
+        assert!(Geometry { content_offset: 48, ..Geometry::MK24C64 }.check().is_err());
+        assert!(Geometry { content_offset: 0, ..Geometry::MK24C64 }.check().is_err());
+        assert!(Geometry { content_offset: EEPROM_SIZE, ..Geometry::MK24C64 }.check().is_err());
+        assert!(Geometry { address_bytes: 1, ..Geometry::MK24C64 }.check().is_err());
+        assert!(Geometry { address_bytes: 3, ..Geometry::MK24C64 }.check().is_err());
+
+        // The reserve can shrink the content down to a byte, but never into the metadata.
+        Geometry { reserve: EEPROM_SIZE - DEFAULT_CONTENT_OFFSET - 1, ..Geometry::MK24C64 }.check().unwrap();
+        assert!(Geometry { reserve: EEPROM_SIZE - DEFAULT_CONTENT_OFFSET, ..Geometry::MK24C64 }.check().is_err());
+        assert!(Geometry { reserve: u16::MAX, ..Geometry::MK24C64 }.check().is_err());
+    }
+
+    #[test]
+    fn addresses_with_its_address_size() {
+        assert_eq!(Geometry::MK24C64.address(0x1234), [0x12, 0x34]);
+        assert_eq!(Geometry { size: 256, address_bytes: 1, ..Geometry::MK24C64 }.address(0x34), [0x34]);
+        assert_eq!(Geometry::MK24C64.max_content_size(), EEPROM_SIZE - DEFAULT_CONTENT_OFFSET);
+        assert_eq!(Geometry { reserve: 100, ..Geometry::MK24C64 }.max_content_size(), EEPROM_SIZE - DEFAULT_CONTENT_OFFSET - 100);
+    }
+}
@@ -0,0 +1,184 @@
+//! History ring, keeping a trace of the files replaced in EEPROM.
+//!
+//! The ring is stored at the end of the EEPROM when the (v2) metadata has `FLAG_HISTORY` set. Before the metadata
+//! of a new file is written, the metadata it replaces is recorded into the entry following the newest one. Each
+//! entry is 32 bytes, all multi-byte fields being little-endian:
+//!
+//! | Bytes    | Field                                      |
+//! |----------|--------------------------------------------|
+//! | `0..4`   | `sequence`                                 |
+//! | `4..12`  | `timestamp` (Unix time it was replaced at) |
+//! | `12..14` | `flags`                                    |
+//! | `14..16` | `content_crc`                              |
+//! | `16..18` | `content_size`                             |
+//! | `18..26` | `label` (payload version tag)              |
+//! | `26..32` | reserved                                   |
+//!
+//! An entry whose sequence number is 0 or `u32::MAX` (i.e. blank memory) is unused.
+
+use crate::metadata::{from_padded, to_padded, PAYLOAD_VERSION_SIZE};
+
+/// Number of entries in the ring.
+pub const HISTORY_ENTRY_COUNT: usize = 4;
+
+/// Size of a single entry in bytes, a page so that recording an entry is a single write.
+pub const HISTORY_ENTRY_SIZE: usize = 32;
+
+/// Size of the ring in bytes.
+pub const HISTORY_SIZE: usize = HISTORY_ENTRY_COUNT * HISTORY_ENTRY_SIZE;
+
+/// Metadata of a file replaced in EEPROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Increasing number telling the order in which entries were recorded.
+    pub sequence: u32,
+    pub timestamp: u64,
+    pub flags: u16,
+    pub content_crc: u16,
+    pub content_size: u16,
+    pub label: String,
+}
+
+impl Entry {
+    pub fn to_bytes(&self) -> [u8; HISTORY_ENTRY_SIZE] {
+        let mut bytes = [0; HISTORY_ENTRY_SIZE];
+
+        bytes[0..4].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[14..16].copy_from_slice(&self.content_crc.to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.content_size.to_le_bytes());
+        bytes[18..26].copy_from_slice(&to_padded::<PAYLOAD_VERSION_SIZE>(&self.label));
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; HISTORY_ENTRY_SIZE]) -> Option<Self> {
+        let sequence = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+
+        if sequence == 0 || sequence == u32::MAX {
+            return None;
+        }
+
+        Some(Self {
+            sequence,
+            timestamp: u64::from_le_bytes(bytes[4..12].try_into().unwrap()),
+            flags: u16::from_le_bytes([bytes[12], bytes[13]]),
+            content_crc: u16::from_le_bytes([bytes[14], bytes[15]]),
+            content_size: u16::from_le_bytes([bytes[16], bytes[17]]),
+            label: from_padded(&bytes[18..26]),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct History {
+    pub entries: [Option<Entry>; HISTORY_ENTRY_COUNT],
+}
+
+impl History {
+    pub fn from_bytes(bytes: &[u8; HISTORY_SIZE]) -> Self {
+        let mut history = Self::default();
+
+        for (bytes, entry) in bytes.chunks_exact(HISTORY_ENTRY_SIZE).zip(history.entries.iter_mut()) {
+            *entry = bytes.try_into().ok().and_then(Entry::from_bytes);
+        }
+
+        history
+    }
+
+    /// Index of the newest entry, or `None` if the ring is empty.
+    fn newest(&self) -> Option<usize> {
+        (0..HISTORY_ENTRY_COUNT).filter(|&index| self.entries[index].is_some())
+            .max_by_key(|&index| self.entries[index].as_ref().map(|entry| entry.sequence))
+    }
+
+    /// Index and sequence number of the entry to record next, overwriting the oldest one once the ring is full.
+    pub fn next(&self) -> (usize, u32) {
+        match self.newest() {
+            Some(index) => {
+                let sequence = self.entries[index].as_ref().unwrap().sequence;
+                ((index + 1) % HISTORY_ENTRY_COUNT, sequence % (u32::MAX - 1) + 1)
+            }
+            None => (0, 1),
+        }
+    }
+
+    /// Entries, from the newest to the oldest.
+    pub fn newest_first(&self) -> Vec<&Entry> {
+        let mut entries: Vec<&Entry> = self.entries.iter().flatten().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.sequence));
+        entries
+    }
+}
+
+/// Format a Unix time as a UTC date and time, e.g. `2024-06-30 12:00:00 UTC`.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html.
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(sequence: u32) -> Entry {
+        Entry { sequence, timestamp: 1_700_000_000, flags: 1, content_crc: 0xBEEF, content_size: 100, label: "cal-v2".to_string() }
+    }
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(Entry::from_bytes(&entry(7).to_bytes()), Some(entry(7)));
+        assert_eq!(Entry::from_bytes(&[0xFF; HISTORY_ENTRY_SIZE]), None);
+        assert_eq!(Entry::from_bytes(&[0; HISTORY_ENTRY_SIZE]), None);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut history = History::default();
+        assert_eq!(history.next(), (0, 1));
+
+        for sequence in 1..=5 {
+            let (index, next_sequence) = history.next();
+            assert_eq!(next_sequence, sequence);
+            history.entries[index] = Some(entry(sequence));
+        }
+
+        assert_eq!(history.next(), (1, 6));
+        assert_eq!(history.newest_first().iter().map(|entry| entry.sequence).collect::<Vec<_>>(), [5, 4, 3, 2]);
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951_825_600), "2000-02-29 12:00:00 UTC");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13:20 UTC");
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn arbitrary_entries_round_trip(sequence in 1..u32::MAX, timestamp: u64, flags: u16, content_crc: u16, content_size: u16, label in "[\\x{01}-\\x{FF}]{0,8}") {
+            let entry = Entry { sequence, timestamp, flags, content_crc, content_size, label };
+
+            proptest::prop_assert_eq!(Entry::from_bytes(&entry.to_bytes()), Some(entry));
+        }
+
+        #[test]
+        fn parse_never_panics(bytes in proptest::collection::vec(proptest::prelude::any::<u8>(), HISTORY_SIZE)) {
+            let history = History::from_bytes(bytes.as_slice().try_into().unwrap());
+            let _ = (history.next(), history.newest_first());
+        }
+    }
+}
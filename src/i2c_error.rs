@@ -0,0 +1,102 @@
+//! Explanations of I2C errors for the people running the tool, from the errno reported by the adapter driver.
+//!
+//! The codes are the ones adapter drivers are expected to use, see the kernel's `i2c/fault-codes.rst`.
+
+use std::error::Error;
+use std::fmt::Display;
+
+/// Likely cause of an error with errno `errno`, for a transfer with `target` (e.g. "address 0x50 on /dev/i2c-3").
+pub fn explain(errno: i32, target: &str) -> Option<String> {
+    let explanation = match errno {
+        libc::ENXIO => format!("no device is answering at {target} (check wiring, power and address)"),
+        #[cfg(target_os = "linux")]
+        libc::EREMOTEIO => format!("no device is answering at {target} (check wiring, power and address)"),
+        libc::EBUSY => format!("{target} is claimed by a kernel driver (e.g. at24), unbind it first"),
+        libc::EOPNOTSUPP => "the I2C adapter does not support this transfer type".to_string(),
+        libc::EMSGSIZE => "the transfer is too large for the I2C adapter (try a smaller --read-chunk)".to_string(),
+        libc::EAGAIN => "arbitration was lost to another controller on the bus (try more --io-retries)".to_string(),
+        libc::ETIMEDOUT => "the bus timed out, e.g. a device is holding the clock low".to_string(),
+        libc::ENOENT | libc::ENODEV => format!("the I2C bus of {target} does not exist (is the i2c-dev module loaded?)"),
+        libc::EACCES | libc::EPERM => format!("permission denied on the I2C bus of {target} (run as root or join the i2c group)"),
+        _ => return None,
+    };
+
+    Some(explanation)
+}
+
+/// Describe `error`, whose errno is `errno`, with its likely cause followed by the original error on a `caused by:`
+/// line, or as the original error alone if its cause is unknown.
+pub fn describe(error: &impl Display, errno: Option<i32>, target: &str) -> String {
+    match errno.and_then(|errno| explain(errno, target)) {
+        Some(explanation) => format!("{explanation}\ncaused by: {error}"),
+        None => error.to_string(),
+    }
+}
+
+/// Error of an I2C transfer displayed as the explanation of its likely cause, with the original error as its source.
+#[derive(Debug, thiserror::Error)]
+#[error("{explanation}")]
+pub struct Explained {
+    explanation: String,
+    #[source]
+    error: Box<dyn Error + Send + Sync>,
+}
+
+/// `error`, whose errno is `errno`, as the source of a failure of a transfer with `target`: explained like `describe`
+/// does, or the original error alone if its cause is unknown.
+pub fn explained(error: impl Error + Send + Sync + 'static, errno: Option<i32>, target: &str) -> Box<dyn Error + Send + Sync> {
+    match errno.and_then(|errno| explain(errno, target)) {
+        Some(explanation) => Box::new(Explained { explanation, error: Box::new(error) }),
+        None => Box::new(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: &str = "address 0x50 on /dev/i2c-3";
+
+    #[test]
+    fn explains_missing_device() {
+        assert!(explain(libc::ENXIO, TARGET).unwrap().starts_with("no device is answering at address 0x50 on /dev/i2c-3"));
+
+        #[cfg(target_os = "linux")]
+        assert!(explain(libc::EREMOTEIO, TARGET).unwrap().starts_with("no device is answering at address 0x50 on /dev/i2c-3"));
+    }
+
+    #[test]
+    fn explains_adapter_limitations() {
+        assert!(explain(libc::EBUSY, TARGET).unwrap().contains("kernel driver"));
+        assert!(explain(libc::EOPNOTSUPP, TARGET).unwrap().contains("does not support"));
+        assert!(explain(libc::EMSGSIZE, TARGET).unwrap().contains("too large"));
+    }
+
+    #[test]
+    fn keeps_the_original_error() {
+        let error = std::io::Error::from_raw_os_error(libc::ENXIO);
+        let description = describe(&error, error.raw_os_error(), TARGET);
+
+        assert_eq!(description.lines().nth(1), Some(format!("caused by: {error}").as_str()));
+    }
+
+    #[test]
+    fn explained_errors_keep_the_original_as_source() {
+        let error = explained(std::io::Error::from_raw_os_error(libc::ENXIO), Some(libc::ENXIO), TARGET);
+
+        assert_eq!(error.to_string(), explain(libc::ENXIO, TARGET).unwrap());
+        assert_eq!(error.source().unwrap().to_string(), std::io::Error::from_raw_os_error(libc::ENXIO).to_string());
+        assert!(explained(std::io::Error::from_raw_os_error(libc::ENOSPC), Some(libc::ENOSPC), TARGET).source().is_none());
+    }
+
+    #[test]
+    fn leaves_unknown_errors_as_they_are() {
+        let error = std::io::Error::from_raw_os_error(libc::ENOSPC);
+
+        assert_eq!(explain(libc::ENOSPC, TARGET), None);
+        // Invalid arguments have many causes besides the size of the transfer.
+        assert_eq!(explain(libc::EINVAL, TARGET), None);
+        assert_eq!(describe(&error, error.raw_os_error(), TARGET), error.to_string());
+        assert_eq!(describe(&"unknown", None, TARGET), "unknown");
+    }
+}
@@ -0,0 +1,27 @@
+//! Deferred handling of SIGINT and SIGTERM during writes: instead of killing the process between (or during) page
+//! writes, a signal only sets a flag, which the write checks before each page so that it can stop cleanly.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set once SIGINT or SIGTERM was received after `defer` was called.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Signal handler, which only sets the flag: storing to an atomic is async-signal-safe, and the I2C transfer in
+/// progress, if any, completes normally.
+extern "C" fn handle(_signal: libc::c_int) {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Defer SIGINT and SIGTERM until `requested` is checked, for the rest of the process.
+pub fn defer() {
+    // SAFETY: `handle` is async-signal-safe, and `signal` has no other requirement.
+    unsafe {
+        libc::signal(libc::SIGINT, handle as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, handle as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+/// Check whether SIGINT or SIGTERM was received since `defer` was called.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
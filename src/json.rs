@@ -0,0 +1,18 @@
+//! JSON written by hand, for the few values the tool and its logs output as JSON.
+
+/// Format a string as a JSON string literal.
+pub fn string(value: &str) -> String {
+    let mut output = String::from('"');
+
+    for character in value.chars() {
+        match character {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            character if character.is_control() => output.push_str(&format!("\\u{:04x}", character as u32)),
+            character => output.push(character),
+        }
+    }
+
+    output.push('"');
+    output
+}
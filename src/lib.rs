@@ -0,0 +1,22 @@
+//! Reading and writing a file with its metadata in the EEPROM of a module, such as the MK24C64, over I2C.
+
+pub mod ab;
+pub mod content_type;
+pub mod crc_detect;
+pub mod device;
+pub mod eeprom;
+pub mod geometry;
+pub mod history;
+pub mod i2c_error;
+pub mod interrupt;
+pub mod json;
+pub mod metadata;
+pub mod mux;
+pub mod pages;
+pub mod polling;
+pub mod retry;
+pub mod slots;
+pub mod stats;
+pub mod tlv;
+pub mod transaction_log;
+pub mod watchdog;
@@ -0,0 +1,62 @@
+//! Advisory lock preventing several instances of the tool from using the same I2C bus at the same time.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory holding the lock files, falling back to the temporary directory if it does not exist.
+const LOCK_DIRECTORY: &str = "/run/lock";
+
+/// Path of the lock file for the bus at `device_path`, e.g. `/run/lock/vki2cfile-i2c-3.lock` for `/dev/i2c-3`.
+pub fn lock_path(device_path: &str) -> PathBuf {
+    let directory = Path::new(LOCK_DIRECTORY);
+    let directory = if directory.is_dir() { directory.to_path_buf() } else { std::env::temp_dir() };
+    let name = Path::new(device_path).file_name().map_or(device_path.into(), |name| name.to_string_lossy());
+
+    directory.join(format!("vki2cfile-{name}.lock"))
+}
+
+/// Acquire an exclusive lock on `path`, blocking until it is available unless `no_wait` is set, in which case
+/// an `io::ErrorKind::WouldBlock` error is returned. The lock is held until the returned file is closed, which
+/// the kernel also does when the process exits, whichever way it does.
+#[cfg(unix)]
+pub fn acquire(path: &Path, no_wait: bool) -> io::Result<File> {
+    use std::os::fd::AsRawFd;
+
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+    let operation = if no_wait { libc::LOCK_EX | libc::LOCK_NB } else { libc::LOCK_EX };
+
+    // SAFETY: `flock` only operates on the file descriptor, which stays valid for the duration of the call.
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(file)
+}
+
+/// Open the lock file at `path` without locking it, as there is no `flock` outside Unix. Devices cannot be opened on
+/// these platforms anyway, see `device::unsupported`.
+#[cfg(not(unix))]
+pub fn acquire(path: &Path, _no_wait: bool) -> io::Result<File> {
+    OpenOptions::new().create(true).truncate(false).write(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_path_is_derived_from_device_name() {
+        assert!(lock_path("/dev/i2c-3").ends_with("vki2cfile-i2c-3.lock"));
+    }
+
+    #[test]
+    fn second_lock_fails_without_waiting() {
+        let path = std::env::temp_dir().join(format!("vki2cfile-test-{}.lock", std::process::id()));
+        let _lock = acquire(&path, true).unwrap();
+
+        assert_eq!(acquire(&path, true).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+//! Backend of the `log` macros for the binary, printing to stderr at the level set with `-v` and `-q`.
+//!
+//! Info messages are the details `--verbose` always printed and keep their plain form. Debug messages, one per I2C
+//! transaction with `-vv`, and trace messages, hex dumps of small transfers with `-vvv`, both logged by the library,
+//! are prefixed with their level and the time since the start of the program, to tell how long the transactions took
+//! apart from each other.
+//!
+//! As with env_logger, `RUST_LOG` set to a level (`off`, `error`, `warn`, `info`, `debug` or `trace`) overrides the
+//! flags, e.g. to get the transactions out of a tool run by a service.
+
+use std::sync::OnceLock;
+use std::time::Instant;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+static LOGGER: Logger = Logger;
+
+/// Time the logger was installed at, which the debug and trace messages are timed from.
+static START: OnceLock<Instant> = OnceLock::new();
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match record.level() {
+            Level::Info => eprintln!("{}", record.args()),
+            Level::Error | Level::Warn => eprintln!("{}: {}", record.level().as_str().to_lowercase(), record.args()),
+            level => {
+                let elapsed = START.get().map_or(0.0, |start| start.elapsed().as_secs_f64());
+
+                eprintln!("[{:<5} {elapsed:9.6}] {}", level.as_str(), record.args());
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Level of the messages printed given the number of `-v` flags and `--quiet`. Without either, nothing more is
+/// printed than before the tool logged anything.
+pub fn level(verbose: u8, quiet: bool) -> LevelFilter {
+    match (verbose, quiet) {
+        (_, true) => LevelFilter::Error,
+        (0, false) => LevelFilter::Warn,
+        (1, false) => LevelFilter::Info,
+        (2, false) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Install the logger, printing messages up to `level` unless `RUST_LOG` sets another level.
+pub fn init(level: LevelFilter) {
+    let level = std::env::var("RUST_LOG").ok().and_then(|level| level.parse().ok()).unwrap_or(level);
+
+    START.get_or_init(Instant::now);
+
+    // Only fails if a logger is already installed, which is then kept.
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_set_the_level() {
+        assert_eq!(level(0, false), LevelFilter::Warn);
+        assert_eq!(level(1, false), LevelFilter::Info);
+        assert_eq!(level(2, false), LevelFilter::Debug);
+        assert_eq!(level(5, false), LevelFilter::Trace);
+        assert_eq!(level(0, true), LevelFilter::Error);
+    }
+}
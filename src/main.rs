@@ -1,41 +1,287 @@
 use std::time::Duration;
-use std::{fs::File, io::Read, path::PathBuf};
-use std::process::abort;
+use std::{fs::File, io::{IsTerminal, Read, Seek, Write}, path::{Path, PathBuf}};
+use std::sync::{Mutex, OnceLock};
 use clap::{Args, Parser, Subcommand};
-use i2cdev::core::I2CDevice;
-use i2cdev::{core::{I2CMessage, I2CTransfer}, linux::LinuxI2CDevice};
-use serde::{Deserialize, Serialize};
-
-/// Total size of the EEPROM in bytes.
-const EEPROM_SIZE: u16 = 8192;
-/// Offset to the address of the first byte in EEPROM where the metadata resides.
-const METADATA_OFFSET: u16 = 0;
-/// Offset to the address of the first byte in EEPROM where the content resides.
-const CONTENT_OFFSET: u16 = 32;
-/// Maximum size of content that can be stored in the EEPROM memory.
-const MAX_CONTENT_SIZE: u16 = EEPROM_SIZE - CONTENT_OFFSET;
-
-/// CRC algorithm used.
-const CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_USB);
+use vki2cfile::{ab, content_type, crc_detect, device, eeprom, geometry, history, i2c_error, interrupt, json, metadata, mux, pages, polling, retry, slots, stats, tlv, transaction_log, watchdog};
+use vki2cfile::eeprom::{check_layout, check_unlocked, content_end, crc_mismatch, describe_error, to_hex, validate_content, with_retries, Eeprom, Error, Options, ReadOptions, Result, WriteCycle, WriteOptions, CRC, DEFAULT_CONTENT_OFFSET, DEFAULT_CRC_RETRIES, DEFAULT_IO_RETRIES, DEFAULT_PAGE_RETRIES, DEFAULT_READ_CHUNK, DEFAULT_READ_RETRIES, DEFAULT_WRITE_DELAY, EEPROM_SIZE, METADATA_OFFSET};
+use device::{Device, PlatformDevice};
+use geometry::Geometry;
+use content_type::ContentType;
+use metadata::{FileInfo, Format, Layout, Metadata, CONTENT_TYPE_MASK, FLAG_LOCKED, MODULE_FLAGS};
+use slots::{Slot, SlotTable, SLOT_COUNT};
+use transaction_log::LogFormat;
 
-/// Sanity check.
-static _METDATA_SIZE_ASSERTION: () = assert!(std::mem::size_of::<Metadata>() <= CONTENT_OFFSET as usize);
+mod lock;
+mod logger;
+
+/// Exit codes other than 0 (success), a stable contract for scripts. They are listed in the long help and by the `codes`
+/// subcommand, see `exit_codes_help`. Codes are never renumbered: new outcomes get new codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitCode {
+    Failed = 1,
+    Usage = 2,
+    Changed = 3,
+    PayloadVersionMismatch = 10,
+    WriteInterrupted = 11,
+    Blank = 12,
+    Interrupted = 13,
+    BusStuck = 14,
+    Corrupted = 15,
+    DeviceNotFound = 16,
+    TooLarge = 17,
+    Io = 18,
+    Empty = 19,
+    Busy = 20,
+    Mux = 21,
+}
+
+impl ExitCode {
+    const ALL: [ExitCode; 15] = [
+        ExitCode::Failed, ExitCode::Usage, ExitCode::Changed, ExitCode::PayloadVersionMismatch, ExitCode::WriteInterrupted,
+        ExitCode::Blank, ExitCode::Interrupted, ExitCode::BusStuck, ExitCode::Corrupted, ExitCode::DeviceNotFound, ExitCode::TooLarge,
+        ExitCode::Io, ExitCode::Empty, ExitCode::Busy, ExitCode::Mux,
+    ];
+
+    fn description(self) -> &'static str {
+        match self {
+            ExitCode::Failed => "any other failure",
+            ExitCode::Usage => "invalid command line",
+            ExitCode::Changed => "write --if-changed --report-changed wrote the file",
+            ExitCode::PayloadVersionMismatch => "the payload version of the file does not match --require-payload-version",
+            ExitCode::WriteInterrupted => "the previous write was interrupted, the file in EEPROM is incomplete",
+            ExitCode::Blank => "the EEPROM is blank (factory default)",
+            ExitCode::Interrupted => "the write was stopped by SIGINT or SIGTERM, see write --resume",
+            ExitCode::BusStuck => "a transfer exceeded --io-timeout-ms, the bus may be stuck",
+            ExitCode::Corrupted => "the file in EEPROM does not match its CRC or digest",
+            ExitCode::DeviceNotFound => "the I2C bus device does not exist",
+            ExitCode::TooLarge => "the file (or data) does not fit in the space available",
+            ExitCode::Io => "an I2C transfer failed even after its retries, trying again may succeed",
+            ExitCode::Empty => "the file in EEPROM is empty, e.g. it was erased",
+            ExitCode::Busy => "another instance of this tool is using the I2C bus, with --no-wait",
+            ExitCode::Mux => "the mux in front of the EEPROM could not be opened or its channel selected",
+        }
+    }
+}
 
-/// Metadata stored in the memory
-/// 
-/// Note: If you modify this structure, take care to ensure backwards compatiblity.
-#[repr(C)]
-#[derive(Serialize, Deserialize)]
-struct Metadata {
-    unused: [u8; 28],
-    content_crc: u16,
-    content_size: u16,
+/// List of the exit codes for the long help.
+fn exit_codes_help() -> String {
+    let mut help = String::from("Exit codes:\n    0  success\n");
+
+    for code in ExitCode::ALL {
+        help += &format!("  {:>3}  {}\n", code as i32, code.description());
+    }
+
+    help
+}
+
+/// Code to exit the process with on `error`.
+fn exit_code(error: &Error) -> ExitCode {
+    match error {
+        Error::PayloadVersionMismatch { .. } => ExitCode::PayloadVersionMismatch,
+        Error::WriteInterrupted { .. } => ExitCode::WriteInterrupted,
+        Error::Blank { .. } => ExitCode::Blank,
+        Error::Interrupted { .. } => ExitCode::Interrupted,
+        Error::CrcMismatch { .. } | Error::Corrupted { .. } => ExitCode::Corrupted,
+        Error::DeviceNotFound { .. } => ExitCode::DeviceNotFound,
+        Error::ContentTooLarge { .. } | Error::DataTooLarge { .. } | Error::SlotOverlap { .. } => ExitCode::TooLarge,
+        Error::Transfer { .. } | Error::AckTimeout { .. } | Error::Poll { .. } => ExitCode::Io,
+        Error::Empty { .. } => ExitCode::Empty,
+        Error::Busy { .. } => ExitCode::Busy,
+        Error::Mux { .. } => ExitCode::Mux,
+        Error::Usage { .. } => ExitCode::Usage,
+        Error::DeviceOpen { .. } | Error::MetadataInvalid { .. } | Error::NotSlotted { .. } | Error::SlotEmpty { .. }
+            | Error::HoldsPlainFile { .. } | Error::NoActiveHalf { .. } | Error::HalfNotWritten { .. } | Error::NotPlainFile { .. }
+            | Error::AlreadyWritten { .. } | Error::NotFound { .. } | Error::Locked { .. } | Error::Unprovisioned { .. }
+            | Error::Unsupported { .. } | Error::MagicMismatch { .. } | Error::StuckContent { .. } | Error::Verification { .. }
+            | Error::CannotResume { .. } | Error::Unstable { .. } | Error::OutOfRange { .. } | Error::NoEui { .. }
+            | Error::InvalidLayout { .. } | Error::InvalidRequest { .. } | Error::Lock { .. } | Error::TransactionLog { .. }
+            | Error::SourceFile { .. } | Error::SourceChanged { .. } | Error::DestinationExists { .. } | Error::Destination { .. }
+            | Error::SelfTest { .. } | Error::Benchmark { .. } | Error::Restore { .. } => ExitCode::Failed,
+    }
 }
 
+/// Sanity check.
+static _METDATA_SIZE_ASSERTION: () = assert!(std::mem::size_of::<Metadata>() <= DEFAULT_CONTENT_OFFSET as usize);
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_long_help = exit_codes_help())]
 struct Command {
+    /// Path to the I2C bus the EEPROM is on.
+    #[arg(long, global = true, env = "VKI2CFILE_DEVICE", default_value = DEFAULT_DEVICE_PATH)]
+    device: String,
+
+    /// Use a simulated EEPROM persisted to the file at this path instead of the I2C device, e.g. to test provisioning
+    /// scripts without hardware. The file holds the whole EEPROM and is created blank (all 0xFF) if it does not exist.
+    /// The simulated part has the pages and write cycles of the MK24C64.
+    #[arg(long, global = true, env = "VKI2CFILE_SIMULATE")]
+    simulate: Option<String>,
+
+    /// Skip the write cycles of the simulated EEPROM, so that writes complete immediately.
+    #[arg(long, global = true, requires = "simulate")]
+    simulate_fast: bool,
+
+    /// I2C address of the EEPROM, in decimal or in hex (prefixed with `0x`).
+    #[arg(long, global = true, env = "VKI2CFILE_ADDRESS", value_parser = parse_address, default_value = "0x50")]
+    address: u16,
+
+    /// I2C address of a PCA9548-style mux the EEPROM is behind, in decimal or in hex (prefixed with `0x`).
+    #[arg(long, global = true, value_parser = parse_address, requires = "mux_channel")]
+    mux_address: Option<u16>,
+
+    /// Channel of the mux to select before accessing the EEPROM.
+    #[arg(long, global = true, value_parser = clap::value_parser!(u8).range(0..mux::CHANNEL_COUNT as i64), requires = "mux_address")]
+    mux_channel: Option<u8>,
+
+    /// Disconnect all channels of the mux before exiting, whether the subcommand succeeds or not.
+    #[arg(long, global = true, requires = "mux_address")]
+    mux_clear: bool,
+
+    /// Offset to the address of the first byte in EEPROM where the metadata resides.
+    #[arg(long, global = true, default_value_t = METADATA_OFFSET)]
+    metadata_offset: u16,
+
+    /// Offset to the address of the first byte in EEPROM where the content resides, e.g. to leave room for a larger
+    /// header. Must be a multiple of the page size and leave room for the metadata before it. Devices written with
+    /// another offset can only be read with that same offset.
+    #[arg(long, global = true, default_value_t = DEFAULT_CONTENT_OFFSET)]
+    content_offset: u16,
+
+    /// Encoder of v1 metadata blocks. Both give the same bytes: `v1` writes and reads the layout by hand rather than
+    /// through bincode, to check that a bincode upgrade leaves the metadata of devices in the field readable.
+    #[arg(long, global = true, value_enum, value_name = "LAYOUT", default_value_t = Layout::Bincode)]
+    metadata_layout: Layout,
+
+    /// Fail immediately instead of waiting if another instance of this tool is using the I2C bus.
+    #[arg(long, global = true)]
+    no_wait: bool,
+
+    /// Wait this many milliseconds after each write (of the metadata or of a page) for the device to complete its
+    /// write cycle, instead of polling the device until it acknowledges again. For adapters where ACK polling
+    /// misbehaves, or parts needing a specific delay: 0 for FRAM, which has no write cycle. With `write --fast`,
+    /// polling is used regardless and this is the minimum time to poll for before giving up. Without this option, a
+    /// 10 ms delay is used where ACK polling is not supported.
+    #[arg(long, global = true, value_name = "MS", visible_alias = "write-delay-ms")]
+    write_delay: Option<u64>,
+
+    /// Adaptive timing: wait this many milliseconds after each write at first, reading each page back and waiting
+    /// longer (up to --max-delay) whenever one does not read back as written, to learn the write cycle of an unknown
+    /// part. The delay settled on is reported at the end of a write.
+    #[arg(long, global = true, value_name = "MS", requires = "max_delay", conflicts_with = "write_delay")]
+    min_delay: Option<u64>,
+
+    /// Longest delay after each write with adaptive timing, see --min-delay.
+    #[arg(long, global = true, value_name = "MS", requires = "min_delay")]
+    max_delay: Option<u64>,
+
+    /// Wait this many milliseconds after reading the metadata before going on. Reads do not start a write cycle, so
+    /// no delay is needed unless a part needs time to recover between transfers.
+    #[arg(long, global = true, value_name = "MS", default_value_t = 0)]
+    read_delay: u64,
+
+    /// Retry a failed I2C transfer up to this many times, with a short exponential backoff, before giving up.
+    #[arg(long, global = true, value_name = "COUNT", default_value_t = DEFAULT_IO_RETRIES)]
+    io_retries: u32,
+
+    /// Give up on a single I2C transfer taking longer than this, in milliseconds, as the bus is likely stuck: set as
+    /// the adapter timeout where supported, and enforced by a watchdog exiting the process otherwise. 0 disables it.
+    #[arg(long, global = true, value_name = "MS", default_value_t = DEFAULT_IO_TIMEOUT_MS)]
+    io_timeout_ms: u64,
+
+    /// Delay before retrying a failed I2C transfer the first time, doubled before each following retry.
+    #[arg(long, global = true, value_name = "MS", default_value_t = retry::DEFAULT_INITIAL_DELAY.as_millis() as u64)]
+    retry_delay: u64,
+
+    /// Upper bound on the delay between retries.
+    #[arg(long, global = true, value_name = "MS", default_value_t = retry::DEFAULT_MAX_DELAY.as_millis() as u64)]
+    retry_max_delay: u64,
+
+    /// Pick each delay between retries at random between half and all of its value, so that controllers sharing a
+    /// bus do not keep retrying at the same time.
+    #[arg(long, global = true)]
+    retry_jitter: bool,
+
+    /// Size in bytes of the physical pages of the EEPROM. Writes are split so that none crosses a page boundary.
+    #[arg(long, global = true, value_parser = pages::parse_page_size, default_value_t = pages::DEFAULT_PAGE_SIZE)]
+    page_size: u16,
+
+    /// Expert option: number of bytes at the end of the EEPROM (before the history ring, if any) kept out of the space
+    /// for content, e.g. for data written by other tools. It lowers the maximum file size, both for writes and for the
+    /// sizes accepted when reading, and must leave at least a byte for content after the metadata.
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = 0)]
+    reserve: u16,
+
+    /// How to write into EEPROM: a page (or part of one) per transaction, or a single byte per transaction for parts
+    /// and adapters that misbehave with page writes. Single-byte writes are dramatically slower, each byte taking a
+    /// whole write cycle.
+    #[arg(long, global = true, value_enum, default_value_t = PageWriteMode::Sequential)]
+    page_write_mode: PageWriteMode,
+
+    /// Maximum number of bytes read in a single transfer, for adapters limiting the size of transfers (e.g. to 255
+    /// or 512 bytes). Without it, reads start with chunks of 1024 bytes, halved whenever the adapter rejects a read as
+    /// too large.
+    #[arg(long, global = true, value_name = "BYTES", value_parser = clap::value_parser!(u16).range(1..))]
+    read_chunk: Option<u16>,
+
+    /// Print details about the operations performed and the transfers they take to stderr, e.g. retries, adapter
+    /// fallbacks and the time spent waiting for write cycles. Given twice, also print each I2C transaction with its
+    /// direction, offset, length, duration and retries, and three times, hex dumps of the small ones.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Bench testing: read (or write and verify) the file this many times, tallying the successes, failures and
+    /// retries, to surface intermittent bus issues when bringing up a board.
+    #[arg(long, global = true, hide = true, value_name = "COUNT", value_parser = clap::value_parser!(u32).range(1..))]
+    repeat: Option<u32>,
+
+    /// Print the bytes transferred, the number of transfers and retries and the time spent in them and waiting for
+    /// write cycles to stderr at the end, and include them in --json output.
+    #[arg(long, global = true)]
+    stats: bool,
+
+    /// Read again up to this many times when the metadata changed while reading the file, before failing.
+    #[arg(long, global = true, value_name = "COUNT", default_value_t = DEFAULT_READ_RETRIES)]
+    read_retries: u32,
+
+    /// Read the content again up to this many times when it does not match its CRC, e.g. after a glitch on the bus,
+    /// before failing. Gives up early if a read gives the same bytes as the previous one.
+    #[arg(long, global = true, value_name = "COUNT", default_value_t = DEFAULT_CRC_RETRIES)]
+    crc_retries: u32,
+
+    /// When the content does not match its CRC, read it once more and report the offsets of the bytes that differed
+    /// between the two reads, which points to noise on the bus, or that none did, which points to corrupted content.
+    #[arg(long, global = true)]
+    diagnose: bool,
+
+    /// For electrically noisy setups: when the content read fails its CRC, read it this many times in all (an odd
+    /// number) and take the byte-wise majority before checking it. Bytes corrected are counted in --stats.
+    #[arg(long, global = true, value_name = "COUNT", default_value_t = 1, value_parser = parse_votes)]
+    read_votes: u32,
+
+    /// Limit the bulk transfers of reads and writes to this many bytes per second on average, pausing between chunks
+    /// and pages (never within a transfer) to leave the bus idle for other devices on it.
+    #[arg(long, global = true, value_name = "BYTES_PER_SEC", value_parser = clap::value_parser!(u64).range(1..))]
+    throttle: Option<u64>,
+
+    /// Show the progress of transfers of at least this many bytes on stderr, unless stderr is not a terminal or with
+    /// --quiet or --json.
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = DEFAULT_PROGRESS_THRESHOLD)]
+    progress_threshold: u64,
+
+    /// Append a timestamped record of every read and write transaction with the EEPROM (region, offset, length,
+    /// outcome and retries) to this file, for an audit trail of each unit. Each record is flushed as soon as the
+    /// transaction ends.
+    #[arg(long, global = true, value_name = "PATH")]
+    transaction_log: Option<PathBuf>,
+
+    /// Format of the records of --transaction-log.
+    #[arg(long, global = true, value_enum, requires = "transaction_log", default_value_t = LogFormat::Jsonl)]
+    transaction_log_format: LogFormat,
+
+    /// Only print errors and the output requested (e.g. info, or a file read to stdout), not the write estimate,
+    /// confirmations or the progress display, for scripts.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
     #[command(subcommand)]
     subcommand: Sub
 }
@@ -44,160 +290,2354 @@ struct Command {
 enum Sub {
     Read(ReadCommand),
     Write(WriteCommand),
+    Verify(VerifyCommand),
+    Erase(EraseCommand),
+    Info(InfoCommand),
+    Ls(LsCommand),
+    Kv(KvCommand),
+    Userdata(UserdataCommand),
+    Serial(SerialCommand),
+    Lock(LockCommand),
+    Unlock(UnlockCommand),
+    History(HistoryCommand),
+    SelfTest(SelfTestCommand),
+    DetectCrc(DetectCrcCommand),
+    Benchmark(BenchmarkCommand),
+    Eui(EuiCommand),
+    Codes(CodesCommand),
 }
 
 /// Read a file from EEPROM into the filesystem.
 #[derive(Args)]
 struct ReadCommand {
-    /// Read the file out regardless whether CRC validation succeeds or not. 
+    /// Read the file out regardless whether CRC (and digest) validation succeeds or not. 
     #[arg(long)]
     ignore_crc: bool,
 
-    /// Read the file out even if it is empty (i.e. zero-sized).
+    /// Read the file out even if it is empty (i.e. zero-sized), or the EEPROM is blank.
     #[arg(long)]
     allow_empty: bool,
 
-    /// Path in the filesystem to write the file into.
-    destination: PathBuf
+    /// Only read an empty file if its metadata was explicitly written with a metadata CRC (v3), and fail on a blank
+    /// EEPROM or legacy metadata of size 0, e.g. all zero, which cannot be told apart from an unprovisioned part.
+    #[arg(long)]
+    strict_size: bool,
+
+    /// Read the file stored in the given slot (defaults to slot 0).
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..SLOT_COUNT as i64))]
+    slot: Option<u8>,
+
+    /// Read the stored bytes out as-is, even if the metadata flags say they need processing this tool cannot
+    /// undo (e.g. decryption) or carry flags unknown to this version of the tool.
+    #[arg(long)]
+    force_raw: bool,
+
+    /// Warn if the content is entirely 0x00 or 0xFF, which usually means the wrong device was read.
+    #[arg(long)]
+    sanity_check: bool,
+
+    /// Fail instead of warning when the sanity check flags the content.
+    #[arg(long, requires = "sanity_check")]
+    strict: bool,
+
+    /// Check that the file starts with these bytes (given as hex), as written by `write --magic`, and strip them.
+    #[arg(long)]
+    expect_magic: Option<HexBytes>,
+
+    /// Fail unless the file has this payload version tag. Files without a tag have an empty one.
+    #[arg(long)]
+    require_payload_version: Option<String>,
+
+    /// Read `--size` bytes from the start of the EEPROM as-is, for contents written with `write --raw`. There is no
+    /// metadata, hence no CRC or size to check.
+    #[arg(long, group = "raw_mode", requires = "size", conflicts_with_all = ["ignore_crc", "allow_empty", "strict_size", "slot", "force_raw", "sanity_check", "expect_magic", "require_payload_version"])]
+    raw: bool,
+
+    /// Read `--size` bytes of content as-is without reading the metadata, e.g. when it is corrupted or was written by
+    /// another tool. The bytes are read from where the content starts, after the metadata, with nothing to check.
+    #[arg(long, group = "raw_mode", requires = "size", conflicts_with_all = ["ignore_crc", "allow_empty", "strict_size", "slot", "force_raw", "sanity_check", "expect_magic", "require_payload_version"])]
+    ignore_metadata: bool,
+
+    /// Number of bytes to read in raw mode or with --ignore-metadata. Reading past the end of the EEPROM fails.
+    #[arg(long, requires = "raw_mode", value_parser = clap::value_parser!(u16).range(1..=EEPROM_SIZE as i64))]
+    size: Option<u16>,
+
+    /// Print the file content as a hex + ASCII dump to stdout.
+    #[arg(long)]
+    hexdump: bool,
+
+    /// Overwrite the destination file if it already exists.
+    #[arg(long)]
+    force: bool,
+
+    /// Rename the destination file to `<name>.bak` if it already exists, before writing the file read.
+    #[arg(long, conflicts_with = "force")]
+    backup: bool,
+
+    /// Update the destination in place if it already exists, only writing the bytes that differ from the file read,
+    /// e.g. when it is a device node or a memory-mapped file. A regular file is truncated to the size of the file read.
+    #[arg(long, conflicts_with_all = ["backup", "force"])]
+    patch: bool,
+
+    /// If the file read cannot be written into the destination, write it to stdout instead of saving it to a
+    /// temporary file, so that it does not need to be read out of EEPROM again.
+    #[arg(long, requires = "destination", conflicts_with = "hexdump")]
+    stdout_on_fail: bool,
+
+    /// Path in the filesystem to write the file into, or `-` for stdout.
+    #[arg(required_unless_present = "hexdump")]
+    destination: Option<PathBuf>
 }
 
 /// Write a file from the filesystem into EEPROM.
 #[derive(Args)]
 struct WriteCommand {
-    /// Path in the filesystem to read the file from.
-    source: PathBuf
+    /// Format of the metadata written alongside the file.
+    #[arg(long, value_enum, default_value_t = Format::default())]
+    write_format: Format,
+
+    /// Additionally store a digest of the file in a trailer right after its content (requires v2 metadata).
+    #[arg(long, value_enum)]
+    digest: Option<DigestAlgorithm>,
+
+    /// Mark the file as gzip-compressed (requires v2 metadata). The file must already be compressed.
+    #[arg(long)]
+    compressed: bool,
+
+    /// Mark the file as encrypted (requires v2 metadata). The file must already be encrypted.
+    #[arg(long)]
+    encrypted: bool,
+
+    /// Write the file into the given slot, leaving the other slots untouched. Without this option, the EEPROM
+    /// holds a single plain file and any slot table is discarded.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..SLOT_COUNT as i64), conflicts_with_all = ["digest", "compressed", "encrypted"])]
+    slot: Option<u8>,
+
+    /// Address in EEPROM where a new slot starts (must be a multiple of 32). Defaults to right after the last
+    /// used slot. Existing slots keep their address.
+    #[arg(long, requires = "slot")]
+    slot_offset: Option<u16>,
+
+    /// Type of the content written into the slot, free for the user to define.
+    #[arg(long, requires = "slot", default_value_t = 0)]
+    slot_type: u8,
+
+    /// Prepend these bytes (given as hex) to the file, e.g. a magic expected by a bootloader. They are covered by
+    /// the CRC and size like the rest of the content.
+    #[arg(long, conflicts_with = "append")]
+    magic: Option<HexBytes>,
+
+    /// Pad the file up to this size. The padding is part of the content: it is covered by the CRC and included in
+    /// the stored size, so reading the file back returns it padded.
+    #[arg(long, conflicts_with = "append")]
+    pad_to: Option<u16>,
+
+    /// Byte used for padding, e.g. 0xFF to match erased memory.
+    #[arg(long, requires = "pad_to", value_parser = parse_byte, default_value = "0x00")]
+    pad_byte: u8,
+
+    /// Version tag of the content format (e.g. "calib-v3"), up to 8 printable ASCII characters (requires v2
+    /// metadata).
+    #[arg(long, value_parser = parse_payload_version)]
+    payload_version: Option<String>,
+
+    /// Record the metadata of the files replaced in a history ring at the end of the EEPROM (requires v2 metadata).
+    /// Once enabled, later writes keep it enabled. The ring takes 128 bytes off the maximum file size.
+    #[arg(long)]
+    history: bool,
+
+    /// Write the file as-is from the start of the EEPROM, without any metadata, for layouts defined by the firmware
+    /// reading it. The whole EEPROM is available, and the metadata of a previous file is overwritten.
+    #[arg(long, conflicts_with_all = ["write_format", "digest", "compressed", "encrypted", "slot", "magic", "pad_to", "payload_version", "history", "append"])]
+    raw: bool,
+
+    /// Append the file to the file currently stored in EEPROM instead of replacing it.
+    #[arg(long, conflicts_with_all = ["digest", "compressed", "encrypted", "slot"])]
+    append: bool,
+
+    /// Write the file into the inactive half of an A/B layout (requires v2 metadata), making it active only once it
+    /// is written and read back, so that the previous file stays in use if the write fails. Reading falls back to the
+    /// other half if the active one is corrupted. An EEPROM holding a plain file is converted to the A/B layout, which
+    /// halves the maximum file size.
+    #[arg(long, conflicts_with_all = ["digest", "compressed", "encrypted", "slot", "append", "raw", "payload_version", "resume", "start_page"])]
+    ab: bool,
+
+    /// Additionally store a CRC of the metadata followed by the file in a trailer right after the file (requires v2
+    /// metadata), giving a single value covering the whole used part of the EEPROM, see `info --full-crc`.
+    #[arg(long, conflicts_with_all = ["slot", "append", "raw", "ab"])]
+    full_crc: bool,
+
+    /// Store this CRC (given as hex) in the metadata instead of computing one over the file, for firmware owning the
+    /// CRC semantics (requires v2 metadata). The CRC is then not checked when reading the file: detecting corrupted
+    /// content is the responsibility of the firmware.
+    #[arg(long, value_parser = parse_crc, value_name = "HEX", conflicts_with_all = ["slot", "append", "raw", "ab"])]
+    crc_value: Option<u16>,
+
+    /// Store a zero CRC in the metadata instead of computing one over the file, like --crc-value 0.
+    #[arg(long, conflicts_with_all = ["crc_value", "slot", "append", "raw", "ab"])]
+    no_crc: bool,
+
+    /// Read the content currently stored first, and only write the pages that differ from it, saving time and
+    /// endurance when rewriting a file with few changes. Pages past the end of the file currently stored are always
+    /// written.
+    #[arg(long, conflicts_with_all = ["slot", "append", "raw", "ab"])]
+    diff_write: bool,
+
+    /// Fail if the adapter does not support ACK polling, instead of falling back to a fixed delay after each write.
+    /// ACK polling is used by default unless --write-delay is given, which then sets the minimum polling timeout.
+    #[arg(long, visible_alias = "no-delay")]
+    fast: bool,
+
+    /// Read each page back right after writing it, and write it again if it differs, failing with its address once
+    /// --page-retries is exhausted.
+    #[arg(long)]
+    verify_pages: bool,
+
+    /// Number of times a page that does not read back as written is written again.
+    #[arg(long, requires = "verify_pages", default_value_t = DEFAULT_PAGE_RETRIES)]
+    page_retries: u32,
+
+    /// Resume an interrupted write of the same file with the same options, from the last progress it recorded. The
+    /// part already written must match the file. Does nothing if the write already completed.
+    #[arg(long, conflicts_with_all = ["raw", "slot", "append", "safe"])]
+    resume: bool,
+
+    /// Resume an interrupted write of the same file from the given page of its content, counted from 0, leaving the
+    /// earlier pages as they are in EEPROM (with --page-write-mode single, pages are single bytes). Unlike --resume, the
+    /// part already written is not checked.
+    #[arg(long, conflicts_with_all = ["raw", "slot", "append", "safe", "resume"])]
+    start_page: Option<usize>,
+
+    /// Refuse to overwrite a valid, non-empty file already stored in EEPROM (or in the slot written), unless --force
+    /// is given.
+    #[arg(long, conflicts_with = "append")]
+    safe: bool,
+
+    /// Write even if the EEPROM is locked, or holds a valid file with --safe. A locked EEPROM stays locked afterwards.
+    #[arg(long)]
+    force: bool,
+
+    /// Read the file back once written, and fail unless its metadata and content match the source.
+    #[arg(long)]
+    verify_after: bool,
+
+    /// Once written, read back the metadata block, the first and last pages of content and a few pages picked at
+    /// random, and fail unless they match what was written. Much faster than --verify-after on slow adapters.
+    #[arg(long, conflicts_with_all = ["slot", "append", "ab"])]
+    quick_verify: bool,
+
+    /// Seed picking the pages checked by --quick-verify, as printed by an earlier run, to check the same pages again.
+    #[arg(long, requires = "quick_verify", value_name = "SEED")]
+    quick_verify_seed: Option<u64>,
+
+    /// Store a hint of the kind of content of the file in the metadata, shown by `info` (requires v2 metadata).
+    #[arg(long, value_enum, conflicts_with_all = ["slot", "append", "ab", "raw"])]
+    content_type: Option<ContentType>,
+
+    /// Store a hint of the kind of content of the file in the metadata like --content-type, inferred from its first
+    /// bytes.
+    #[arg(long, conflicts_with_all = ["content_type", "slot", "append", "ab", "raw"])]
+    sniff_content_type: bool,
+
+    /// Only write the file if the EEPROM does not already hold it, as told by its size and CRC (and the options
+    /// stored along with it) in the metadata, so that an unchanged file costs a single read.
+    #[arg(long, conflicts_with_all = ["raw", "slot", "append", "ab", "resume", "start_page"])]
+    if_changed: bool,
+
+    /// With --if-changed, also compare the file with the content in EEPROM byte by byte, as different files may have
+    /// the same CRC.
+    #[arg(long, requires = "if_changed")]
+    deep: bool,
+
+    /// With --if-changed, exit with code 3 if the file was written, and 0 if the EEPROM already held it.
+    #[arg(long, requires = "if_changed")]
+    report_changed: bool,
+
+    /// Print the outcome of the write, including that of --verify-after, as JSON.
+    #[arg(long)]
+    json: bool,
+
+    /// Write this string (as UTF-8) as the content instead of a file, e.g. a serial number.
+    #[arg(long, conflicts_with_all = ["sources", "hex"])]
+    data: Option<String>,
+
+    /// Write these bytes (given as hex) as the content instead of a file, e.g. a MAC address.
+    #[arg(long, conflicts_with = "sources")]
+    hex: Option<HexBytes>,
+
+    /// Path in the filesystem to read the file from. Several paths can be given, e.g. a header and its data, to
+    /// write them concatenated in order as a single file, covered by a single CRC.
+    #[arg(required_unless_present_any = ["data", "hex"])]
+    sources: Vec<PathBuf>
 }
 
-fn open_device() -> LinuxI2CDevice {
-    const DEVICE_PATH: &str = "/dev/i2c-3";
-    const EEPROM_ADDRESS: u16 = 0x50;
+/// Check the integrity of the file stored in EEPROM.
+#[derive(Args)]
+struct VerifyCommand {
+    /// Verify the file stored in the given slot (defaults to slot 0).
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..SLOT_COUNT as i64))]
+    slot: Option<u8>,
 
-    match LinuxI2CDevice::new(DEVICE_PATH, EEPROM_ADDRESS) {
-        Ok(device) => device,
-        Err(error) => {
-            eprintln!("Failed to open device: {error}");
-            abort()
-        },
+    /// Fail unless the file has this payload version tag. Files without a tag have an empty one.
+    #[arg(long)]
+    require_payload_version: Option<String>,
+}
+
+/// Erase the whole EEPROM to 0xFF, after which it reads as blank, or only the file in a slot.
+#[derive(Args)]
+struct EraseCommand {
+    /// Only erase the file stored in the given slot, leaving the other slots untouched. An EEPROM without slots only
+    /// has slot 0, erased with the whole EEPROM.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..SLOT_COUNT as i64))]
+    slot: Option<u8>,
+
+    /// Erase even if the EEPROM is locked.
+    #[arg(long)]
+    force: bool,
+}
+
+/// List the slots of the EEPROM.
+#[derive(Args)]
+struct LsCommand {}
+
+/// Print the exit codes of the tool and what they mean, without accessing the EEPROM.
+#[derive(Args)]
+struct CodesCommand {}
+
+/// Lock the EEPROM, making writes fail unless forced (requires v2 metadata).
+///
+/// This is an advisory software protection against writing to the wrong module, on top of any hardware write
+/// protection. Reads are not affected.
+#[derive(Args)]
+struct LockCommand {}
+
+/// Unlock the EEPROM, allowing writes again.
+#[derive(Args)]
+struct UnlockCommand {}
+
+/// List the metadata of the files previously stored in EEPROM, from the newest to the oldest (requires the history
+/// to have been enabled with `write --history`).
+#[derive(Args)]
+struct HistoryCommand {}
+
+/// Check that reading, writing and verifying work on this device and bus, by writing the stored data back unchanged.
+///
+/// The pages holding the metadata and the file(s) are read, written back and read again. If writing or verifying
+/// fails, the data read first is written back again, so that a failed self-test leaves the EEPROM as it was found.
+#[derive(Args)]
+struct SelfTestCommand {}
+
+/// Measure the read throughput of the EEPROM with the current bus, read chunk and timing settings, and with
+/// --destructive its write throughput, to compare buses and tune the write delay with data rather than guesswork.
+///
+/// The write benchmark writes a test pattern over the bytes benchmarked, checks it reads back, then writes the data
+/// read first back. It is destructive in that a failure or power loss in between leaves the pattern in EEPROM.
+#[derive(Args)]
+struct BenchmarkCommand {
+    /// Number of bytes to transfer, from the start of the EEPROM, rounded up to a multiple of 32.
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..=EEPROM_SIZE as i64), default_value_t = EEPROM_SIZE)]
+    size: u16,
+
+    /// Number of times to read them, to average out the noise.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = 3)]
+    passes: u32,
+
+    /// Also benchmark writes, overwriting the bytes benchmarked for a while.
+    #[arg(long)]
+    destructive: bool,
+}
+
+/// Print the EUI-48 (MAC address) or EUI-64 programmed at the factory into a reserved region of EEPROM+EUI parts such
+/// as the 24AA02E48, regardless of the file stored.
+#[derive(Args)]
+struct EuiCommand {
+    /// Address of the EUI in EEPROM, in decimal or in hex (prefixed with `0x`). Defaults to 0xFA for an EUI-48 and
+    /// 0xF8 for an EUI-64, as on the 24AA02E48 and 24AA025E64.
+    #[arg(long, value_parser = parse_memory_address)]
+    offset: Option<u16>,
+
+    /// Read an EUI-64 (8 bytes) rather than an EUI-48 (6 bytes).
+    #[arg(long)]
+    eui64: bool,
+
+    /// Address the EEPROM with a single byte, as 2 Kbit parts such as the 24AA02E48 expect, rather than with two.
+    #[arg(long)]
+    short_address: bool,
+}
+
+/// Find which CRC-16 algorithms of the CRC catalogue give the content CRC stored in the metadata, e.g. for an
+/// EEPROM written by another tool.
+#[derive(Args)]
+struct DetectCrcCommand {
+    /// Check the file stored in the given slot (defaults to slot 0).
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..SLOT_COUNT as i64))]
+    slot: Option<u8>,
+}
+
+/// Manage small key-value records, stored as the file content.
+#[derive(Args)]
+struct KvCommand {
+    #[command(subcommand)]
+    action: KvAction,
+}
+
+/// Get or set the unused bytes of the metadata as user data.
+///
+/// These are the bytes not used by any field: 28 bytes with v1 metadata, fewer with v2. Writing a file in the
+/// same metadata format keeps them.
+#[derive(Args)]
+struct UserdataCommand {
+    #[command(subcommand)]
+    action: UserdataAction,
+}
+
+#[derive(Subcommand)]
+enum UserdataAction {
+    /// Print the user data as hex.
+    Get,
+    /// Replace the user data, zero-padded, leaving the rest of the metadata as it is.
+    Set {
+        /// User data, as hex bytes.
+        data: HexBytes,
+        /// Overwrite the user data even if it is not all zero.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Get or set the serial number of the module, stored in the (v2) metadata.
+#[derive(Args)]
+struct SerialCommand {
+    #[command(subcommand)]
+    action: SerialAction,
+}
+
+#[derive(Subcommand)]
+enum SerialAction {
+    /// Print the serial number.
+    Get,
+    /// Store the serial number, leaving the rest of the metadata as it is.
+    Set {
+        /// Serial number, up to 12 printable ASCII characters.
+        serial: String,
+        /// Overwrite a different serial number already stored.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum KvAction {
+    /// Set the value of a key, adding the key if needed.
+    Set {
+        /// Short identifier made of ASCII letters, digits, '_', '-' and '.'.
+        key: String,
+        /// Value to store, as a string unless --hex is given.
+        value: String,
+        /// Parse the value as hex bytes instead of a string.
+        #[arg(long)]
+        hex: bool,
+    },
+    /// Print the value of a key.
+    Get {
+        key: String,
+        /// Print the value as hex bytes instead of a string.
+        #[arg(long)]
+        hex: bool,
+    },
+    /// List all keys and their values.
+    List,
+    /// Remove a key.
+    Delete {
+        key: String,
+    },
+}
+
+/// Print information about the file stored in EEPROM.
+#[derive(Args)]
+struct InfoCommand {
+    /// Print the information as JSON, same as `--format json`.
+    #[arg(long, conflicts_with = "format")]
+    json: bool,
+
+    /// Format of the information printed.
+    #[arg(long, value_enum)]
+    format: Option<InfoFormat>,
+
+    /// Compute the CRC of the metadata followed by the file, and fail if it does not match the one stored with
+    /// `write --full-crc`, if any.
+    #[arg(long)]
+    full_crc: bool,
+}
+
+/// Bytes given on the command line as a hex string.
+#[derive(Clone)]
+struct HexBytes(Vec<u8>);
+
+impl std::str::FromStr for HexBytes {
+    type Err = String;
+
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        parse_hex(hex).filter(|bytes| !bytes.is_empty()).map(HexBytes).ok_or(format!("invalid hex bytes '{hex}'"))
     }
 }
 
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum DigestAlgorithm {
+    Sha256,
+}
 
-fn main() {
-    match Command::parse().subcommand {
-        Sub::Read(read) => {
-            let mut device = open_device();
-            let mut metadata_buffer = vec![0; std::mem::size_of::<Metadata>()];
-
-            if let Err(error) = device.transfer(&mut [
-                I2CMessage::write(&METADATA_OFFSET.to_be_bytes()),
-                I2CMessage::read(metadata_buffer.as_mut_slice()),
-            ]) {
-                eprintln!("Failed to read file metadata from EEPROM: {error}.");
-                abort()
-            }
-            
-            std::thread::sleep(Duration::from_millis(10));
-
-            let Ok(metadata) = bincode::deserialize::<Metadata>(metadata_buffer.as_slice()) else {
-                eprintln!("Invalid file metadata in EEPROM.");
-                abort()
-            };
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum InfoFormat {
+    /// Human-readable text.
+    Text,
+    /// JSON object.
+    Json,
+    /// Metrics in the Prometheus text format, e.g. for the node exporter textfile collector.
+    Prometheus,
+}
 
-            if metadata.content_size > MAX_CONTENT_SIZE {
-                eprintln!("Invalid file size in EEPROM: exceeds maximum possible ({} > {}).", metadata.content_size, MAX_CONTENT_SIZE);
-                abort()
-            }
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum PageWriteMode {
+    /// Write a page (or part of one) per transaction.
+    Sequential,
+    /// Write a single byte per transaction.
+    Single,
+}
 
-            if !read.allow_empty && metadata.content_size == 0 {
-                eprintln!("File in EEPROM is empty or does not exists.");
-                abort()
-            }
+/// Format `content` as lines of 16 bytes, each annotated with its offset and followed by its ASCII
+/// representation (non-printable bytes are shown as `.`).
+fn hexdump(content: &[u8]) -> String {
+    let mut output = String::new();
 
-            let mut content_buffer = vec![0; metadata.content_size as usize];
+    for (index, line) in content.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|byte| format!("{byte:02x}")).collect();
+        let ascii: String = line.iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
 
-            if let Err(error) = device.transfer(&mut [
-                I2CMessage::write(&CONTENT_OFFSET.to_be_bytes()),
-                I2CMessage::read(content_buffer.as_mut_slice()),
-            ]) {
-                eprintln!("Failed to read file contents from EEPROM: {error}.");
-                abort()
-            }
+        output.push_str(&format!("{:08x}  {:<47}  |{ascii}|\n", index * 16, hex.join(" ")));
+    }
 
-            if !read.ignore_crc {
-                let crc = CRC.checksum(&content_buffer.as_slice());
-    
-                if crc != metadata.content_crc {
-                    eprintln!("File does not exist or is corrupted: CRC of file content does not match CRC in its metadata.");
-                    abort()
-                }
-            }
+    output
+}
 
-            if let Err(error) = std::fs::write(read.destination.as_path(), content_buffer.as_slice()) {
-                eprintln!("Failed to write to file '{:?}': {error}", read.destination);
-                abort()
-            }
-        }
-        Sub::Write(write) => {
-            let mut device = open_device();
-            let mut content_buffer = Vec::default();
-            let mut metadata_buffer = Vec::from(METADATA_OFFSET.to_be_bytes());
+/// Parse a byte given either in decimal or in hex (prefixed with `0x`).
+fn parse_byte(value: &str) -> Result<u8, String> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    }.map_err(|error| error.to_string())
+}
 
-            let file_size = match File::open(write.source.as_path()).and_then(|mut f| f.read_to_end(&mut content_buffer)) {
-                Ok(file_size) => file_size,
-                Err(error) => {
-                    eprintln!("Failed to read from file '{:?}': {error}", write.source);
-                    abort()
-                }
-            };
+/// Parse a CRC given in hex, optionally prefixed with `0x`.
+fn parse_crc(value: &str) -> Result<u16, String> {
+    u16::from_str_radix(value.strip_prefix("0x").unwrap_or(value), 16).map_err(|error| error.to_string())
+}
 
-            if file_size > MAX_CONTENT_SIZE as usize {
-                eprintln!("File '{:?}' is too large. Max allowable size is {MAX_CONTENT_SIZE} bytes.", write.source);
-                abort()
-            }
+/// Parse a 7-bit I2C address given either in decimal or in hex (prefixed with `0x`), excluding the reserved ones.
+fn parse_address(value: &str) -> Result<u16, String> {
+    match parse_byte(value)? {
+        address @ 0x08..=0x77 => Ok(address as u16),
+        _ => Err("must be a 7-bit address between 0x08 and 0x77".to_string()),
+    }
+}
 
-            let metadata = Metadata {
-                unused: Default::default(),
-                content_crc: CRC.checksum(content_buffer.as_slice()),
-                content_size: file_size as u16,
-            };
+/// Parse an address in EEPROM given either in decimal or in hex (prefixed with `0x`).
+fn parse_memory_address(value: &str) -> Result<u16, String> {
+    let address = match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    let address = address.map_err(|error| error.to_string())?;
 
-            // Unwrap should always succeed.
-            bincode::serialize_into(&mut metadata_buffer, &metadata).unwrap();
+    match address < EEPROM_SIZE {
+        true => Ok(address),
+        false => Err(format!("must be below the EEPROM size ({EEPROM_SIZE})")),
+    }
+}
 
-            // Sanity check that the serialized size is the same as the struct size.
-            if metadata_buffer.len() - 2 != std::mem::size_of::<Metadata>() {
-                eprintln!("Internal error: unexpected metadata size.");
-                abort()
-            }
+/// Parse a number of reads to take the majority of, which must be odd so that there are no ties.
+fn parse_votes(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>().map_err(|error| error.to_string())? {
+        votes if votes % 2 == 1 => Ok(votes),
+        _ => Err("must be an odd number".to_string()),
+    }
+}
 
-            // Write file metadata.
-            if let Err(error) = device.write(metadata_buffer.as_slice()) {
-                eprintln!("Failed to write file metadata into EEPROM: {error}.");
-                abort()
-            }
+/// Parse a payload version tag.
+fn parse_payload_version(value: &str) -> Result<String, String> {
+    if value.len() > metadata::PAYLOAD_VERSION_SIZE || !value.bytes().all(|byte| byte.is_ascii_graphic()) {
+        return Err(format!("must be up to {} printable ASCII characters", metadata::PAYLOAD_VERSION_SIZE));
+    }
 
-            std::thread::sleep(Duration::from_millis(10));
+    Ok(value.to_string())
+}
 
-            // Write file content.
-            let mut buffer = vec![0_u8; 34];
+/// Metrics of `info` in the Prometheus text format, each labelled with `labels` (e.g. the bus and address of the
+/// EEPROM) to tell the EEPROMs of a machine apart.
+fn prometheus_metrics(labels: &[(&str, &str)], metadata: &FileInfo, crc_valid: bool, bytes_free: usize) -> String {
+    let format = match metadata.format {
+        Format::V1 => "v1",
+        Format::V2 => "v2",
+        Format::V3 => "v3",
+    };
+    let info_labels = [("format", format), ("serial", metadata.serial.as_str()), ("payload_version", metadata.payload_version.as_str())];
+    let metrics = [
+        ("info", "Metadata of the file stored in EEPROM, in labels.", &info_labels[..], 1),
+        ("content_size_bytes", "Size of the file stored in EEPROM.", &[], metadata.content_size as usize),
+        ("crc_valid", "Whether the file stored in EEPROM matches the CRC (and digest) in its metadata.", &[], crc_valid as usize),
+        ("bytes_free", "Bytes left in EEPROM for a larger file.", &[], bytes_free),
+    ];
+    let mut output = String::new();
 
-            for (index, chunk) in content_buffer.chunks(32).enumerate() {
-                let offset = CONTENT_OFFSET + 32 * (index as u16);
-                let size = 2 + chunk.len();
+    for (name, help, extra_labels, value) in metrics {
+        let labels: Vec<String> = labels.iter().chain(extra_labels)
+            .map(|(label, value)| format!("{label}=\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+            .collect();
 
-                buffer[0..2].copy_from_slice(&offset.to_be_bytes());
-                buffer[2..size].copy_from_slice(chunk);
+        output += &format!("# HELP vki2cfile_{name} {help}\n# TYPE vki2cfile_{name} gauge\nvki2cfile_{name}{{{}}} {value}\n", labels.join(","));
+    }
 
-                // Always copy 32 bytes even if the actual payload size is smaller.
-                // This helps circumvent some bugs with the device itself. These additional bytes don't matter
-                // since we are never going to read them.
-                if let Err(error) = device.write(&buffer) {
-                    eprintln!("Failed to write file into EEPROM: {error}.");
-                    abort()
-                }
+    output
+}
 
-                std::thread::sleep(Duration::from_millis(10));
-            }
+/// Parse a hex string (optionally prefixed with `0x`) into bytes.
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok()).collect()
+}
+
+/// Lock on the I2C bus, held until the process exits.
+static BUS_LOCK: OnceLock<File> = OnceLock::new();
+
+/// Default path to the I2C bus the EEPROM is on.
+const DEFAULT_DEVICE_PATH: &str = "/dev/i2c-3";
+
+/// Mux to disconnect from all its channels before exiting, from `--mux-clear`, along with the options to access it.
+static MUX_TO_CLEAR: OnceLock<Mutex<(PlatformDevice, Options)>> = OnceLock::new();
+
+/// How to reach the EEPROM, from the global options.
+struct Bus {
+    device_path: String,
+    /// Write cycle of the simulated EEPROM at `device_path`, if it is simulated rather than an I2C bus.
+    simulated: Option<Duration>,
+    address: u16,
+    no_wait: bool,
+    /// Address of the mux the EEPROM is behind and channel to select on it.
+    mux: Option<(u16, u8)>,
+    /// Disconnect all channels of the mux before exiting.
+    mux_clear: bool,
+    /// Bound on the time a single transfer takes, if any.
+    io_timeout: Option<Duration>,
+}
+
+impl Bus {
+    /// Description of the EEPROM in errors, see `Eeprom::with_target`.
+    fn target(&self) -> String {
+        format!("address 0x{:02x} on {}", self.address, self.device_path)
+    }
+}
+
+/// Lock the bus, select the channel of the mux if any, and open the EEPROM, to be accessed with `options`.
+fn open_device(bus: &Bus, mut options: Options) -> Result<Eeprom<PlatformDevice>> {
+    let device_path = bus.device_path.as_str();
+    let address = bus.address;
+    let lock_path = lock::lock_path(device_path);
+    let target = bus.target();
+    let open = |address| match bus.simulated {
+        Some(write_cycle) => device::simulate(device_path, write_cycle),
+        None => device::open(device_path, address),
+    };
+
+    match lock::acquire(&lock_path, bus.no_wait) {
+        Ok(lock) => {
+            let _ = BUS_LOCK.set(lock);
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+            return Err(Error::Busy { target, lock_path: lock_path.display().to_string() });
+        }
+        Err(source) => {
+            return Err(Error::Lock { target, lock_path: lock_path.display().to_string(), source });
+        }
+    }
+
+    if let Some(timeout) = bus.io_timeout {
+        watchdog::start(timeout, device_path.to_string(), ExitCode::BusStuck as i32);
+    }
+
+    if let Some((mux_address, channel)) = bus.mux {
+        let mux_target = format!("address 0x{mux_address:02x} on {device_path}");
+        let mux_error = |channel, error| {
+            let errno = PlatformDevice::errno(&error);
+
+            Error::Mux { target: target.clone(), mux: mux_target.clone(), channel, source: i2c_error::explained(error, errno, &mux_target) }
+        };
+        let mut mux = open(mux_address).map_err(|error| mux_error(None, error))?;
+
+        with_retries(&mut mux, &options, &mux_target, &mut 0, |mux| mux::select(mux, Some(channel)))
+            .map_err(|error| mux_error(Some(channel), error))?;
+
+        if bus.mux_clear {
+            let _ = MUX_TO_CLEAR.set(Mutex::new((mux, options.clone())));
+        }
+    }
+
+    let device = open(address).map_err(|error| {
+        let errno = PlatformDevice::errno(&error);
+        let (path, source) = (device_path.to_string(), i2c_error::explained(error, errno, &target));
+
+        match errno {
+            Some(libc::ENOENT | libc::ENODEV) => Error::DeviceNotFound { path, address, source },
+            _ => Error::DeviceOpen { path, address, source },
+        }
+    })?;
+
+    if let Some(timeout) = bus.io_timeout {
+        if !device.set_timeout(timeout) {
+            log::info!("Adapter does not support setting its timeout, only the watchdog bounds the time a transfer takes.");
         }
     }
-}
\ No newline at end of file
+
+    if let Some(page_size) = fitted_page_size(&device, options.geometry.page_size) {
+        log::info!("Adapter lacks plain I2C transfers, using SMBus transactions: writing at most {page_size} bytes at a time and reading byte by byte.");
+
+        options.geometry.page_size = page_size;
+    }
+
+    Ok(Eeprom::new(device, options).with_target(target))
+}
+
+/// `page_size` shrunk so that page writes fit in a single transaction of `device`, e.g. an SMBus block write on an
+/// adapter without plain I2C transfers, or `None` if its writes are not limited.
+fn fitted_page_size(device: &impl Device, page_size: u16) -> Option<u16> {
+    // The 2 address bytes come on top of the data.
+    device.max_write_size().map(|max_write_size| pages::fit_page_size(page_size, max_write_size - 2))
+}
+
+/// Disconnect all channels of the mux if `--mux-clear` was given.
+fn clear_mux() {
+    let Some(mux) = MUX_TO_CLEAR.get() else {
+        return;
+    };
+
+    let mut mux = mux.lock().unwrap_or_else(|error| error.into_inner());
+    let (mux, options) = &mut *mux;
+
+    if let Err(error) = with_retries(mux, options, "the mux", &mut 0, |mux| mux::select(mux, None)) {
+        eprintln!("Failed to clear mux: {}", describe_error::<PlatformDevice>(&error, "the mux"));
+    }
+}
+
+
+/// Default bound on the time a single transfer takes, much longer than any transfer of a healthy bus.
+const DEFAULT_IO_TIMEOUT_MS: u64 = 2000;
+
+/// Default size of the smallest transfer showing its progress, about a quarter of the MK24C64 or a second of writing.
+const DEFAULT_PROGRESS_THRESHOLD: u64 = 2048;
+
+/// Choose how to wait for write cycles: the fixed `write_delay` if given, adaptive timing within `adaptive_delay`
+/// (the minimum and maximum delays) if given, ACK polling otherwise, falling back to `DEFAULT_WRITE_DELAY` if the
+/// adapter does not support it. If `require_polling` is set, polling is always used, `write_delay` being the minimum
+/// polling timeout. Invalid combinations are reported as a usage error of the EEPROM `target`.
+fn select_write_cycle(target: &str, polling_supported: bool, write_delay: Option<u64>, adaptive_delay: Option<(u64, u64)>, require_polling: bool) -> Result<WriteCycle> {
+    let write_delay = write_delay.map(Duration::from_millis);
+
+    if let Some((min_delay, max_delay)) = adaptive_delay {
+        if require_polling {
+            return Err(Error::Usage { target: target.to_string(), reason: "fast mode requires ACK polling, which cannot be combined with adaptive timing".to_string() });
+        }
+
+        if min_delay > max_delay {
+            return Err(Error::Usage { target: target.to_string(), reason: format!("invalid adaptive timing, minimum delay ({min_delay} ms) exceeds maximum delay ({max_delay} ms)") });
+        }
+
+        return Ok(WriteCycle::Adaptive { min: Duration::from_millis(min_delay), max: Duration::from_millis(max_delay) });
+    }
+
+    if let (Some(write_delay), false) = (write_delay, require_polling) {
+        return Ok(WriteCycle::Delay(write_delay));
+    }
+
+    if polling_supported {
+        return Ok(WriteCycle::Poll(write_delay.map_or(polling::POLL_TIMEOUT, |write_delay| write_delay.max(polling::POLL_TIMEOUT))));
+    }
+
+    if require_polling {
+        return Err(Error::Usage { target: target.to_string(), reason: "fast mode requires ACK polling, which is not supported by the I2C adapter".to_string() });
+    }
+
+    log::info!("ACK polling is not supported by the I2C adapter, waiting {DEFAULT_WRITE_DELAY:?} after each write instead.");
+
+    Ok(WriteCycle::Delay(DEFAULT_WRITE_DELAY))
+}
+
+/// Report the outcome of a write of a file of `content_size` bytes with CRC `content_crc`, leaving `bytes_free` bytes
+/// of free space, along with the outcome of `--verify-after`, if given. A failed verification is only printed here as
+/// JSON, as it is otherwise reported like any other error.
+fn report_write(eeprom: &Eeprom<impl Device>, verification: Option<&Result<()>>, json: bool, content_size: u16, content_crc: u16, bytes_free: usize) {
+    if json {
+        let verification = match verification {
+            Some(Ok(())) => "\"verified\":true,".to_string(),
+            Some(Err(error)) => format!("\"verified\":false,\"error\":{},", json::string(&error.to_string())),
+            None => String::new(),
+        };
+
+        println!("{{{verification}\"content_size\":{content_size},\"content_crc\":{content_crc},\"bytes_free\":{bytes_free}{}}}", stats_json(eeprom));
+        return;
+    }
+
+    match verification {
+        Some(Err(_)) => return,
+        Some(Ok(())) if !eeprom.options().announce => {}
+        Some(Ok(())) => println!("Verified file in EEPROM ({content_size} bytes, CRC 0x{content_crc:04x})."),
+        None => {}
+    }
+
+    if eeprom.options().announce {
+        println!("{bytes_free} bytes free.");
+    }
+}
+
+/// The transfer statistics so far as a JSON field to add to an object, if `--stats` was given.
+fn stats_json(eeprom: &Eeprom<impl Device>) -> String {
+    match stats::enabled() {
+        true => format!(",\"stats\":{}", stats::json(eeprom.retried_transfers(), eeprom.corrected_bytes(), eeprom.stall_time())),
+        false => String::new(),
+    }
+}
+
+/// Write `content`, read out of the EEPROM `target`, into the file at `destination` (or to stdout for `-`), refusing to
+/// overwrite an existing file unless `read` has `--force` or `--backup`. If writing fails otherwise, the content is not
+/// thrown away: it is written to stdout with `--stdout-on-fail`, or saved to a temporary file, and the error reports
+/// where it went.
+fn save_content(target: &str, destination: &Path, content: &[u8], read: &ReadCommand) -> Result<()> {
+    if destination == Path::new("-") {
+        let mut stdout = std::io::stdout().lock();
+
+        return stdout.write_all(content).and_then(|()| stdout.flush())
+            .map_err(|source| Error::Destination { target: target.to_string(), action: "write to stdout".to_string(), recovery: None, source });
+    }
+
+    if read.backup {
+        back_up(target, destination)?;
+    }
+
+    if read.patch {
+        return match patch_file(destination, content) {
+            Ok(patched) => {
+                log::info!("Patched {patched} of {} bytes in '{destination:?}'.", content.len());
+
+                Ok(())
+            }
+            Err(error) => recover_content(target, format!("patch file '{}'", destination.display()), error, content, read),
+        };
+    }
+
+    // Creating the file only if it does not exist yet, rather than checking first, leaves no window for another
+    // process to create it in between.
+    let result = match read.force {
+        true => File::create(destination),
+        false => File::options().write(true).create_new(true).open(destination),
+    };
+
+    match result.and_then(|mut file| file.write_all(content)) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => Err(destination_exists(target, destination)),
+        Err(error) => recover_content(target, format!("write to file '{}'", destination.display()), error, content, read),
+    }
+}
+
+/// Make the file at `destination` hold `content` by only writing the bytes that differ from what it holds, creating it
+/// if needed, for `--patch`. Returns the number of bytes written.
+fn patch_file(destination: &Path, content: &[u8]) -> std::io::Result<usize> {
+    let mut file = File::options().read(true).write(true).create(true).truncate(false).open(destination)?;
+    let mut existing = Vec::new();
+
+    file.read_to_end(&mut existing)?;
+
+    let ranges = differing_ranges(existing.as_slice(), content);
+
+    for range in &ranges {
+        file.seek(std::io::SeekFrom::Start(range.start as u64))?;
+        file.write_all(&content[range.clone()])?;
+    }
+
+    // Device nodes have no length of their own to truncate.
+    if existing.len() > content.len() && file.metadata()?.is_file() {
+        file.set_len(content.len() as u64)?;
+    }
+
+    file.sync_all()?;
+    Ok(ranges.iter().map(|range| range.len()).sum())
+}
+
+/// Ranges of `content` that differ from `existing`, including any part past its end.
+fn differing_ranges(existing: &[u8], content: &[u8]) -> Vec<std::ops::Range<usize>> {
+    let mut ranges: Vec<std::ops::Range<usize>> = Vec::new();
+
+    for (index, byte) in content.iter().enumerate() {
+        if existing.get(index) == Some(byte) {
+            continue;
+        }
+
+        match ranges.last_mut() {
+            Some(range) if range.end == index => range.end += 1,
+            _ => ranges.push(index..index + 1),
+        }
+    }
+
+    ranges
+}
+
+fn destination_exists(target: &str, destination: &Path) -> Error {
+    Error::DestinationExists { target: target.to_string(), path: destination.display().to_string() }
+}
+
+/// Rename an existing file at `destination`, of a read of the EEPROM `target`, to `<destination>.bak` for `--backup`.
+fn back_up(target: &str, destination: &Path) -> Result<()> {
+    let mut backup = destination.as_os_str().to_owned();
+    backup.push(".bak");
+
+    match std::fs::rename(destination, &backup) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(source) => Err(Error::Destination {
+            target: target.to_string(),
+            action: format!("back up file '{}' to '{}'", destination.display(), Path::new(&backup).display()),
+            recovery: None,
+            source,
+        }),
+    }
+}
+
+/// Write `content`, read out of the EEPROM `target`, which could not be saved to its destination because doing
+/// `action` failed with `source`, to stdout with `--stdout-on-fail` or to a temporary file, and return the error saying
+/// where it went.
+fn recover_content(target: &str, action: String, source: std::io::Error, content: &[u8], read: &ReadCommand) -> Result<()> {
+    let recovery = if read.stdout_on_fail {
+        let mut stdout = std::io::stdout().lock();
+
+        match stdout.write_all(content).and_then(|()| stdout.flush()) {
+            Ok(()) => "The content read was written to stdout instead.".to_string(),
+            Err(stdout_error) => format!("Writing the content read to stdout failed too: {stdout_error}."),
+        }
+    } else {
+        let recovered = std::env::temp_dir().join(format!("vki2cfile-recovered-{}.bin", std::process::id()));
+
+        match std::fs::write(recovered.as_path(), content) {
+            Ok(()) => format!("The content read was saved to '{}' instead.", recovered.display()),
+            Err(recovered_error) => format!("Saving the content read to '{}' failed too: {recovered_error}.", recovered.display()),
+        }
+    };
+
+    Err(Error::Destination { target: target.to_string(), action, recovery: Some(recovery), source })
+}
+
+/// Destination file of a read, written as the content arrives from EEPROM into a temporary file in the same directory,
+/// which is only moved into place once the content has been checked. Dropping it before removes the temporary file,
+/// so that a failed read never leaves a corrupt file at the destination.
+struct StreamedDestination {
+    temporary: PathBuf,
+    /// File being written, or `None` once writing it failed.
+    file: Option<File>,
+    /// Bytes written so far.
+    written: usize,
+    /// What failed writing the file and how, after which the content is only kept in memory.
+    error: Option<(String, std::io::Error)>,
+}
+
+impl StreamedDestination {
+    /// Create the temporary file for `destination`, or return `None` if that fails, in which case the content is kept
+    /// in memory and written with `save_content` instead.
+    fn create(destination: &Path) -> Option<Self> {
+        let name = destination.file_name()?.to_string_lossy();
+        let temporary = destination.with_file_name(format!(".{name}.vki2cfile-{}.tmp", std::process::id()));
+        let file = File::options().write(true).create_new(true).open(temporary.as_path()).ok()?;
+
+        Some(StreamedDestination { temporary, file: Some(file), written: 0, error: None })
+    }
+
+    /// Write `chunk`, at `position` in the content, to the temporary file. A position before the end of what was
+    /// written already means the read started over, and drops the rest. A failure is reported right away with the
+    /// offset reached, and the read goes on so that the content can be saved elsewhere.
+    fn write(&mut self, position: usize, chunk: &[u8]) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+
+        let rewind = match position < self.written {
+            true => file.set_len(position as u64).and_then(|()| file.seek(std::io::SeekFrom::Start(position as u64))).map(drop),
+            false => Ok(()),
+        };
+
+        if rewind.is_ok() {
+            self.written = self.written.min(position);
+        }
+
+        match rewind.and_then(|()| file.write_all(chunk)) {
+            Ok(()) => self.written += chunk.len(),
+            Err(error) => {
+                let action = format!("write to file '{}' at byte {}", self.temporary.display(), self.written);
+
+                eprintln!("Failed to {action}: {error}. Reading on into memory.");
+                self.error = Some((action, error));
+                self.file = None;
+            }
+        }
+    }
+
+    /// Move the temporary file, holding all of `content` read out of the EEPROM `target`, into place at `destination`
+    /// as `read` says, or fall back to saving `content` elsewhere if writing it failed.
+    fn finish(mut self, target: &str, destination: &Path, content: &[u8], read: &ReadCommand) -> Result<()> {
+        let result = self.file.take().map_or(Ok(()), |file| file.sync_all());
+
+        if let Some((action, error)) = self.error.take() {
+            return recover_content(target, action, error, content, read);
+        }
+
+        if let Err(error) = result {
+            return recover_content(target, format!("write to file '{}'", self.temporary.display()), error, content, read);
+        }
+
+        if read.backup {
+            back_up(target, destination)?;
+        }
+
+        // Without --force, linking the temporary file into place fails if the destination exists, leaving no window
+        // for another process to create it in between. Renaming overwrites it, so is only a fallback for file systems
+        // without hard links.
+        let result = match read.force {
+            true => std::fs::rename(self.temporary.as_path(), destination),
+            false => match std::fs::hard_link(self.temporary.as_path(), destination) {
+                Err(error) if error.kind() != std::io::ErrorKind::AlreadyExists && !destination.exists() => {
+                    std::fs::rename(self.temporary.as_path(), destination)
+                }
+                result => result,
+            },
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => Err(destination_exists(target, destination)),
+            Err(error) => {
+                recover_content(target, format!("move file '{}' to '{}'", self.temporary.display(), destination.display()), error, content, read)
+            }
+        }
+    }
+}
+
+impl Drop for StreamedDestination {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(self.temporary.as_path());
+    }
+}
+
+/// Run `iteration` on `eeprom` `count` times for `--repeat`, reporting each failure and a summary of the successes,
+/// failures and retries, and return the result of the last successful iteration (or the last error if none
+/// succeeded). An interrupted write stops the repetition.
+fn repeat<D: Device, T>(eeprom: &mut Eeprom<D>, count: u32, mut iteration: impl FnMut(&mut Eeprom<D>) -> Result<T>) -> Result<T> {
+    let retried = eeprom.retried_transfers();
+    let mut failures = 0;
+    let mut last = None;
+
+    for index in 1..=count {
+        match iteration(eeprom) {
+            Ok(value) => last = Some(Ok(value)),
+            Err(error @ Error::Interrupted { .. }) => return Err(error),
+            Err(error) => {
+                eprintln!("Iteration {index}/{count} failed: {error}");
+                failures += 1;
+
+                if !matches!(last, Some(Ok(_))) {
+                    last = Some(Err(error));
+                }
+            }
+        }
+    }
+
+    eprintln!(
+        "Repeated {count} times: {} succeeded, {failures} failed, {} transfers retried.",
+        count - failures, eeprom.retried_transfers() - retried,
+    );
+
+    last.expect("--repeat is at least 1")
+}
+
+/// Read the file at `path` chunk by chunk after `prefix` and return the bytes read, `prefix` included: the whole file is
+/// held in memory, which `max_size`, the space for content, bounds. A regular file larger than `max_size` is rejected
+/// before reading anything, as is one whose size changes while it is read. Reading other files (e.g. pipes) stops with
+/// an error as soon as the bytes exceed `max_size`, so that a wrong path to a large file is never read whole. The file
+/// is to be written into the EEPROM `target`, named in the error if it is too large.
+fn read_source(target: &str, path: &Path, prefix: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let source_error = |source| Error::SourceFile { target: target.to_string(), path: path.display().to_string(), source };
+    let mut file = File::open(path).map_err(source_error)?;
+    let file_size = file.metadata().ok().filter(|metadata| metadata.is_file()).map(|metadata| metadata.len());
+    let too_large = |size| Error::ContentTooLarge { target: target.to_string(), file: format!("'{path:?}'"), size, max: max_size };
+
+    if let Some(file_size) = file_size.filter(|file_size| prefix.len() as u64 + file_size > max_size as u64) {
+        return Err(too_large(prefix.len() + file_size as usize));
+    }
+
+    let mut content = Vec::from(prefix);
+    let mut chunk = [0; 256];
+
+    loop {
+        let size = match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(size) => size,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(source_error(error)),
+        };
+
+        if content.len() + size > max_size {
+            return Err(too_large(content.len() + size));
+        }
+
+        content.extend(&chunk[..size]);
+    }
+
+    let read_size = (content.len() - prefix.len()) as u64;
+
+    if let Some(size) = file_size.filter(|&file_size| file_size != read_size) {
+        return Err(Error::SourceChanged { target: target.to_string(), path: path.display().to_string(), size, read: read_size });
+    }
+
+    Ok(content)
+}
+
+/// Bytes given on the command line to write as the content with `--data` or `--hex`, if any.
+fn literal_content(write: &WriteCommand) -> Option<&[u8]> {
+    match (&write.data, &write.hex) {
+        (Some(data), _) => Some(data.as_bytes()),
+        (None, Some(hex)) => Some(hex.0.as_slice()),
+        (None, None) => None,
+    }
+}
+
+/// Description of the source of the content written for messages: its path(s), quoted, or the option giving it.
+fn source_name(write: &WriteCommand) -> String {
+    match (write.sources.as_slice(), &write.data) {
+        ([], Some(_)) => "given with --data".to_string(),
+        ([], None) => "given with --hex".to_string(),
+        (sources, _) => sources.iter().map(|source| format!("'{source:?}'")).collect::<Vec<_>>().join(" + "),
+    }
+}
+
+/// How the library reads the file described by `read`.
+fn read_options(read: &ReadCommand) -> ReadOptions {
+    ReadOptions {
+        slot: read.slot,
+        ignore_crc: read.ignore_crc,
+        allow_empty: read.allow_empty,
+        strict_size: read.strict_size,
+        force_raw: read.force_raw,
+        sanity_check: read.sanity_check,
+        strict: read.strict,
+        expect_magic: read.expect_magic.as_ref().map_or_else(Vec::new, |magic| magic.0.clone()),
+        require_payload_version: read.require_payload_version.clone(),
+    }
+}
+
+/// How the library stores the file described by `write`.
+fn write_options(write: &WriteCommand) -> WriteOptions {
+    WriteOptions {
+        format: write.write_format,
+        digest: write.digest.is_some(),
+        compressed: write.compressed,
+        encrypted: write.encrypted,
+        slot: write.slot,
+        slot_offset: write.slot_offset,
+        slot_type: write.slot_type,
+        magic: write.magic.as_ref().map_or_else(Vec::new, |magic| magic.0.clone()),
+        pad_to: write.pad_to,
+        pad_byte: write.pad_byte,
+        payload_version: write.payload_version.clone().unwrap_or_default(),
+        history: write.history,
+        append: write.append,
+        ab: write.ab,
+        full_crc: write.full_crc,
+        crc: write.crc_value.or(write.no_crc.then_some(0)),
+        diff_write: write.diff_write,
+        resume: write.resume,
+        start_page: write.start_page,
+        safe: write.safe,
+        force: write.force,
+        content_type: write.content_type,
+        sniff_content_type: write.sniff_content_type,
+        deep: write.deep,
+        source: source_name(write),
+    }
+}
+
+/// Read the source files of `write` one after the other after `prefix`, concatenating them, and return the bytes, see
+/// `read_source`. The byte range taken by each file is logged.
+fn read_sources(target: &str, write: &WriteCommand, prefix: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let [path] = write.sources.as_slice() else {
+        // Check the combined size first, so that no file is read if they are too large together.
+        let total_size = write.sources.iter()
+            .filter_map(|path| std::fs::metadata(path).ok().filter(|metadata| metadata.is_file()))
+            .map(|metadata| metadata.len())
+            .sum::<u64>();
+
+        if prefix.len() as u64 + total_size > max_size as u64 {
+            return Err(Error::ContentTooLarge { target: target.to_string(), file: source_name(write), size: prefix.len() + total_size as usize, max: max_size });
+        }
+
+        let mut content = Vec::from(prefix);
+        let mut ranges = Vec::new();
+
+        for path in &write.sources {
+            let start = content.len();
+
+            content = read_source(target, path, content.as_slice(), max_size)?;
+            ranges.push((path, start..content.len()));
+        }
+
+        log::info!("Concatenated {} files into {} bytes of content:", ranges.len(), content.len());
+
+        for (path, range) in ranges {
+            log::info!("  {path:?}: bytes {}..{} ({} bytes)", range.start, range.end, range.len());
+        }
+
+        return Ok(content);
+    };
+
+    read_source(target, path, prefix, max_size)
+}
+
+/// Read the content written by `write` after `prefix`, from its source file(s) or from the command line, and return
+/// it, see `read_sources`.
+fn read_content_source(target: &str, write: &WriteCommand, prefix: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    // Clap requires a source file unless the content is given on the command line.
+    let Some(literal) = literal_content(write) else {
+        return read_sources(target, write, prefix, max_size);
+    };
+
+    if prefix.len() + literal.len() > max_size {
+        return Err(Error::ContentTooLarge { target: target.to_string(), file: source_name(write), size: prefix.len() + literal.len(), max: max_size });
+    }
+
+    Ok([prefix, literal].concat())
+}
+
+/// Run a user data subcommand. Setting the user data re-emits the whole metadata block as read, so that the
+/// other fields stay exactly as they were.
+fn run_userdata(eeprom: &mut Eeprom<impl Device>, action: UserdataAction) -> Result<()> {
+    let metadata = eeprom.read_metadata()?;
+
+    match action {
+        UserdataAction::Get => println!("{}", to_hex(&metadata.reserved)),
+        UserdataAction::Set { data, force } => {
+            let capacity = metadata.format.reserved_range().len();
+
+            if data.0.len() > capacity {
+                return Err(Error::DataTooLarge { target: eeprom.target().to_string(), what: "user data".to_string(), size: data.0.len(), max: capacity });
+            }
+
+            if metadata.format == Format::V1 && data.0.starts_with(&metadata::MAGIC) {
+                return Err(Error::InvalidRequest {
+                    target: eeprom.target().to_string(),
+                    reason: format!("user data of v1 metadata must not start with {}, which would be mistaken for v2 metadata", to_hex(&metadata::MAGIC)),
+                });
+            }
+
+            if !force && metadata.reserved.iter().any(|&byte| byte != 0) {
+                return Err(Error::AlreadyWritten { target: eeprom.target().to_string(), what: format!("user data {}", to_hex(&metadata.reserved)) });
+            }
+
+            eeprom.update_metadata(&FileInfo { reserved: data.0, ..metadata })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a serial number subcommand.
+fn run_serial(eeprom: &mut Eeprom<impl Device>, action: SerialAction) -> Result<()> {
+    let metadata = eeprom.read_metadata()?;
+
+    match action {
+        SerialAction::Get => {
+            if metadata.serial.is_empty() {
+                return Err(Error::NotFound { target: eeprom.target().to_string(), what: "serial number".to_string() });
+            }
+
+            println!("{}", metadata.serial);
+        }
+        SerialAction::Set { serial, force } => {
+            if serial.is_empty() || serial.len() > metadata::SERIAL_SIZE || !serial.bytes().all(|byte| byte.is_ascii_graphic()) {
+                return Err(Error::InvalidRequest {
+                    target: eeprom.target().to_string(),
+                    reason: format!("invalid serial number '{serial}', it must be 1 to {} printable ASCII characters", metadata::SERIAL_SIZE),
+                });
+            }
+
+            if !metadata.format.is_v2_or_later() {
+                return Err(Error::InvalidRequest {
+                    target: eeprom.target().to_string(),
+                    reason: "storing a serial number requires the v2 metadata format, rewrite the file with it first".to_string(),
+                });
+            }
+
+            if metadata.serial == serial {
+                return Ok(());
+            }
+
+            if !force && !metadata.serial.is_empty() {
+                return Err(Error::AlreadyWritten { target: eeprom.target().to_string(), what: format!("serial number '{}'", metadata.serial) });
+            }
+
+            eeprom.update_metadata(&FileInfo { serial, ..metadata })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a key-value subcommand, rewriting the file content (and only the pages of it that changed) on mutation.
+fn run_kv(eeprom: &mut Eeprom<impl Device>, action: KvAction) -> Result<()> {
+    let metadata = eeprom.read_metadata_or_empty()?;
+
+    if metadata.flags & !(MODULE_FLAGS | CONTENT_TYPE_MASK) != 0 {
+        return Err(Error::NotPlainFile { target: eeprom.target().to_string(), flags: metadata.flags, action: "hold key-value records" });
+    }
+
+    let content = eeprom.read_content_at(eeprom.options().geometry.content_offset, &metadata)?;
+
+    if content.crc != metadata.content_crc {
+        return Err(crc_mismatch(&metadata, &content, eeprom.target()));
+    }
+
+    let mut entries = tlv::parse(content.bytes.as_slice())
+        .map_err(|error| Error::InvalidRequest { target: eeprom.target().to_string(), reason: format!("its file is not a valid key-value container: {error}") })?;
+
+    match action {
+        KvAction::Get { key, hex } => {
+            let Some(entry) = entries.iter().find(|entry| entry.key == key) else {
+                return Err(Error::NotFound { target: eeprom.target().to_string(), what: format!("key '{key}'") });
+            };
+
+            match (hex, std::str::from_utf8(&entry.value)) {
+                (false, Ok(value)) => println!("{value}"),
+                (false, Err(_)) => {
+                    return Err(Error::InvalidRequest {
+                        target: eeprom.target().to_string(),
+                        reason: format!("value of key '{key}' is not a valid string, pass --hex to print it as hex"),
+                    });
+                }
+                (true, _) => println!("{}", to_hex(&entry.value)),
+            }
+
+            return Ok(());
+        }
+        KvAction::List => {
+            for entry in &entries {
+                match std::str::from_utf8(&entry.value) {
+                    Ok(value) if !value.chars().any(char::is_control) => println!("{} = {value}", entry.key),
+                    _ => println!("{} = 0x{}", entry.key, to_hex(&entry.value)),
+                }
+            }
+
+            return Ok(());
+        }
+        KvAction::Set { key, value, hex } => {
+            if !tlv::is_valid_key(&key) {
+                return Err(Error::InvalidRequest {
+                    target: eeprom.target().to_string(),
+                    reason: format!("invalid key '{key}', it must be 1 to {} ASCII letters, digits, '_', '-' or '.'", tlv::MAX_KEY_LENGTH),
+                });
+            }
+
+            let value = match hex {
+                false => value.into_bytes(),
+                true => parse_hex(&value).ok_or_else(|| Error::InvalidRequest { target: eeprom.target().to_string(), reason: format!("invalid hex value '{value}'") })?,
+            };
+
+            match entries.iter_mut().find(|entry| entry.key == key) {
+                Some(entry) => entry.value = value,
+                None => entries.push(tlv::Entry { key, value }),
+            }
+        }
+        KvAction::Delete { key } => {
+            let count = entries.len();
+            entries.retain(|entry| entry.key != key);
+
+            if entries.len() == count {
+                return Err(Error::NotFound { target: eeprom.target().to_string(), what: format!("key '{key}'") });
+            }
+        }
+    }
+
+    check_unlocked(&metadata, false, eeprom.target())?;
+
+    let new_content = tlv::serialize(&entries);
+
+    let max_size = content_end(&eeprom.options().geometry, metadata.flags) - eeprom.options().geometry.content_offset;
+
+    if new_content.len() > max_size as usize {
+        return Err(Error::DataTooLarge { target: eeprom.target().to_string(), what: "key-value records".to_string(), size: new_content.len(), max: max_size as usize });
+    }
+
+    eeprom.mark_dirty(&metadata)?;
+    eeprom.write_changed_pages(eeprom.options().geometry.content_offset, content.bytes.as_slice(), new_content.as_slice())?;
+    eeprom.write_metadata(&FileInfo {
+        content_crc: CRC.checksum(new_content.as_slice()),
+        content_size: new_content.len() as u16,
+        ..metadata
+    })
+}
+
+/// Run the self-test: read the used part of the EEPROM, write it back unchanged and check that it reads back the
+/// same, restoring it if anything fails after the first read.
+fn run_self_test(eeprom: &mut Eeprom<impl Device>) -> Result<()> {
+    let start = std::time::Instant::now();
+    let size = eeprom.used_end()?;
+    let mut backup = vec![0; size];
+
+    eeprom.read_eeprom(0, backup.as_mut_slice()).map_err(|error| Error::SelfTest {
+        target: eeprom.target().to_string(),
+        outcome: format!("reading its first {size} bytes, before writing anything"),
+        source: Box::new(error),
+    })?;
+
+    println!("Read {size} bytes in {:.3}s.", start.elapsed().as_secs_f64());
+
+    let start = std::time::Instant::now();
+    let result = eeprom.write_pages(0, backup.as_slice()).and_then(|()| {
+        println!("Wrote {size} bytes in {} pages in {:.3}s.", eeprom.page_count(0, size), start.elapsed().as_secs_f64());
+
+        let start = std::time::Instant::now();
+        let mut readback = vec![0; size];
+
+        eeprom.read_eeprom(0, readback.as_mut_slice())?;
+
+        if let Some(index) = readback.iter().zip(&backup).position(|(read, written)| read != written) {
+            return Err(Error::Verification {
+                target: eeprom.target().to_string(),
+                reason: format!("byte at address {index} reads back as 0x{:02x} instead of 0x{:02x}", readback[index], backup[index]),
+            });
+        }
+
+        println!("Verified {size} bytes in {:.3}s.", start.elapsed().as_secs_f64());
+
+        Ok(())
+    });
+
+    let retried = eeprom.retried_transfers();
+
+    if let Err(error) = result {
+        let target = eeprom.target().to_string();
+
+        return match eeprom.write_pages(0, backup.as_slice()) {
+            Ok(()) => Err(Error::SelfTest { target, outcome: format!("the original data was written back ({retried} transfers retried)"), source: Box::new(error) }),
+            Err(restore_error) => {
+                log::error!("{}", eeprom::report(&Error::SelfTest { target: target.clone(), outcome: "and writing the original data back failed too".to_string(), source: Box::new(error) }));
+
+                Err(Error::Restore { target, test: "self-test", source: Box::new(restore_error) })
+            }
+        };
+    }
+
+    println!("Self-test passed ({retried} transfers retried).");
+
+    Ok(())
+}
+
+/// Format `eui` as colon-separated hex bytes, e.g. `00:04:a3:12:34:56`.
+fn format_eui(eui: &[u8]) -> String {
+    eui.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(":")
+}
+
+/// Throughput of a transfer of `bytes` bytes taking `time`, in bytes per second.
+fn throughput(bytes: usize, time: Duration) -> f64 {
+    bytes as f64 / time.as_secs_f64().max(f64::EPSILON)
+}
+
+/// Run the read benchmark, and the write benchmark with `--destructive`, see `BenchmarkCommand`.
+fn run_benchmark(eeprom: &mut Eeprom<impl Device>, benchmark: &BenchmarkCommand) -> Result<()> {
+    // Writes are padded up to the end of a 32-byte block, which must be written back too.
+    let size = (benchmark.size as usize).next_multiple_of(32).min(eeprom.options().geometry.size as usize);
+    let mut original = vec![0; size];
+    let start = std::time::Instant::now();
+
+    for _ in 0..benchmark.passes {
+        eeprom.read_eeprom(0, original.as_mut_slice()).map_err(|error| Error::Benchmark {
+            target: eeprom.target().to_string(),
+            outcome: format!("reading its first {size} bytes, before writing anything"),
+            source: Box::new(error),
+        })?;
+    }
+
+    let time = start.elapsed() / benchmark.passes;
+
+    println!("Read {size} bytes in {:.3}s on average over {} passes: {:.0} bytes/s (chunks of {} bytes).",
+        time.as_secs_f64(), benchmark.passes, throughput(size, time), eeprom.options().read_chunk);
+
+    if !benchmark.destructive {
+        return Ok(());
+    }
+
+    let pattern: Vec<u8> = (0..size).map(|index| if index % 2 == 0 { 0x55 } else { 0xAA }).collect();
+    let start = std::time::Instant::now();
+    let result = eeprom.write_pages(0, pattern.as_slice()).and_then(|()| {
+        let time = start.elapsed();
+        let write_cycle = match eeprom.options().write_cycle {
+            WriteCycle::Poll(timeout) => format!("ACK polling, up to {timeout:?}"),
+            WriteCycle::Delay(delay) => format!("a write delay of {delay:?}"),
+            WriteCycle::Adaptive { .. } => format!("an adaptive write delay, settled on {:?}", eeprom.adaptive_delay()),
+        };
+
+        println!("Wrote {size} bytes in {} pages in {:.3}s: {:.0} bytes/s (with {write_cycle}).", eeprom.page_count(0, size), time.as_secs_f64(), throughput(size, time));
+
+        let mut readback = vec![0; size];
+
+        eeprom.read_eeprom(0, readback.as_mut_slice())?;
+
+        match readback.iter().zip(&pattern).position(|(read, written)| read != written) {
+            Some(index) => Err(Error::Verification {
+                target: eeprom.target().to_string(),
+                reason: format!("byte at address {index} reads back as 0x{:02x} instead of 0x{:02x}, the write delay may be too short", readback[index], pattern[index]),
+            }),
+            None => Ok(()),
+        }
+    });
+
+    // Whatever happened, put the original data back.
+    let target = eeprom.target().to_string();
+
+    match (result, eeprom.write_pages(0, original.as_slice())) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Err(error), Ok(())) => Err(Error::Benchmark { target, outcome: "the original data was written back".to_string(), source: Box::new(error) }),
+        (result, Err(restore_error)) => {
+            if let Err(error) = result {
+                log::error!("{}", eeprom::report(&Error::Benchmark { target: target.clone(), outcome: "and writing the original data back failed too".to_string(), source: Box::new(error) }));
+            }
+
+            Err(Error::Restore { target, test: "benchmark", source: Box::new(restore_error) })
+        }
+    }
+}
+
+/// Run the subcommand given on the command line.
+fn run(command: Command) -> Result<i32> {
+    if let Sub::Codes(_) = command.subcommand {
+        print!("{}", exit_codes_help());
+        return Ok(0);
+    }
+
+    let geometry = Geometry {
+        metadata_offset: command.metadata_offset,
+        content_offset: command.content_offset,
+        page_size: command.page_size,
+        reserve: command.reserve,
+        ..Geometry::MK24C64
+    };
+
+    check_layout(&geometry, 0)?;
+    logger::init(logger::level(command.verbose, command.quiet));
+
+    let options = Options {
+        geometry,
+        metadata_layout: command.metadata_layout,
+        read_delay: Duration::from_millis(command.read_delay),
+        io_retries: command.io_retries,
+        retry_backoff: retry::Backoff {
+            initial: Duration::from_millis(command.retry_delay),
+            max: Duration::from_millis(command.retry_max_delay),
+            jitter: command.retry_jitter,
+        },
+        single_byte_writes: command.page_write_mode == PageWriteMode::Single,
+        read_chunk: command.read_chunk.unwrap_or(DEFAULT_READ_CHUNK),
+        probe_read_chunk: command.read_chunk.is_none(),
+        read_retries: command.read_retries,
+        crc_retries: command.crc_retries,
+        diagnose: command.diagnose,
+        read_votes: command.read_votes,
+        // With `write --json`, stdout only holds the JSON object.
+        announce: !command.quiet && !matches!(&command.subcommand, Sub::Write(write) if write.json),
+        ..Options::default()
+    };
+    let write_cycle = if command.simulate_fast { Duration::ZERO } else { device::image::WRITE_CYCLE };
+    let bus = Bus {
+        simulated: command.simulate.is_some().then_some(write_cycle),
+        device_path: command.simulate.unwrap_or(command.device),
+        address: command.address,
+        no_wait: command.no_wait,
+        mux: command.mux_address.zip(command.mux_channel),
+        mux_clear: command.mux_clear,
+        io_timeout: (command.io_timeout_ms > 0).then(|| Duration::from_millis(command.io_timeout_ms)),
+    };
+
+    if command.stats {
+        stats::enable();
+    }
+
+    if let Some(rate) = command.throttle {
+        stats::set_throttle(rate);
+    }
+
+    if let Some(path) = &command.transaction_log {
+        let log = transaction_log::Log::open(path, command.transaction_log_format)
+            .map_err(|source| Error::TransactionLog { target: bus.target(), path: path.display().to_string(), source })?;
+
+        transaction_log::start(log);
+    }
+
+    let json = match &command.subcommand {
+        Sub::Write(write) => write.json,
+        Sub::Info(info) => info.json,
+        _ => false,
+    };
+
+    if !command.quiet && !json && std::io::stderr().is_terminal() {
+        stats::enable_progress(command.progress_threshold);
+    }
+
+    if command.page_write_mode == PageWriteMode::Single && !command.quiet {
+        eprintln!("Warning: single-byte page write mode waits for a write cycle after every byte, which makes writes up to {}x slower. Only use it for parts or adapters that misbehave with page writes.", command.page_size);
+    }
+
+    let mut eeprom = open_device(&bus, options)?;
+    let polling_supported = polling::is_supported(eeprom.device());
+    let adaptive_delay = command.min_delay.zip(command.max_delay);
+    let mut exit_code = 0;
+
+    match command.subcommand {
+        Sub::Read(read) => {
+            let options = read_options(&read);
+            let read_once = |eeprom: &mut Eeprom<PlatformDevice>| match (read.raw, read.ignore_metadata, read.size) {
+                (true, _, Some(size)) => eeprom.read_raw(0, size),
+                (_, true, Some(size)) => eeprom.read_raw(eeprom.options().geometry.content_offset, size),
+                _ => eeprom.read_file(&options),
+            };
+
+            // A file read once is written to its destination as it arrives, checking first that it may be so as not
+            // to read it all for nothing. A patched destination is only written once the whole file is read.
+            let destination = read.destination.as_deref().filter(|destination| *destination != Path::new("-"));
+            let mut streamed = match (destination, read.raw || read.ignore_metadata || read.patch, command.repeat) {
+                (Some(destination), false, None) => {
+                    if !read.force && !read.backup && destination.exists() {
+                        return Err(destination_exists(eeprom.target(), destination));
+                    }
+
+                    StreamedDestination::create(destination)
+                }
+                _ => None,
+            };
+
+            let content_buffer = match (command.repeat, &mut streamed) {
+                (Some(count), _) => repeat(&mut eeprom, count, read_once)?,
+                (None, Some(streamed)) => eeprom.read_file_with(&options, |position, chunk| streamed.write(position, chunk))?,
+                (None, None) => read_once(&mut eeprom)?,
+            };
+
+            if read.hexdump {
+                print!("{}", hexdump(content_buffer.as_slice()));
+            }
+
+            match (streamed, &read.destination) {
+                (Some(streamed), Some(destination)) => streamed.finish(eeprom.target(), destination.as_path(), content_buffer.as_slice(), &read)?,
+                (None, Some(destination)) => save_content(eeprom.target(), destination.as_path(), content_buffer.as_slice(), &read)?,
+                (_, None) => {}
+            }
+        }
+        Sub::Write(write) => {
+            let write_cycle = select_write_cycle(eeprom.target(), polling_supported, command.write_delay, adaptive_delay, write.fast)?;
+            eeprom.set_write_cycle(write_cycle);
+            eeprom.set_verify_pages(write.verify_pages, write.page_retries);
+            interrupt::defer();
+            let magic = write.magic.as_ref().map_or(&[][..], |magic| magic.0.as_slice());
+            // The magic counts against the space for the content, the whole EEPROM with --raw.
+            let max_size = if write.raw { eeprom.options().geometry.size } else { eeprom.max_content_size() };
+            let content_buffer = read_content_source(eeprom.target(), &write, magic, max_size as usize)?;
+            let options = write_options(&write);
+            let quick_verify_seed = write.quick_verify.then(|| write.quick_verify_seed.unwrap_or_else(retry::random));
+
+            if write.raw {
+                match command.repeat {
+                    Some(count) => repeat(&mut eeprom, count, |eeprom| {
+                        eeprom.write_raw(&options, content_buffer.as_slice())?;
+                        eeprom.verify_raw(content_buffer.as_slice())
+                    })?,
+                    None => eeprom.write_raw(&options, content_buffer.as_slice())?,
+                }
+
+                if let Some(seed) = quick_verify_seed {
+                    eeprom.quick_verify(None, 0, content_buffer.as_slice(), seed)?;
+                }
+
+                let verification = write.verify_after.then(|| eeprom.verify_raw(content_buffer.as_slice()));
+                report_write(&eeprom, verification.as_ref(), write.json, content_buffer.len() as u16, CRC.checksum(content_buffer.as_slice()), eeprom.options().geometry.size as usize - content_buffer.len());
+                verification.transpose()?;
+            } else if write.if_changed && eeprom.is_up_to_date(&options, content_buffer.as_slice())? {
+                if !command.quiet {
+                    println!("EEPROM already holds this file, it is up to date.");
+                }
+            } else {
+                let source = quick_verify_seed.map(|_| content_buffer.clone());
+                let metadata = match command.repeat {
+                    Some(count) => repeat(&mut eeprom, count, |eeprom| {
+                        let metadata = eeprom.write_file(content_buffer.as_slice(), &options)?;
+                        eeprom.verify_written(write.slot, &metadata)?;
+                        Ok(metadata)
+                    })?,
+                    None => eeprom.write_file(content_buffer.as_slice(), &options)?,
+                };
+
+                if let (Some(seed), Some(mut content)) = (quick_verify_seed, source) {
+                    content.resize(metadata.content_size as usize, write.pad_byte);
+                    eeprom.quick_verify(Some(&metadata), eeprom.options().geometry.content_offset, content.as_slice(), seed)?;
+                }
+
+                let bytes_free = eeprom.free_size()?;
+                let verification = write.verify_after.then(|| eeprom.verify_written(write.slot, &metadata));
+                report_write(&eeprom, verification.as_ref(), write.json, metadata.content_size, metadata.content_crc, bytes_free);
+                verification.transpose()?;
+
+                if write.report_changed {
+                    exit_code = ExitCode::Changed as i32;
+                }
+            }
+
+            if matches!(eeprom.options().write_cycle, WriteCycle::Adaptive { .. }) && !command.quiet {
+                println!("Settled on a write delay of {:?}.", eeprom.adaptive_delay());
+            }
+        }
+        Sub::Verify(verify) => {
+            let metadata = eeprom.verify(&ReadOptions { slot: verify.slot, require_payload_version: verify.require_payload_version.clone(), ..ReadOptions::default() })?;
+
+            if !command.quiet {
+                println!("File in EEPROM is valid ({} bytes).", metadata.content_size);
+            }
+        }
+        Sub::Erase(erase) => {
+            let write_cycle = select_write_cycle(eeprom.target(), polling_supported, command.write_delay, adaptive_delay, false)?;
+            eeprom.set_write_cycle(write_cycle);
+
+            match erase.slot {
+                Some(slot) => {
+                    eeprom.erase_slot(slot, erase.force)?;
+                }
+                None => {
+                    check_unlocked(&eeprom.read_metadata_or_empty()?, erase.force, eeprom.target())?;
+                    eeprom.erase()?;
+                }
+            }
+        }
+        Sub::Info(info) => {
+            let (metadata, digest, crc_valid, full_crc) = eeprom.read_consistent(|eeprom| {
+                let metadata = eeprom.read_metadata()?;
+                let content = eeprom.read_content_at(eeprom.options().geometry.content_offset, &metadata)?;
+                let full_crc = match info.full_crc {
+                    true => Some(eeprom.check_full_crc(&metadata)?),
+                    false => None,
+                };
+
+                let crc_valid = validate_content(&metadata, &content, eeprom.target()).is_ok();
+
+                Ok((metadata, content.digest, crc_valid, full_crc))
+            })?;
+            let bytes_free = eeprom.free_size()?;
+            let format = match metadata.format {
+                Format::V1 => "v1",
+                Format::V2 => "v2",
+                Format::V3 => "v3",
+            };
+            let halves = match metadata.has_ab() {
+                true => Some(eeprom.read_ab_halves(&metadata)?),
+                false => None,
+            };
+            let active = halves.and_then(|halves| ab::active(&halves.map(|half| half.map(|(header, _)| header))));
+            let output = info.format.unwrap_or(if info.json { InfoFormat::Json } else { InfoFormat::Text });
+
+            if output == InfoFormat::Prometheus {
+                let address = format!("0x{:02x}", bus.address);
+
+                print!("{}", prometheus_metrics(&[("device", bus.device_path.as_str()), ("address", address.as_str())], &metadata, crc_valid, bytes_free));
+            } else if output == InfoFormat::Json {
+                let digest = match digest {
+                    Some(digest) => format!("\"{}\"", to_hex(&digest)),
+                    None => "null".to_string(),
+                };
+
+                let flag_names: Vec<String> = metadata::flag_names(metadata.flags).iter()
+                    .map(|name| format!("\"{name}\""))
+                    .collect();
+
+                let serial = match metadata.serial.is_empty() {
+                    true => "null".to_string(),
+                    false => json::string(&metadata.serial),
+                };
+
+                let ab_halves = match halves {
+                    Some(halves) => {
+                        let halves: Vec<String> = halves.iter().enumerate().map(|(index, half)| match half {
+                            Some((header, valid)) => format!(
+                                "{{\"active\":{},\"sequence\":{},\"content_size\":{},\"content_crc\":{},\"valid\":{valid}}}",
+                                active == Some(index), header.sequence, header.size, header.crc,
+                            ),
+                            None => "null".to_string(),
+                        }).collect();
+
+                        format!("[{}]", halves.join(","))
+                    }
+                    None => "null".to_string(),
+                };
+
+                let full_crc = match full_crc {
+                    Some((crc, stored)) => format!(",\"full_crc\":{crc},\"full_crc_stored\":{stored}"),
+                    None => String::new(),
+                };
+
+                let content_type = match ContentType::from_flags(metadata.flags) {
+                    Some(content_type) => format!("\"{}\"", content_type.name()),
+                    None => "null".to_string(),
+                };
+
+                println!(
+                    "{{\"format\":\"{format}\",\"flags\":{},\"flag_names\":[{}],\"serial\":{serial},\"payload_version\":{},\"content_type\":{content_type},\"content_size\":{},\"content_crc\":{},\"sha256\":{digest},\"bytes_free\":{bytes_free},\"ab_halves\":{ab_halves}{full_crc}{}}}",
+                    metadata.flags, flag_names.join(","), json::string(&metadata.payload_version), metadata.content_size, metadata.content_crc, stats_json(&eeprom),
+                );
+            } else {
+                println!("Format:       {format}");
+                println!("Flags:        0x{:04x} ({})", metadata.flags, metadata::flag_names(metadata.flags).join(", "));
+                if !metadata.serial.is_empty() {
+                    println!("Serial:       {}", metadata.serial);
+                }
+
+                if !metadata.payload_version.is_empty() {
+                    println!("Payload:      {}", metadata.payload_version);
+                }
+
+                if let Some(content_type) = ContentType::from_flags(metadata.flags) {
+                    println!("Content type: {}", content_type.name());
+                }
+
+                println!("Content size: {} bytes", metadata.content_size);
+                println!("Content CRC:  0x{:04x}{}", metadata.content_crc, if metadata.has_external_crc() { " (external, not checked)" } else { "" });
+                println!("Bytes free:   {bytes_free}");
+
+                if let Some(digest) = digest {
+                    println!("SHA-256:      {}", to_hex(&digest));
+                }
+
+                if let Some((crc, stored)) = full_crc {
+                    println!("Full CRC:     0x{crc:04x} ({})", if stored { "matches the one stored" } else { "not stored" });
+                }
+
+                for (index, half) in halves.iter().flatten().enumerate() {
+                    let status = match half {
+                        Some((header, valid)) => format!(
+                            "{}sequence {}, {} bytes, CRC 0x{:04x}, {}",
+                            if active == Some(index) { "active, " } else { "" }, header.sequence, header.size, header.crc, if *valid { "valid" } else { "corrupted" },
+                        ),
+                        None => "empty".to_string(),
+                    };
+
+                    println!("Half {}:       {status}", ab::HALF_NAMES[index]);
+                }
+            }
+        }
+        Sub::Ls(_) => {
+            let metadata = eeprom.read_metadata()?;
+            let table = eeprom.read_slot_table(&metadata)?.unwrap_or_else(|| {
+                let mut table = SlotTable::default();
+
+                if metadata.content_size != 0 {
+                    table.slots[0] = Some(Slot {
+                        offset: eeprom.options().geometry.content_offset,
+                        size: metadata.content_size,
+                        crc: metadata.content_crc,
+                        kind: 0,
+                    });
+                }
+
+                table
+            });
+
+            println!("Slot  Address  Size  CRC     Type");
+
+            for (index, slot) in table.slots.iter().enumerate() {
+                match slot {
+                    Some(slot) => println!("{index:<4}  0x{:04x}   {:<4}  0x{:04x}  {}", slot.offset, slot.size, slot.crc, slot.kind),
+                    None => println!("{index:<4}  (empty)"),
+                }
+            }
+        }
+        Sub::Kv(kv) => {
+            let write_cycle = select_write_cycle(eeprom.target(), polling_supported, command.write_delay, adaptive_delay, false)?;
+            eeprom.set_write_cycle(write_cycle);
+            run_kv(&mut eeprom, kv.action)?;
+        }
+        Sub::Userdata(userdata) => {
+            let write_cycle = select_write_cycle(eeprom.target(), polling_supported, command.write_delay, adaptive_delay, false)?;
+            eeprom.set_write_cycle(write_cycle);
+            run_userdata(&mut eeprom, userdata.action)?;
+        }
+        Sub::Serial(serial) => {
+            let write_cycle = select_write_cycle(eeprom.target(), polling_supported, command.write_delay, adaptive_delay, false)?;
+            eeprom.set_write_cycle(write_cycle);
+            run_serial(&mut eeprom, serial.action)?;
+        }
+        Sub::Lock(_) | Sub::Unlock(_) => {
+            let locked = matches!(command.subcommand, Sub::Lock(_));
+            let write_cycle = select_write_cycle(eeprom.target(), polling_supported, command.write_delay, adaptive_delay, false)?;
+            eeprom.set_write_cycle(write_cycle);
+            let metadata = eeprom.read_metadata()?;
+
+            if !metadata.format.is_v2_or_later() {
+                return Err(Error::InvalidRequest {
+                    target: eeprom.target().to_string(),
+                    reason: "locking requires the v2 metadata format, rewrite the file with it first".to_string(),
+                });
+            }
+
+            if metadata.is_locked() != locked {
+                let flags = if locked { metadata.flags | FLAG_LOCKED } else { metadata.flags & !FLAG_LOCKED };
+                eeprom.update_metadata(&FileInfo { flags, ..metadata })?;
+            }
+        }
+        Sub::SelfTest(_) => {
+            let write_cycle = select_write_cycle(eeprom.target(), polling_supported, command.write_delay, adaptive_delay, false)?;
+            eeprom.set_write_cycle(write_cycle);
+            run_self_test(&mut eeprom)?;
+        }
+        Sub::Codes(_) => unreachable!("codes is handled before opening the EEPROM"),
+        Sub::Eui(eui) => {
+            let (size, default_offset) = if eui.eui64 { (8, 0xF8) } else { (6, 0xFA) };
+            let bytes = eeprom.read_eui(eui.offset.unwrap_or(default_offset), size, eui.short_address)?;
+
+            println!("{}", format_eui(&bytes));
+        }
+        Sub::Benchmark(benchmark) => {
+            let write_cycle = select_write_cycle(eeprom.target(), polling_supported, command.write_delay, adaptive_delay, false)?;
+            eeprom.set_write_cycle(write_cycle);
+            run_benchmark(&mut eeprom, &benchmark)?;
+        }
+        Sub::DetectCrc(detect) => {
+            let metadata = eeprom.read_metadata()?;
+            let (offset, metadata) = eeprom.select_slot(metadata, detect.slot)?;
+            let content = eeprom.read_content_at(offset, &metadata)?;
+            let names = crc_detect::matching(content.bytes.as_slice(), metadata.content_crc);
+
+            if names.is_empty() {
+                return Err(Error::NotFound { target: eeprom.target().to_string(), what: format!("known CRC-16 algorithm giving the stored CRC 0x{:04x}", metadata.content_crc) });
+            }
+
+            for name in names {
+                println!("{name}");
+            }
+        }
+        Sub::History(_) => {
+            let metadata = eeprom.read_metadata()?;
+
+            if !metadata.has_history() {
+                return Err(Error::NotFound { target: eeprom.target().to_string(), what: "history of its metadata (enable it with `write --history`)".to_string() });
+            }
+
+            println!("Replaced at              Size  CRC     Label");
+
+            for entry in eeprom.read_history()?.newest_first() {
+                println!("{}  {:<4}  0x{:04x}  {}", history::format_timestamp(entry.timestamp), entry.content_size, entry.content_crc, entry.label);
+            }
+        }
+    }
+
+    if !eeprom.stall_time().is_zero() {
+        log::info!("Waited {:?} in total for the device to complete its write cycles.", eeprom.stall_time());
+    }
+
+    if stats::enabled() {
+        eprintln!("{}", stats::summary(eeprom.retried_transfers(), eeprom.corrected_bytes(), eeprom.stall_time()));
+        eprintln!("Read in chunks of up to {} bytes{}.", eeprom.options().read_chunk, if command.read_chunk.is_none() { " (probed)" } else { "" });
+    }
+
+    Ok(exit_code)
+}
+
+fn main() {
+    let result = run(Command::parse());
+
+    clear_mux();
+
+    match result {
+        Ok(0) => {}
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(error) => {
+            eprintln!("{}", eeprom::report(&error));
+            std::process::exit(exit_code(&error) as i32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device::mock::MockEeprom;
+    use vki2cfile::eeprom::UNNAMED_TARGET;
+
+    fn eeprom() -> Eeprom<MockEeprom> {
+        Eeprom::new(MockEeprom::new(EEPROM_SIZE as usize), Options { write_cycle: WriteCycle::Delay(Duration::ZERO), ..Options::default() })
+    }
+
+    fn write_command(args: &[&str]) -> WriteCommand {
+        match Command::parse_from([&["vki2cfile", "write"], args, &["file"]].concat()).subcommand {
+            Sub::Write(write) => write,
+            _ => unreachable!(),
+        }
+    }
+
+    fn read_command() -> ReadCommand {
+        match Command::parse_from(["vki2cfile", "read", "file"]).subcommand {
+            Sub::Read(read) => read,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(eeprom: &mut Eeprom<MockEeprom>, write: &WriteCommand, content: &[u8]) -> Result<FileInfo> {
+        eeprom.write_file(content, &write_options(write))
+    }
+
+    #[test]
+    fn content_is_saved_to_temporary_file_if_destination_cannot_be_written() {
+        let content = b"calibration".to_vec();
+        let destination = std::env::temp_dir().join("vki2cfile-missing-directory").join("file");
+        let recovered = std::env::temp_dir().join(format!("vki2cfile-recovered-{}.bin", std::process::id()));
+
+        let error = save_content(UNNAMED_TARGET, destination.as_path(), &content, &read_command()).unwrap_err();
+
+        assert!(error.to_string().contains(&recovered.display().to_string()), "{error}");
+        assert_eq!(std::fs::read(recovered.as_path()).unwrap(), content);
+        std::fs::remove_file(recovered).unwrap();
+    }
+
+    #[test]
+    fn existing_destination_is_only_overwritten_with_force_or_backup() {
+        let destination = std::env::temp_dir().join(format!("vki2cfile-destination-{}.bin", std::process::id()));
+        let backup = destination.with_extension("bin.bak");
+        let read = |args: &[&str]| match Command::parse_from([&["vki2cfile", "read"], args, &["file"]].concat()).subcommand {
+            Sub::Read(read) => read,
+            _ => unreachable!(),
+        };
+
+        std::fs::write(destination.as_path(), b"edited").unwrap();
+
+        let error = save_content(UNNAMED_TARGET, destination.as_path(), b"read", &read(&[])).unwrap_err();
+        assert!(error.to_string().contains("pass --force"), "{error}");
+        assert_eq!(std::fs::read(destination.as_path()).unwrap(), b"edited");
+
+        save_content(UNNAMED_TARGET, destination.as_path(), b"read", &read(&["--backup"])).unwrap();
+        assert_eq!(std::fs::read(destination.as_path()).unwrap(), b"read");
+        assert_eq!(std::fs::read(backup.as_path()).unwrap(), b"edited");
+
+        save_content(UNNAMED_TARGET, destination.as_path(), b"forced", &read(&["--force"])).unwrap();
+        assert_eq!(std::fs::read(destination.as_path()).unwrap(), b"forced");
+
+        std::fs::remove_file(destination).unwrap();
+        std::fs::remove_file(backup).unwrap();
+    }
+
+    #[test]
+    fn patch_only_writes_differing_bytes() {
+        assert_eq!(differing_ranges(b"calibration", b"calibration"), []);
+        assert_eq!(differing_ranges(b"calibration-1", b"CALibration-2x"), [0..3, 12..14]);
+
+        let destination = std::env::temp_dir().join(format!("vki2cfile-patched-{}.bin", std::process::id()));
+
+        assert_eq!(patch_file(destination.as_path(), b"calibration-1").unwrap(), 13);
+        assert_eq!(patch_file(destination.as_path(), b"calibration-2").unwrap(), 1);
+        assert_eq!(std::fs::read(destination.as_path()).unwrap(), b"calibration-2");
+
+        assert_eq!(patch_file(destination.as_path(), b"cal").unwrap(), 0);
+        assert_eq!(std::fs::read(destination.as_path()).unwrap(), b"cal");
+
+        std::fs::remove_file(destination).unwrap();
+    }
+
+    #[test]
+    fn streamed_read_only_reaches_destination_once_checked() {
+        let destination = std::env::temp_dir().join(format!("vki2cfile-streamed-{}.bin", std::process::id()));
+        let content: Vec<u8> = (0..300).map(|index| index as u8).collect();
+        let mut eeprom = eeprom();
+        write(&mut eeprom, &write_command(&[]), &content).unwrap();
+        eeprom.device().memory[DEFAULT_CONTENT_OFFSET as usize + 200] ^= 0xFF;
+
+        let mut streamed = StreamedDestination::create(destination.as_path()).unwrap();
+        let temporary = streamed.temporary.clone();
+        assert!(eeprom.read_file_with(&read_options(&read_command()), |position, chunk| streamed.write(position, chunk)).is_err());
+        drop(streamed);
+        assert!(!destination.exists() && !temporary.exists());
+
+        eeprom.device().memory[DEFAULT_CONTENT_OFFSET as usize + 200] ^= 0xFF;
+
+        let mut streamed = StreamedDestination::create(destination.as_path()).unwrap();
+        let read = eeprom.read_file_with(&read_options(&read_command()), |position, chunk| streamed.write(position, chunk)).unwrap();
+        streamed.finish(UNNAMED_TARGET, destination.as_path(), read.as_slice(), &read_command()).unwrap();
+        assert_eq!(std::fs::read(destination.as_path()).unwrap(), content);
+        assert!(!temporary.exists());
+
+        std::fs::remove_file(destination).unwrap();
+    }
+
+    #[test]
+    fn page_size_fits_smbus_block_writes() {
+        let mut device = MockEeprom::new(EEPROM_SIZE as usize);
+        assert_eq!(fitted_page_size(&device, pages::DEFAULT_PAGE_SIZE), None);
+
+        device.max_write_size = Some(33);
+        let page_size = fitted_page_size(&device, pages::DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(page_size, 16);
+
+        let mut page = vec![0, 0];
+        page.extend(vec![0x42; page_size as usize]);
+        device.write(&page).unwrap();
+        page.extend(vec![0x42; page_size as usize]);
+        assert!(device.write(&page).is_err());
+    }
+
+    #[test]
+    fn long_help_lists_every_exit_code() {
+        let help = exit_codes_help();
+
+        for code in ExitCode::ALL {
+            assert!(help.contains(&format!(" {}  {}", code as i32, code.description())), "{help}");
+        }
+    }
+
+    #[test]
+    fn failures_exit_with_their_code() {
+        let mut eeprom = eeprom();
+        let read = |eeprom: &mut Eeprom<MockEeprom>| exit_code(&eeprom.read_file(&read_options(&read_command())).unwrap_err()) as i32;
+
+        assert_eq!(read(&mut eeprom), 12);
+
+        write(&mut eeprom, &write_command(&[]), &[0x42; 100]).unwrap();
+        eeprom.device().memory[DEFAULT_CONTENT_OFFSET as usize + 50] ^= 1;
+        assert_eq!(read(&mut eeprom), 15);
+
+        let oversized = vec![0x42; (EEPROM_SIZE - DEFAULT_CONTENT_OFFSET) as usize + 1];
+        assert_eq!(exit_code(&write(&mut eeprom, &write_command(&[]), &oversized).unwrap_err()) as i32, 17);
+
+        write(&mut eeprom, &write_command(&[]), &[]).unwrap();
+        assert_eq!(read(&mut eeprom), 19);
+
+        assert_eq!(exit_code(&Error::NotFound { target: UNNAMED_TARGET.to_string(), what: "serial number".to_string() }) as i32, 1);
+
+        // A transfer failing even after its retries is a transient I/O error.
+        eeprom.device().writes_left = Some(3);
+        assert_eq!(exit_code(&write(&mut eeprom, &write_command(&[]), &[0x43; 200]).unwrap_err()) as i32, 18);
+        eeprom.device().writes_left = None;
+        assert_eq!(read(&mut eeprom), 11);
+
+        // So is the EEPROM not coming back after a write cycle.
+        let source = || Box::new(std::io::Error::from_raw_os_error(libc::ENXIO));
+        assert_eq!(exit_code(&Error::AckTimeout { target: UNNAMED_TARGET.to_string(), timeout: polling::POLL_TIMEOUT, source: source() }) as i32, 18);
+        assert_eq!(exit_code(&Error::Poll { target: UNNAMED_TARGET.to_string(), source: source() }) as i32, 18);
+
+        // Options that do not go together are usage errors, whether clap or the tool finds out.
+        assert_eq!(Command::try_parse_from(["vki2cfile", "write"]).err().unwrap().exit_code(), ExitCode::Usage as i32);
+        assert_eq!(exit_code(&select_write_cycle(UNNAMED_TARGET, true, None, Some((5, 2)), false).unwrap_err()), ExitCode::Usage);
+    }
+
+    #[test]
+    fn repeat_returns_last_success_unless_every_iteration_fails() {
+        let mut eeprom = eeprom();
+        let mut calls = 0;
+        let result = repeat(&mut eeprom, 4, |_| {
+            calls += 1;
+            match calls % 2 {
+                0 => Err(Error::Verification { target: UNNAMED_TARGET.to_string(), reason: format!("failure {calls}") }),
+                _ => Ok(calls),
+            }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(calls, 4);
+
+        let result: Result<()> = repeat(&mut eeprom, 2, |_| Err(Error::Verification { target: UNNAMED_TARGET.to_string(), reason: "failure".to_string() }));
+        assert!(result.is_err());
+
+        let mut calls = 0;
+        let result: Result<()> = repeat(&mut eeprom, 5, |eeprom| {
+            calls += 1;
+            Err(Error::Interrupted { target: eeprom.target().to_string(), address: 0x20 })
+        });
+        assert!(matches!(result, Err(Error::Interrupted { .. })));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn oversized_source_is_rejected_before_reading() {
+        let path = std::env::temp_dir().join(format!("vki2cfile-source-{}.bin", std::process::id()));
+        std::fs::write(path.as_path(), [0x42; 100]).unwrap();
+
+        let content = read_source(UNNAMED_TARGET, path.as_path(), b"VK", 102).unwrap();
+        assert_eq!(content.len(), 102);
+
+        let Err(error) = read_source(UNNAMED_TARGET, path.as_path(), b"VK", 101) else { panic!("oversized source was read") };
+        assert!(error.to_string().contains("too large"), "{error}");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn several_sources_are_concatenated() {
+        let paths = ["header", "data"].map(|name| std::env::temp_dir().join(format!("vki2cfile-{name}-{}.bin", std::process::id())));
+        std::fs::write(paths[0].as_path(), [0x01; 10]).unwrap();
+        std::fs::write(paths[1].as_path(), [0x02; 20]).unwrap();
+
+        let parse = |args: &[&str]| match Command::parse_from([&["vki2cfile", "write"], args].concat()).subcommand {
+            Sub::Write(write) => write,
+            _ => unreachable!(),
+        };
+        let write = parse(&[paths[0].to_str().unwrap(), paths[1].to_str().unwrap()]);
+
+        let content = read_content_source(UNNAMED_TARGET, &write, b"VK", 32).unwrap();
+        assert_eq!(content, [&b"VK"[..], &[0x01; 10], &[0x02; 20]].concat());
+
+        let Err(error) = read_content_source(UNNAMED_TARGET, &write, b"VK", 31) else { panic!("oversized sources were accepted") };
+        assert!(error.to_string().contains("(32 bytes) is too large"), "{error}");
+
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn destructive_benchmark_restores_the_original_data() {
+        let mut eeprom = eeprom();
+        write(&mut eeprom, &write_command(&[]), b"calibration").unwrap();
+        let original = eeprom.device().memory.clone();
+        let benchmark = match Command::parse_from(["vki2cfile", "benchmark", "--destructive", "--size", "300", "--passes", "2"]).subcommand {
+            Sub::Benchmark(benchmark) => benchmark,
+            _ => unreachable!(),
+        };
+
+        run_benchmark(&mut eeprom, &benchmark).unwrap();
+        assert_eq!(eeprom.device().memory, original);
+    }
+
+    #[test]
+    fn content_can_be_given_on_the_command_line() {
+        let parse = |args: &[&str]| Command::try_parse_from([&["vki2cfile", "write"], args].concat()).map(|command| match command.subcommand {
+            Sub::Write(write) => write,
+            _ => unreachable!(),
+        });
+
+        let write = parse(&["--magic", "564b", "--data", "VK-0042"]).unwrap();
+        let content = read_content_source(UNNAMED_TARGET, &write, b"VK", 100).unwrap();
+        assert_eq!(content, b"VKVK-0042");
+
+        let write = parse(&["--hex", "02005e100001"]).unwrap();
+        assert_eq!(read_content_source(UNNAMED_TARGET, &write, &[], 100).unwrap(), [0x02, 0x00, 0x5e, 0x10, 0x00, 0x01]);
+        let Err(error) = read_content_source(UNNAMED_TARGET, &write, &[], 5) else { panic!("oversized content was accepted") };
+        assert!(error.to_string().contains("given with --hex"), "{error}");
+
+        assert!(parse(&["--data", "VK-0042", "file"]).is_err());
+        assert!(parse(&["--data", "VK-0042", "--hex", "00"]).is_err());
+        assert!(parse(&[]).is_err());
+    }
+
+    #[test]
+    fn invalid_metadata_is_shown_and_can_be_ignored() {
+        let mut eeprom = eeprom();
+        write(&mut eeprom, &write_command(&[]), b"calibration").unwrap();
+        eeprom.device().memory[METADATA_OFFSET as usize + 2] = 0xFF;
+
+        let error = eeprom.read_file(&read_options(&read_command())).unwrap_err().to_string();
+        assert!(error.contains("unsupported metadata version 255") && error.contains("564bff") && error.contains("--ignore-metadata"), "{error}");
+
+        assert_eq!(eeprom.read_raw(DEFAULT_CONTENT_OFFSET, 11).unwrap(), b"calibration");
+        assert!(matches!(eeprom.read_raw(DEFAULT_CONTENT_OFFSET, EEPROM_SIZE), Err(Error::OutOfRange { .. })));
+
+        let parse = |args: &[&str]| Command::try_parse_from([&["vki2cfile", "read"], args, &["file"]].concat());
+        assert!(parse(&["--ignore-metadata", "--size", "11"]).is_ok());
+        assert!(parse(&["--ignore-metadata"]).is_err());
+        assert!(parse(&["--size", "11"]).is_err());
+        assert!(parse(&["--ignore-metadata", "--raw", "--size", "11"]).is_err());
+    }
+
+    #[test]
+    fn info_formats_prometheus_metrics() {
+        let metadata = FileInfo { content_size: 300, serial: "VK-\"1\"".to_string(), ..FileInfo::default() };
+        let metrics = prometheus_metrics(&[("device", "/dev/i2c-3"), ("address", "0x50")], &metadata, true, 7000);
+
+        assert!(metrics.contains("\nvki2cfile_content_size_bytes{device=\"/dev/i2c-3\",address=\"0x50\"} 300\n"), "{metrics}");
+        assert!(metrics.contains("\nvki2cfile_crc_valid{device=\"/dev/i2c-3\",address=\"0x50\"} 1\n"), "{metrics}");
+        assert!(metrics.contains("\nvki2cfile_bytes_free{device=\"/dev/i2c-3\",address=\"0x50\"} 7000\n"), "{metrics}");
+        assert!(metrics.contains(",serial=\"VK-\\\"1\\\"\","), "{metrics}");
+        assert!(metrics.contains("# TYPE vki2cfile_crc_valid gauge\n"), "{metrics}");
+    }
+
+    #[test]
+    fn crc_is_given_in_hex_or_left_out() {
+        assert_eq!(write_options(&write_command(&["--crc-value", "0xbeef"])).crc, Some(0xbeef));
+        assert_eq!(write_options(&write_command(&["--crc-value", "beef"])).crc, Some(0xbeef));
+        assert_eq!(write_options(&write_command(&["--no-crc"])).crc, Some(0));
+        assert_eq!(write_options(&write_command(&[])).crc, None);
+        assert!(Command::try_parse_from(["vki2cfile", "write", "--no-crc", "--crc-value", "0", "file"]).is_err());
+    }
+
+    #[test]
+    fn read_votes_must_be_odd() {
+        assert!(parse_votes("3").is_ok());
+        assert!(parse_votes("2").is_err());
+        assert!(parse_votes("0").is_err());
+    }
+
+    #[test]
+    fn eui_is_read_from_its_region() {
+        let mut eeprom = eeprom();
+
+        assert!(eeprom.read_eui(0xFA, 6, false).unwrap_err().to_string().contains("No EUI"));
+
+        eeprom.device().memory[0xFA..0x100].copy_from_slice(&[0x00, 0x04, 0xA3, 0x12, 0x34, 0x56]);
+        assert_eq!(format_eui(&eeprom.read_eui(0xFA, 6, false).unwrap()), "00:04:a3:12:34:56");
+        assert!(eeprom.read_eui(0xFC, 6, true).is_err());
+    }
+
+    #[test]
+    fn write_delay_replaces_polling_unless_polling_is_required() {
+        assert_eq!(select_write_cycle(UNNAMED_TARGET, true, None, None, false).unwrap(), WriteCycle::Poll(polling::POLL_TIMEOUT));
+        assert_eq!(select_write_cycle(UNNAMED_TARGET, false, None, None, false).unwrap(), WriteCycle::Delay(DEFAULT_WRITE_DELAY));
+        assert_eq!(select_write_cycle(UNNAMED_TARGET, true, Some(0), None, false).unwrap(), WriteCycle::Delay(Duration::ZERO));
+        assert_eq!(select_write_cycle(UNNAMED_TARGET, true, Some(0), None, true).unwrap(), WriteCycle::Poll(polling::POLL_TIMEOUT));
+        assert_eq!(select_write_cycle(UNNAMED_TARGET, true, Some(40), None, true).unwrap(), WriteCycle::Poll(Duration::from_millis(40)));
+        assert!(select_write_cycle(UNNAMED_TARGET, false, Some(40), None, true).is_err());
+    }
+
+    #[test]
+    fn adaptive_delay_starts_at_minimum_and_grows_up_to_maximum() {
+        assert!(select_write_cycle(UNNAMED_TARGET, true, None, Some((5, 2)), false).is_err());
+        assert!(select_write_cycle(UNNAMED_TARGET, true, None, Some((1, 5)), true).is_err());
+
+        let mut eeprom = eeprom();
+        eeprom.set_write_cycle(select_write_cycle(UNNAMED_TARGET, true, None, Some((1, 5)), false).unwrap());
+        assert_eq!(eeprom.adaptive_delay(), Duration::from_millis(1));
+
+        let mut delays = Vec::new();
+        while eeprom.increase_adaptive_delay() {
+            delays.push(eeprom.adaptive_delay().as_millis());
+        }
+
+        assert_eq!(delays, [2, 4, 5]);
+    }
+
+    #[test]
+    fn zero_write_delay_writes_without_waiting() {
+        let mut eeprom = eeprom();
+        let content = vec![0x42; 500];
+
+        eeprom.set_write_cycle(select_write_cycle(UNNAMED_TARGET, false, Some(0), None, false).unwrap());
+        write(&mut eeprom, &write_command(&[]), &content).unwrap();
+
+        assert_eq!(eeprom.read_file(&read_options(&read_command())).unwrap(), content);
+        assert_eq!(eeprom.stall_time(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,672 @@
+use std::ops::Range;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+/// Size of the metadata block in bytes, regardless of its format.
+pub const METADATA_SIZE: usize = 32;
+
+/// Magic bytes at the start of a v2 metadata block.
+pub const MAGIC: [u8; 2] = *b"VK";
+
+/// Version byte of the v2 metadata block.
+pub const VERSION_2: u8 = 2;
+
+/// Version byte of the v3 metadata block.
+pub const VERSION_3: u8 = 3;
+
+/// Range of the CRC of the metadata block itself in a v3 block.
+const METADATA_CRC_RANGE: Range<usize> = 13..15;
+
+/// CRC algorithm used for the v3 metadata block, the same as for the content.
+const METADATA_CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_USB);
+
+/// Maximum length of the serial number in bytes.
+pub const SERIAL_SIZE: usize = 12;
+
+/// Maximum length of the payload version tag in bytes.
+pub const PAYLOAD_VERSION_SIZE: usize = 8;
+
+/// Flag: a SHA-256 digest of the content is stored in a trailer right after the content.
+pub const FLAG_DIGEST: u16 = 1 << 0;
+/// Flag: the content is gzip-compressed.
+pub const FLAG_COMPRESSED: u16 = 1 << 1;
+/// Flag: the content is encrypted.
+pub const FLAG_ENCRYPTED: u16 = 1 << 2;
+/// Flag: a slot table follows the metadata block, see the `slots` module. The content CRC and size then describe
+/// the slot table rather than a file.
+pub const FLAG_SLOTS: u16 = 1 << 3;
+/// Flag: the EEPROM is locked, writes are refused unless forced. This is an advisory software protection, on top
+/// of any hardware write protection. It describes the module rather than the content, so it is kept across writes.
+pub const FLAG_LOCKED: u16 = 1 << 4;
+/// Flag: the metadata of replaced files is recorded in a history ring at the end of the EEPROM, see the `history`
+/// module. It describes the module rather than the content, so it is kept across writes.
+pub const FLAG_HISTORY: u16 = 1 << 5;
+/// Flag: a write is in progress, set before the content is touched and cleared when the new metadata is written.
+/// If it is still set, the write was interrupted and the content may be a mix of the old and new files.
+pub const FLAG_DIRTY: u16 = 1 << 6;
+/// Flag: the space for content is split into two halves holding a file each, see the `ab` module. The content CRC and
+/// size then describe no content.
+pub const FLAG_AB: u16 = 1 << 7;
+/// Flag: a CRC of the metadata block followed by the content (and digest trailer, if any) is stored in a trailer
+/// right after them, giving a single value covering the whole used part of the EEPROM.
+pub const FLAG_FULL_CRC: u16 = 1 << 8;
+/// Flag: the content CRC was supplied when writing (or left zero) rather than computed over the content, for firmware
+/// owning the CRC semantics. It is not checked when reading, which is then the responsibility of the firmware.
+pub const FLAG_EXTERNAL_CRC: u16 = 1 << 12;
+
+/// Bits of the flags holding a hint of the kind of content stored, see the `content_type` module. All zero if unknown.
+pub const CONTENT_TYPE_MASK: u16 = 0x0E00;
+
+/// Flags describing the module rather than the content, kept across writes.
+pub const MODULE_FLAGS: u16 = FLAG_LOCKED | FLAG_HISTORY;
+
+/// All flags known to this version of the tool, along with their names.
+pub const FLAG_NAMES: [(u16, &str); 10] = [
+    (FLAG_DIGEST, "digest"),
+    (FLAG_COMPRESSED, "compressed"),
+    (FLAG_ENCRYPTED, "encrypted"),
+    (FLAG_SLOTS, "slots"),
+    (FLAG_LOCKED, "locked"),
+    (FLAG_HISTORY, "history"),
+    (FLAG_DIRTY, "dirty"),
+    (FLAG_AB, "ab"),
+    (FLAG_FULL_CRC, "full-crc"),
+    (FLAG_EXTERNAL_CRC, "external-crc"),
+];
+
+/// Flags set in `flags` that are unknown to this version of the tool, the content type hint aside.
+pub fn unknown_flags(flags: u16) -> u16 {
+    FLAG_NAMES.iter().fold(flags & !CONTENT_TYPE_MASK, |flags, (flag, _)| flags & !flag)
+}
+
+/// Names of the known flags set in `flags`.
+pub fn flag_names(flags: u16) -> Vec<&'static str> {
+    FLAG_NAMES.iter().filter(|(flag, _)| flags & flag != 0).map(|(_, name)| *name).collect()
+}
+
+/// Bincode configuration of the v1 metadata: fixed-size, little-endian integers.
+///
+/// This is what `bincode::serialize` used when v1 was defined, spelled out so that the layout does not depend on
+/// the defaults of the bincode version in use.
+fn v1_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+}
+
+/// Encoder of the v1 metadata block, see `Metadata`.
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Through bincode with `v1_options`, as v1 blocks have always been encoded.
+    #[default]
+    Bincode,
+    /// The layout of v1 blocks written by hand, giving the same bytes as bincode does today whichever version of
+    /// bincode is in use, so that bincode can be upgraded without devices in the field becoming unreadable.
+    V1,
+}
+
+/// Metadata stored in the memory (format v1).
+///
+/// Serialized with `v1_options`, or by hand with `Layout::V1`, all multi-byte fields being little-endian:
+///
+/// | Bytes    | Field          |
+/// |----------|----------------|
+/// | `0..28`  | `unused`       |
+/// | `28..30` | `content_crc`  |
+/// | `30..32` | `content_size` |
+///
+/// Note: If you modify this structure, take care to ensure backwards compatiblity.
+#[repr(C)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub unused: [u8; 28],
+    pub content_crc: u16,
+    pub content_size: u16,
+}
+
+impl Metadata {
+    pub fn to_bytes(&self, layout: Layout) -> [u8; METADATA_SIZE] {
+        match layout {
+            // Unwrap should always succeed.
+            Layout::Bincode => v1_options().serialize(self).unwrap().try_into().unwrap(),
+            Layout::V1 => {
+                let mut bytes = [0; METADATA_SIZE];
+
+                bytes[0..28].copy_from_slice(&self.unused);
+                bytes[28..30].copy_from_slice(&self.content_crc.to_le_bytes());
+                bytes[30..32].copy_from_slice(&self.content_size.to_le_bytes());
+
+                bytes
+            }
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8; METADATA_SIZE], layout: Layout) -> Result<Self, String> {
+        match layout {
+            Layout::Bincode => v1_options().deserialize(bytes).map_err(|error| error.to_string()),
+            Layout::V1 => Ok(Self {
+                unused: bytes[0..28].try_into().unwrap(),
+                content_crc: u16::from_le_bytes([bytes[28], bytes[29]]),
+                content_size: u16::from_le_bytes([bytes[30], bytes[31]]),
+            }),
+        }
+    }
+}
+
+/// Metadata stored in the memory (format v2).
+///
+/// The block is (de)serialized by hand, all multi-byte fields being little-endian:
+///
+/// | Bytes    | Field             |
+/// |----------|-------------------|
+/// | `0..2`   | `MAGIC`           |
+/// | `2`      | `VERSION_2`       |
+/// | `3..5`   | `flags`           |
+/// | `5..13`  | `payload_version` |
+/// | `13..16` | `reserved`        |
+/// | `16..28` | `serial`          |
+/// | `28..30` | `content_crc`     |
+/// | `30..32` | `content_size`    |
+///
+/// `content_crc` and `content_size` are at the same place as in v1, so that older versions of this tool can
+/// still read out files written with a v2 header.
+///
+/// The v3 block has the same layout with `VERSION_3`, except that bytes `13..15` of `reserved` hold a CRC of the
+/// whole block computed with these two bytes zeroed, so that corrupted metadata is detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataV2 {
+    /// Feature flags, see the `FLAG_*` constants. Unassigned bits are reserved and must be zero.
+    pub flags: u16,
+    /// Version tag of the content format, ASCII and zero-padded. All zero if not set.
+    pub payload_version: [u8; PAYLOAD_VERSION_SIZE],
+    /// Reserved for future use. Must be zero for now.
+    pub reserved: [u8; 3],
+    /// Serial number of the module, ASCII and zero-padded. All zero if not set.
+    pub serial: [u8; SERIAL_SIZE],
+    pub content_crc: u16,
+    pub content_size: u16,
+}
+
+impl MetadataV2 {
+    pub fn to_bytes(&self) -> [u8; METADATA_SIZE] {
+        let mut bytes = [0; METADATA_SIZE];
+
+        bytes[0..2].copy_from_slice(&MAGIC);
+        bytes[2] = VERSION_2;
+        bytes[3..5].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[5..13].copy_from_slice(&self.payload_version);
+        bytes[13..16].copy_from_slice(&self.reserved);
+        bytes[16..28].copy_from_slice(&self.serial);
+        bytes[28..30].copy_from_slice(&self.content_crc.to_le_bytes());
+        bytes[30..32].copy_from_slice(&self.content_size.to_le_bytes());
+
+        bytes
+    }
+
+    /// Parse a v2 (or v3) metadata block. Returns `None` if the magic or version does not match.
+    pub fn from_bytes(bytes: &[u8; METADATA_SIZE]) -> Option<Self> {
+        if bytes[0..2] != MAGIC || !matches!(bytes[2], VERSION_2 | VERSION_3) {
+            return None;
+        }
+
+        Some(Self {
+            flags: u16::from_le_bytes([bytes[3], bytes[4]]),
+            payload_version: bytes[5..13].try_into().unwrap(),
+            reserved: bytes[13..16].try_into().unwrap(),
+            serial: bytes[16..28].try_into().unwrap(),
+            content_crc: u16::from_le_bytes([bytes[28], bytes[29]]),
+            content_size: u16::from_le_bytes([bytes[30], bytes[31]]),
+        })
+    }
+}
+
+/// CRC of a v3 metadata block, computed with the bytes holding it zeroed.
+fn metadata_crc(bytes: &[u8; METADATA_SIZE]) -> u16 {
+    let mut bytes = *bytes;
+    bytes[METADATA_CRC_RANGE].fill(0);
+
+    METADATA_CRC.checksum(&bytes)
+}
+
+/// Decode a zero-padded string field, each byte as the character of the same code point (Latin-1), so that
+/// `to_padded` gives the stored bytes back.
+pub fn from_padded(bytes: &[u8]) -> String {
+    bytes.iter().take_while(|&&byte| byte != 0).map(|&byte| byte as char).collect()
+}
+
+/// Encode a string into a zero-padded field, each character as a single byte as `from_padded` decodes it, truncating
+/// it if needed. Characters past U+00FF, which no field read from EEPROM holds, are stored as `?`.
+pub fn to_padded<const N: usize>(value: &str) -> [u8; N] {
+    let mut bytes = [0; N];
+
+    for (byte, char) in bytes.iter_mut().zip(value.chars()) {
+        *byte = u8::try_from(char).unwrap_or(b'?');
+    }
+
+    bytes
+}
+
+/// Format of the metadata block.
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    V1,
+    V2,
+    #[default]
+    V3,
+}
+
+impl Format {
+    /// Range of the bytes of the metadata block not used by any field.
+    pub fn reserved_range(self) -> Range<usize> {
+        match self {
+            Format::V1 => 0..28,
+            Format::V2 => 13..16,
+            Format::V3 => 15..16,
+        }
+    }
+
+    /// Whether the format has the fields added by v2 (flags, serial number, payload version).
+    pub fn is_v2_or_later(self) -> bool {
+        self != Format::V1
+    }
+}
+
+/// Failure to parse a metadata block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// Every byte is 0xFF, as in an EEPROM never written since it left the factory.
+    Blank,
+    /// The block is not valid metadata, along with the reason.
+    Invalid(String),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Blank => f.write_str("EEPROM is blank (factory default)"),
+            ParseError::Invalid(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// Information about the stored file, independent of the metadata format it was read from.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub format: Format,
+    pub flags: u16,
+    /// Bytes of the metadata block not used by any field, see `Format::reserved_range`. Zero-padded or truncated
+    /// to fit when serialized.
+    pub reserved: Vec<u8>,
+    /// Serial number of the module, empty if not set, one character per stored byte (see `from_padded`). Only stored
+    /// with v2 metadata.
+    pub serial: String,
+    /// Version tag of the content format, empty if not set, one character per stored byte. Only stored with v2
+    /// metadata.
+    pub payload_version: String,
+    pub content_crc: u16,
+    pub content_size: u16,
+}
+
+impl FileInfo {
+    /// Parse a metadata block of any format.
+    pub fn parse(bytes: &[u8; METADATA_SIZE]) -> Result<Self, ParseError> {
+        Self::parse_with(bytes, Layout::default())
+    }
+
+    /// Parse a metadata block of any format, decoding v1 blocks with `layout`.
+    pub fn parse_with(bytes: &[u8; METADATA_SIZE], layout: Layout) -> Result<Self, ParseError> {
+        if bytes.iter().all(|&byte| byte == 0xFF) {
+            return Err(ParseError::Blank);
+        }
+
+        if bytes[0..2] == MAGIC {
+            let format = if bytes[2] == VERSION_3 { Format::V3 } else { Format::V2 };
+
+            if format == Format::V3 && u16::from_le_bytes([bytes[13], bytes[14]]) != metadata_crc(bytes) {
+                return Err(ParseError::Invalid("metadata is corrupted, its CRC does not match the CRC stored in it".to_string()));
+            }
+
+            return match MetadataV2::from_bytes(bytes) {
+                Some(metadata) => Ok(Self {
+                    format,
+                    flags: metadata.flags,
+                    reserved: metadata.reserved[3 - format.reserved_range().len()..].to_vec(),
+                    serial: from_padded(&metadata.serial),
+                    payload_version: from_padded(&metadata.payload_version),
+                    content_crc: metadata.content_crc,
+                    content_size: metadata.content_size,
+                }),
+                None => Err(ParseError::Invalid(format!("unsupported metadata version {}", bytes[2]))),
+            };
+        }
+
+        let metadata = Metadata::from_bytes(bytes, layout).map_err(ParseError::Invalid)?;
+
+        Ok(Self {
+            format: Format::V1,
+            flags: 0,
+            reserved: metadata.unused.to_vec(),
+            serial: String::new(),
+            payload_version: String::new(),
+            content_crc: metadata.content_crc,
+            content_size: metadata.content_size,
+        })
+    }
+
+    pub fn has_digest(&self) -> bool {
+        self.flags & FLAG_DIGEST != 0
+    }
+
+    pub fn has_slots(&self) -> bool {
+        self.flags & FLAG_SLOTS != 0
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.flags & FLAG_LOCKED != 0
+    }
+
+    pub fn has_history(&self) -> bool {
+        self.flags & FLAG_HISTORY != 0
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.flags & FLAG_DIRTY != 0
+    }
+
+    pub fn has_ab(&self) -> bool {
+        self.flags & FLAG_AB != 0
+    }
+
+    pub fn has_full_crc(&self) -> bool {
+        self.flags & FLAG_FULL_CRC != 0
+    }
+
+    pub fn has_external_crc(&self) -> bool {
+        self.flags & FLAG_EXTERNAL_CRC != 0
+    }
+
+    /// Serialize into a metadata block of the given format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with(Layout::default())
+    }
+
+    /// Serialize into a metadata block of the given format, encoding v1 blocks with `layout`.
+    pub fn to_bytes_with(&self, layout: Layout) -> Vec<u8> {
+        let mut reserved = self.reserved.clone();
+        reserved.resize(self.format.reserved_range().len(), 0);
+
+        match self.format {
+            Format::V1 => {
+                Metadata {
+                    unused: reserved.try_into().unwrap(),
+                    content_crc: self.content_crc,
+                    content_size: self.content_size,
+                }.to_bytes(layout).to_vec()
+            }
+            Format::V2 | Format::V3 => {
+                let mut padded_reserved = [0; 3];
+                padded_reserved[3 - reserved.len()..].copy_from_slice(&reserved);
+
+                let mut bytes = MetadataV2 {
+                    flags: self.flags,
+                    reserved: padded_reserved,
+                    serial: to_padded(&self.serial),
+                    payload_version: to_padded(&self.payload_version),
+                    content_crc: self.content_crc,
+                    content_size: self.content_size,
+                }.to_bytes();
+
+                if self.format == Format::V3 {
+                    bytes[2] = VERSION_3;
+                    let crc = metadata_crc(&bytes);
+                    bytes[METADATA_CRC_RANGE].copy_from_slice(&crc.to_le_bytes());
+                }
+
+                bytes.to_vec()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_serialized_size_matches_struct() {
+        let bytes = bincode::serialize(&Metadata {
+            unused: [0xAA; 28],
+            content_crc: 0,
+            content_size: 0,
+        }).unwrap();
+
+        assert_eq!(bytes.len(), std::mem::size_of::<Metadata>());
+        assert_eq!(bytes.len(), METADATA_SIZE);
+    }
+
+    #[test]
+    fn v1_field_placement() {
+        let bytes = bincode::serialize(&Metadata {
+            unused: [0xAA; 28],
+            content_crc: 0x1234,
+            content_size: 0x5678,
+        }).unwrap();
+
+        assert_eq!(&bytes[0..28], &[0xAA; 28]);
+        assert_eq!(&bytes[28..30], &[0x34, 0x12]);
+        assert_eq!(&bytes[30..32], &[0x78, 0x56]);
+
+        let metadata = bincode::deserialize::<Metadata>(bytes.as_slice()).unwrap();
+
+        assert_eq!(metadata.unused, [0xAA; 28]);
+        assert_eq!(metadata.content_crc, 0x1234);
+        assert_eq!(metadata.content_size, 0x5678);
+    }
+
+    #[test]
+    fn v1_wire_bytes() {
+        let bytes = FileInfo { format: Format::V1, content_crc: 0xBEEF, content_size: 0x1234, ..Default::default() }.to_bytes();
+        let mut expected = [0; METADATA_SIZE];
+        expected[28..32].copy_from_slice(&[0xEF, 0xBE, 0x34, 0x12]);
+
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn reads_v1_image() {
+        let bytes = bincode::serialize(&Metadata {
+            unused: Default::default(),
+            content_crc: 0xBEEF,
+            content_size: 1234,
+        }).unwrap();
+
+        let info = FileInfo::parse(bytes.as_slice().try_into().unwrap()).unwrap();
+
+        assert_eq!(info, FileInfo { format: Format::V1, reserved: vec![0; 28], content_crc: 0xBEEF, content_size: 1234, ..Default::default() });
+    }
+
+    #[test]
+    fn round_trips_v1() {
+        let info = FileInfo { format: Format::V1, reserved: vec![7; 28], content_crc: 0x1234, content_size: 42, ..Default::default() };
+        let bytes = info.to_bytes();
+
+        assert_eq!(bytes.len(), METADATA_SIZE);
+        assert_eq!(FileInfo::parse(bytes.as_slice().try_into().unwrap()).unwrap(), info);
+    }
+
+    #[test]
+    fn round_trips_v2() {
+        let info = FileInfo {
+            format: Format::V2,
+            flags: 0x0102,
+            reserved: vec![7; 3],
+            serial: "VK-000123".to_string(),
+            payload_version: "calib-v3".to_string(),
+            content_crc: 0xCAFE,
+            content_size: 8160,
+        };
+        let bytes = info.to_bytes();
+
+        assert_eq!(bytes.len(), METADATA_SIZE);
+        assert_eq!(&bytes[0..3], b"VK\x02");
+        assert_eq!(FileInfo::parse(bytes.as_slice().try_into().unwrap()).unwrap(), info);
+    }
+
+    #[test]
+    fn string_fields_keep_their_bytes() {
+        let mut bytes: [u8; METADATA_SIZE] = FileInfo { format: Format::V2, ..Default::default() }.to_bytes().try_into().unwrap();
+        bytes[16..23].copy_from_slice(b"VK\xE90001");
+
+        let info = FileInfo::parse(&bytes).unwrap();
+
+        assert_eq!(info.serial, "VK\u{E9}0001");
+        assert_eq!(info.to_bytes(), bytes);
+        assert_eq!(to_padded::<4>("a\u{20AC}bcd"), *b"a?bc");
+    }
+
+    #[test]
+    fn pads_reserved_bytes() {
+        let info = FileInfo { format: Format::V1, reserved: vec![1, 2, 3], ..Default::default() };
+        let bytes = info.to_bytes();
+
+        assert_eq!(&bytes[0..4], &[1, 2, 3, 0]);
+        assert_eq!(FileInfo::parse(bytes.as_slice().try_into().unwrap()).unwrap().reserved.len(), 28);
+    }
+
+    #[test]
+    fn v2_keeps_v1_field_positions() {
+        let bytes = FileInfo { format: Format::V2, content_crc: 0xCAFE, content_size: 100, ..Default::default() }.to_bytes();
+        let metadata = bincode::deserialize::<Metadata>(bytes.as_slice()).unwrap();
+
+        assert_eq!(metadata.content_crc, 0xCAFE);
+        assert_eq!(metadata.content_size, 100);
+    }
+
+    #[test]
+    fn decodes_flags() {
+        assert_eq!(flag_names(FLAG_DIGEST | FLAG_ENCRYPTED | 0x8000), ["digest", "encrypted"]);
+        assert_eq!(unknown_flags(FLAG_DIGEST | FLAG_ENCRYPTED | 0x8000), 0x8000);
+        assert_eq!(unknown_flags(FLAG_COMPRESSED), 0);
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut bytes = [0; METADATA_SIZE];
+        bytes[0..3].copy_from_slice(b"VK\x04");
+
+        assert!(FileInfo::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn round_trips_v3() {
+        let info = FileInfo {
+            format: Format::V3,
+            flags: FLAG_DIGEST,
+            reserved: vec![7],
+            serial: "VK-000123".to_string(),
+            payload_version: "calib-v3".to_string(),
+            content_crc: 0xCAFE,
+            content_size: 8160,
+        };
+        let bytes = info.to_bytes();
+
+        assert_eq!(&bytes[0..3], b"VK\x03");
+        assert_eq!(bytes[15], 7);
+        assert_eq!(FileInfo::parse(bytes.as_slice().try_into().unwrap()).unwrap(), info);
+    }
+
+    #[test]
+    fn detects_corrupted_v3_metadata() {
+        let mut bytes: [u8; METADATA_SIZE] = FileInfo { format: Format::V3, content_size: 100, ..Default::default() }
+            .to_bytes().try_into().unwrap();
+
+        // A flipped bit in the content size.
+        bytes[31] ^= 0x04;
+
+        assert!(matches!(FileInfo::parse(&bytes), Err(ParseError::Invalid(reason)) if reason.contains("metadata is corrupted")));
+    }
+
+    #[test]
+    fn detects_blank_header() {
+        assert_eq!(FileInfo::parse(&[0xFF; METADATA_SIZE]), Err(ParseError::Blank));
+    }
+
+    #[test]
+    fn zeroed_header_is_an_empty_v1_file() {
+        assert_eq!(FileInfo::parse(&[0; METADATA_SIZE]).unwrap(), FileInfo { format: Format::V1, reserved: vec![0; 28], ..Default::default() });
+    }
+
+    #[test]
+    fn garbage_header_is_not_blank() {
+        let mut bytes = [0xFF; METADATA_SIZE];
+        bytes[7] = 0x12;
+
+        assert_eq!(FileInfo::parse(&bytes).unwrap().content_size, 0xFFFF);
+
+        bytes[0..3].copy_from_slice(b"VK\xFF");
+        assert!(matches!(FileInfo::parse(&bytes), Err(ParseError::Invalid(_))));
+    }
+
+    fn file_info() -> impl proptest::strategy::Strategy<Value = FileInfo> {
+        use proptest::prelude::*;
+
+        let v1 = (prop::array::uniform28(any::<u8>()), any::<u16>(), any::<u16>()).prop_map(|(reserved, content_crc, content_size)| {
+            FileInfo { format: Format::V1, reserved: reserved.to_vec(), content_crc, content_size, ..Default::default() }
+        });
+        let v2 = (
+            prop_oneof![Just(Format::V2), Just(Format::V3)],
+            any::<u16>(),
+            prop::collection::vec(any::<u8>(), 3),
+            "[\\x{01}-\\x{FF}]{0,12}",
+            "[\\x{01}-\\x{FF}]{0,8}",
+            any::<u16>(),
+            any::<u16>(),
+        ).prop_map(|(format, flags, mut reserved, serial, payload_version, content_crc, content_size)| {
+            reserved.truncate(format.reserved_range().len());
+            FileInfo { format, flags, reserved, serial, payload_version, content_crc, content_size }
+        });
+
+        // A v1 block starting with the magic reads as v2, and one of only 0xFF as blank.
+        let v1 = v1.prop_filter("ambiguous v1 block", |info| info.reserved[0..2] != MAGIC && info.to_bytes().iter().any(|&byte| byte != 0xFF));
+
+        prop_oneof![v1, v2]
+    }
+
+    #[test]
+    fn v1_layout_matches_bincode() {
+        let metadata = Metadata { unused: std::array::from_fn(|index| index as u8), content_crc: 0xBEEF, content_size: 0x1234 };
+        let bytes = metadata.to_bytes(Layout::V1);
+
+        assert_eq!(bytes, metadata.to_bytes(Layout::Bincode));
+        assert_eq!(&bytes[28..32], &[0xEF, 0xBE, 0x34, 0x12]);
+        assert_eq!(Metadata::from_bytes(&bytes, Layout::V1), Ok(metadata.clone()));
+        assert_eq!(Metadata::from_bytes(&bytes, Layout::Bincode), Ok(metadata));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn layouts_encode_alike(unused: [u8; 28], content_crc: u16, content_size: u16) {
+            let metadata = Metadata { unused, content_crc, content_size };
+
+            proptest::prop_assert_eq!(metadata.to_bytes(Layout::V1), metadata.to_bytes(Layout::Bincode));
+        }
+
+        #[test]
+        fn layouts_decode_alike(bytes: [u8; METADATA_SIZE]) {
+            proptest::prop_assert_eq!(Metadata::from_bytes(&bytes, Layout::V1), Metadata::from_bytes(&bytes, Layout::Bincode));
+            proptest::prop_assert_eq!(FileInfo::parse_with(&bytes, Layout::V1), FileInfo::parse_with(&bytes, Layout::Bincode));
+        }
+
+        #[test]
+        fn parse_never_panics(bytes: [u8; METADATA_SIZE]) {
+            let _ = FileInfo::parse(&bytes);
+        }
+
+        #[test]
+        fn file_info_round_trips(info in file_info()) {
+            let bytes = info.to_bytes();
+
+            proptest::prop_assert_eq!(FileInfo::parse(bytes.as_slice().try_into().unwrap()), Ok(info));
+        }
+    }
+}
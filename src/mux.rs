@@ -0,0 +1,30 @@
+//! PCA9548-style I2C multiplexer, connecting the upstream bus to any of its downstream channels.
+//!
+//! The mux has a single control register, written without any address byte: bit `n` set connects channel `n`.
+
+use crate::device::Device;
+
+/// Number of downstream channels.
+pub const CHANNEL_COUNT: u8 = 8;
+
+/// Control register value connecting only `channel`, or no channel at all if `None`.
+pub fn control_byte(channel: Option<u8>) -> u8 {
+    channel.map_or(0, |channel| 1 << channel)
+}
+
+/// Connect only `channel` to the upstream bus, or disconnect all channels if `None`.
+pub fn select<D: Device>(mux: &mut D, channel: Option<u8>) -> Result<(), D::Error> {
+    mux.write(&[control_byte(channel)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_a_single_channel() {
+        assert_eq!(control_byte(Some(0)), 0b0000_0001);
+        assert_eq!(control_byte(Some(7)), 0b1000_0000);
+        assert_eq!(control_byte(None), 0);
+    }
+}
@@ -0,0 +1,110 @@
+//! Splitting of writes along the physical pages of the EEPROM.
+//!
+//! A single write transaction must stay within one page: on 24xx parts, bytes written past the end of a page wrap
+//! around to its start and silently overwrite it.
+
+use std::ops::Range;
+
+/// Page size of the MK24C64.
+pub const DEFAULT_PAGE_SIZE: u16 = 32;
+
+/// Largest page size supported.
+pub const MAX_PAGE_SIZE: u16 = 256;
+
+/// Split `size` bytes written from address `offset` into chunks that each fill at most the rest of a page, given as
+/// the address of the chunk and its range within the bytes written. The first chunk is short if `offset` is not at
+/// the start of a page.
+pub fn chunks(offset: u16, size: usize, page_size: u16) -> impl Iterator<Item = (u16, Range<usize>)> {
+    let mut start = 0;
+
+    std::iter::from_fn(move || {
+        if start >= size {
+            return None;
+        }
+
+        let address = offset as usize + start;
+        let end = size.min(start + page_size as usize - address % page_size as usize);
+        let chunk = (address as u16, start..end);
+
+        start = end;
+        Some(chunk)
+    })
+}
+
+/// Largest page size, a power of two, not above `page_size` and writing at most `max_size` bytes (at least 1) at a
+/// time, for adapters limiting the size of a write. It still splits writes along the physical pages, as it divides them.
+pub fn fit_page_size(page_size: u16, max_size: usize) -> u16 {
+    1 << max_size.min(page_size as usize).ilog2()
+}
+
+/// Parse a page size, which must be a power of two up to `MAX_PAGE_SIZE`.
+pub fn parse_page_size(value: &str) -> Result<u16, String> {
+    match value.parse::<u16>() {
+        Ok(page_size) if page_size.is_power_of_two() && page_size <= MAX_PAGE_SIZE => Ok(page_size),
+        _ => Err(format!("must be a power of two up to {MAX_PAGE_SIZE}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(offset: u16, size: usize, page_size: u16) -> Vec<(u16, Range<usize>)> {
+        chunks(offset, size, page_size).collect()
+    }
+
+    #[test]
+    fn aligned_offset_gives_full_pages() {
+        assert_eq!(collect(32, 70, 32), [(32, 0..32), (64, 32..64), (96, 64..70)]);
+        assert_eq!(collect(128, 128, 64), [(128, 0..64), (192, 64..128)]);
+        assert_eq!(collect(0, 100, 128), [(0, 0..100)]);
+    }
+
+    #[test]
+    fn unaligned_offset_gives_short_first_chunk() {
+        assert_eq!(collect(32, 100, 64), [(32, 0..32), (64, 32..96), (128, 96..100)]);
+        assert_eq!(collect(40, 30, 32), [(40, 0..24), (64, 24..30)]);
+        assert_eq!(collect(200, 300, 128), [(200, 0..56), (256, 56..184), (384, 184..300)]);
+        assert_eq!(collect(10, 4, 32), [(10, 0..4)]);
+    }
+
+    #[test]
+    fn chunks_never_cross_a_page_boundary() {
+        for page_size in [8, 16, 32, 64, 128, 256] {
+            for offset in [0, 1, 31, 32, 33, 100, 255, 8000] {
+                let chunks = collect(offset, 300, page_size);
+
+                assert_eq!(chunks.first().map(|(address, _)| *address), Some(offset));
+                assert_eq!(chunks.last().map(|(_, range)| range.end), Some(300));
+
+                for (address, range) in chunks {
+                    let page = address / page_size;
+                    let last = address + range.len() as u16 - 1;
+
+                    assert_eq!(last / page_size, page, "offset {offset}, page size {page_size}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_write_has_no_chunks() {
+        assert!(collect(32, 0, 32).is_empty());
+    }
+
+    #[test]
+    fn page_size_must_be_a_power_of_two() {
+        assert_eq!(parse_page_size("64"), Ok(64));
+        assert!(parse_page_size("48").is_err());
+        assert!(parse_page_size("512").is_err());
+        assert!(parse_page_size("0").is_err());
+    }
+
+    #[test]
+    fn page_size_fits_limited_writes() {
+        assert_eq!(fit_page_size(32, 31), 16);
+        assert_eq!(fit_page_size(32, 32), 32);
+        assert_eq!(fit_page_size(32, 1000), 32);
+        assert_eq!(fit_page_size(64, 1), 1);
+    }
+}
@@ -0,0 +1,62 @@
+//! ACK polling: after a write, the EEPROM does not acknowledge its address until its internal write cycle is
+//! complete, which lets us wait exactly as long as needed instead of sleeping for a fixed duration.
+
+use std::time::{Duration, Instant};
+use crate::device::{Device, PlatformDevice};
+use crate::geometry::Geometry;
+
+/// Default upper bound on how long the device may take to finish a write cycle.
+pub const POLL_TIMEOUT: Duration = Duration::from_millis(25);
+
+/// Check whether the adapter supports the plain I2C writes used for ACK polling. Simulated EEPROMs NACK during their
+/// write cycles as real ones do.
+pub fn is_supported(device: &PlatformDevice) -> bool {
+    device.supports_i2c()
+}
+
+/// Failure of ACK polling.
+#[derive(Debug)]
+pub enum PollError<E> {
+    /// The device still did not acknowledge after the timeout, along with the last error.
+    Timeout(E),
+    /// Polling failed with an error other than the device not acknowledging, e.g. a bus failure.
+    Bus(E),
+}
+
+/// Wait until the device acknowledges its address again, i.e. it has finished its internal write cycle, for up to
+/// `timeout`, and return how long that took.
+///
+/// Polling is done by setting the address pointer, sent as `geometry` says, which does not start a new write cycle.
+pub fn wait_for_ack<D: Device>(device: &mut D, geometry: &Geometry, timeout: Duration) -> Result<Duration, PollError<D::Error>> {
+    let start = Instant::now();
+    let address = geometry.address(0);
+
+    loop {
+        match device.write(&address) {
+            Ok(()) => return Ok(start.elapsed()),
+            Err(error) if !D::is_nack(&error) => return Err(PollError::Bus(error)),
+            Err(error) if start.elapsed() >= timeout => return Err(PollError::Timeout(error)),
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::mock::MockEeprom;
+
+    #[test]
+    fn waits_until_the_write_cycle_completes() {
+        let mut device = MockEeprom::new(64);
+
+        device.write_cycle_nacks = 5;
+        device.write(&[0, 0, 0x42]).unwrap();
+        wait_for_ack(&mut device, &Geometry::default(), POLL_TIMEOUT).unwrap();
+        assert_eq!(device.memory[0], 0x42);
+
+        device.write_cycle_nacks = usize::MAX;
+        device.write(&[0, 1, 0x42]).unwrap();
+        assert!(matches!(wait_for_ack(&mut device, &Geometry::default(), Duration::from_millis(5)), Err(PollError::Timeout(_))));
+    }
+}
@@ -0,0 +1,207 @@
+//! Retrying of I2C transfers, which occasionally fail on a bus shared with other devices, e.g. when losing
+//! arbitration or when a slow clock stretch times out.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::device::Device;
+
+/// Default delay before the first retry.
+pub const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(1);
+
+/// Default upper bound on the delay between retries.
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(50);
+
+/// Delays between retries: exponential, capped, and optionally randomized so that controllers sharing a bus do not
+/// keep retrying in lockstep and colliding again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// Delay before the first retry, doubled before each following one.
+    pub initial: Duration,
+    /// Upper bound on the delay.
+    pub max: Duration,
+    /// Pick each delay at random between half and all of its exponential value.
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff { initial: DEFAULT_INITIAL_DELAY, max: DEFAULT_MAX_DELAY, jitter: false }
+    }
+}
+
+impl Backoff {
+    /// Delay before the retry numbered `retry`, starting from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self.initial.saturating_mul(2_u32.saturating_pow(retry.saturating_sub(1))).min(self.max);
+
+        match self.jitter {
+            true => delay / 2 + delay.mul_f64(random_fraction() / 2.0),
+            false => delay,
+        }
+    }
+}
+
+/// State of the pseudo-random generator used for jitter, seeded from the clock on first use.
+static RANDOM_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Pseudo-random number in `[0, 1)`, good enough to spread retries apart.
+fn random_fraction() -> f64 {
+    (random() >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// Pseudo-random non-zero number, from the generator used for jitter.
+pub fn random() -> u64 {
+    let mut state = RANDOM_STATE.load(Ordering::Relaxed);
+
+    if state == 0 {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+        state = (seed ^ ((std::process::id() as u64) << 32)) | 1;
+    }
+
+    state = xorshift64(state);
+    RANDOM_STATE.store(state, Ordering::Relaxed);
+
+    state
+}
+
+/// Next state of the xorshift64 pseudo-random generator, which must not be seeded with 0.
+pub fn xorshift64(mut state: u64) -> u64 {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    state
+}
+
+/// Run `transfer` on `device`, retrying it up to `retries` times with the delays of `backoff` if it fails, and
+/// return its last result. `on_retry` is called with the number of the retry and the error before each retry.
+///
+/// `transfer` must be a whole transaction, so that a retry starts over from its beginning (e.g. from the start of a
+/// page for a page write).
+pub fn retry<D: Device, T>(
+    device: &mut D,
+    retries: u32,
+    backoff: &Backoff,
+    mut transfer: impl FnMut(&mut D) -> Result<T, D::Error>,
+    mut on_retry: impl FnMut(u32, &D::Error),
+) -> Result<T, D::Error> {
+    let mut retry = 0;
+
+    loop {
+        match transfer(device) {
+            Err(error) if retry < retries => {
+                retry += 1;
+                on_retry(retry, &error);
+
+                std::thread::sleep(backoff.delay(retry));
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device failing its first `failures` transfers, and recording the data of every write attempted.
+    struct FlakyDevice {
+        failures: u32,
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl FlakyDevice {
+        fn transfer(&mut self) -> Result<(), std::io::Error> {
+            if self.failures == 0 {
+                return Ok(());
+            }
+
+            self.failures -= 1;
+            Err(std::io::Error::from_raw_os_error(libc::EAGAIN))
+        }
+    }
+
+    impl Device for FlakyDevice {
+        type Error = std::io::Error;
+
+        fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.writes.push(data.to_vec());
+            self.transfer()
+        }
+
+        fn write_read(&mut self, _data: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+            self.transfer()?;
+            buffer.fill(0xAB);
+            Ok(())
+        }
+
+        fn is_nack(_error: &Self::Error) -> bool {
+            false
+        }
+
+        fn errno(error: &Self::Error) -> Option<i32> {
+            error.raw_os_error()
+        }
+
+        fn max_write_size(&self) -> Option<usize> {
+            None
+        }
+    }
+
+    #[test]
+    fn succeeds_after_transient_failures() {
+        let mut device = FlakyDevice { failures: 2, writes: Vec::new() };
+        let mut retried = Vec::new();
+        let mut buffer = [0; 4];
+
+        retry(&mut device, 3, &Backoff::default(), |device| device.write_read(&[0, 0], &mut buffer), |retry, _| retried.push(retry)).unwrap();
+
+        assert_eq!(retried, [1, 2]);
+        assert_eq!(buffer, [0xAB; 4]);
+    }
+
+    #[test]
+    fn fails_once_retries_are_exhausted() {
+        let mut device = FlakyDevice { failures: 4, writes: Vec::new() };
+
+        let result = retry(&mut device, 3, &Backoff::default(), |device| device.write(&[0, 32, 1, 2]), |_, _| {});
+
+        assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EAGAIN));
+        assert_eq!(device.writes.len(), 4);
+    }
+
+    #[test]
+    fn rewrites_the_whole_page_on_retry() {
+        let mut device = FlakyDevice { failures: 1, writes: Vec::new() };
+        let page = [0, 32, 1, 2, 3];
+
+        retry(&mut device, 3, &Backoff::default(), |device| device.write(&page), |_, _| {}).unwrap();
+
+        assert_eq!(device.writes, [page.to_vec(), page.to_vec()]);
+    }
+
+    #[test]
+    fn backoff_is_exponential_and_capped() {
+        let backoff = Backoff { initial: Duration::from_millis(2), max: Duration::from_millis(10), jitter: false };
+        let delays: Vec<u128> = (1..=5).map(|retry| backoff.delay(retry).as_millis()).collect();
+
+        assert_eq!(delays, [2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn jitter_stays_within_half_and_all_of_the_delay() {
+        let backoff = Backoff { initial: Duration::from_millis(8), max: Duration::from_millis(8), jitter: true };
+
+        for _ in 0..100 {
+            let delay = backoff.delay(1);
+            assert!(delay >= Duration::from_millis(4) && delay <= Duration::from_millis(8), "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn does_not_retry_without_retries() {
+        let mut device = FlakyDevice { failures: 1, writes: Vec::new() };
+
+        assert!(retry(&mut device, 0, &Backoff::default(), |device| device.write(&[0, 0]), |_, _| {}).is_err());
+        assert_eq!(device.writes.len(), 1);
+    }
+}
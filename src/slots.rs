@@ -0,0 +1,132 @@
+//! Slot table, allowing several independent files to be stored in the same EEPROM.
+//!
+//! The table is stored right after the (v2) metadata block, which has `FLAG_SLOTS` set and whose content CRC and
+//! size describe the table itself. Each slot entry is 8 bytes, all multi-byte fields being little-endian:
+//!
+//! | Bytes  | Field    |
+//! |--------|----------|
+//! | `0..2` | `offset` |
+//! | `2..4` | `size`   |
+//! | `4..6` | `crc`    |
+//! | `6`    | `kind`   |
+//! | `7`    | reserved |
+//!
+//! An entry with a zero offset is unused.
+
+/// Maximum number of slots.
+pub const SLOT_COUNT: usize = 4;
+
+/// Size of a single slot entry in bytes.
+const SLOT_ENTRY_SIZE: usize = 8;
+
+/// Size of the slot table in bytes.
+pub const SLOT_TABLE_SIZE: usize = SLOT_COUNT * SLOT_ENTRY_SIZE;
+
+/// Location and checksum of a file stored in a slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    /// Address in EEPROM of the first byte of the slot content.
+    pub offset: u16,
+    pub size: u16,
+    pub crc: u16,
+    /// Type of the content, free for the user to define.
+    pub kind: u8,
+}
+
+impl Slot {
+    /// Address in EEPROM right after the slot content.
+    pub fn end(&self) -> usize {
+        self.offset as usize + self.size as usize
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SlotTable {
+    pub slots: [Option<Slot>; SLOT_COUNT],
+}
+
+impl SlotTable {
+    pub fn to_bytes(&self) -> [u8; SLOT_TABLE_SIZE] {
+        let mut bytes = [0; SLOT_TABLE_SIZE];
+
+        for (entry, slot) in bytes.chunks_mut(SLOT_ENTRY_SIZE).zip(self.slots.iter()) {
+            if let Some(slot) = slot {
+                entry[0..2].copy_from_slice(&slot.offset.to_le_bytes());
+                entry[2..4].copy_from_slice(&slot.size.to_le_bytes());
+                entry[4..6].copy_from_slice(&slot.crc.to_le_bytes());
+                entry[6] = slot.kind;
+            }
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; SLOT_TABLE_SIZE]) -> Self {
+        let mut table = Self::default();
+
+        for (entry, slot) in bytes.chunks(SLOT_ENTRY_SIZE).zip(table.slots.iter_mut()) {
+            let offset = u16::from_le_bytes([entry[0], entry[1]]);
+
+            if offset != 0 {
+                *slot = Some(Slot {
+                    offset,
+                    size: u16::from_le_bytes([entry[2], entry[3]]),
+                    crc: u16::from_le_bytes([entry[4], entry[5]]),
+                    kind: entry[6],
+                });
+            }
+        }
+
+        table
+    }
+
+    /// Find the slot (other than `index`) whose content overlaps `start..end`, if any.
+    pub fn overlapping(&self, index: usize, start: usize, end: usize) -> Option<usize> {
+        self.slots.iter().enumerate().find_map(|(other, slot)| {
+            let slot = slot.as_ref().filter(|_| other != index)?;
+            (start < slot.end() && (slot.offset as usize) < end).then_some(other)
+        })
+    }
+
+    /// Address right after the content of the last slot, or `None` if all slots are unused.
+    pub fn end(&self) -> Option<usize> {
+        self.slots.iter().flatten().map(Slot::end).max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let mut table = SlotTable::default();
+        table.slots[0] = Some(Slot { offset: 64, size: 100, crc: 0xBEEF, kind: 1 });
+        table.slots[2] = Some(Slot { offset: 192, size: 5, crc: 0x1234, kind: 7 });
+
+        assert_eq!(SlotTable::from_bytes(&table.to_bytes()), table);
+    }
+
+    #[test]
+    fn detects_overlap() {
+        let mut table = SlotTable::default();
+        table.slots[0] = Some(Slot { offset: 64, size: 100, crc: 0, kind: 0 });
+        table.slots[1] = Some(Slot { offset: 192, size: 32, crc: 0, kind: 0 });
+
+        assert_eq!(table.overlapping(2, 160, 192), Some(0));
+        assert_eq!(table.overlapping(2, 164, 192), None);
+        assert_eq!(table.overlapping(2, 164, 193), Some(1));
+        assert_eq!(table.overlapping(0, 64, 192), None);
+        assert_eq!(table.end(), Some(224));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn arbitrary_tables_round_trip(slots: [Option<(u16, u16, u16, u8)>; SLOT_COUNT]) {
+            // An offset of zero marks an empty slot.
+            let table = SlotTable { slots: slots.map(|slot| slot.filter(|&(offset, ..)| offset != 0).map(|(offset, size, crc, kind)| Slot { offset, size, crc, kind })) };
+
+            proptest::prop_assert_eq!(SlotTable::from_bytes(&table.to_bytes()), table);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! Counters of the I2C transfers made and of the time they took, reported with `--stats` to see where the time of a
+//! slow provisioning goes.
+//!
+//! The same counters drive the progress display of long transfers, see `progress`. Bulk transfers are also paced
+//! here for `--throttle`, see `throttle`.
+//!
+//! Nothing is measured unless `enable` or `enable_progress` was called, so that the counters cost a single atomic load
+//! per transfer otherwise.
+
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Whether `--stats` was given.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Smallest transfer, in bytes, showing a progress display, or 0 if progress is not displayed.
+static PROGRESS_THRESHOLD: AtomicU64 = AtomicU64::new(0);
+
+/// Progress display of the transfer in progress, if any.
+static PROGRESS: Mutex<Option<Progress>> = Mutex::new(None);
+
+/// Shortest time between two updates of the progress display.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Width of the progress bar, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Pacing of transfers with `--throttle`.
+static THROTTLE: Throttle = Throttle::new();
+
+/// Direction of a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+/// Counters of the transfers in one direction.
+struct Counters {
+    bytes: AtomicU64,
+    transfers: AtomicU64,
+    /// Time spent in transfers, including retries, in microseconds.
+    time: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters { bytes: AtomicU64::new(0), transfers: AtomicU64::new(0), time: AtomicU64::new(0) }
+    }
+}
+
+/// Pacing of transfers to a rate, see `throttle`.
+struct Throttle {
+    /// Rate transfers are limited to, in bytes per second, or 0 if unlimited.
+    rate: AtomicU64,
+    /// Earliest time the next transfer may start.
+    next_transfer: Mutex<Option<Instant>>,
+    /// Time spent pausing between transfers, in microseconds.
+    time: AtomicU64,
+}
+
+impl Throttle {
+    const fn new() -> Self {
+        Throttle { rate: AtomicU64::new(0), next_transfer: Mutex::new(None), time: AtomicU64::new(0) }
+    }
+
+    fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    /// Wait as long as the rate requires before a transfer of `bytes` bytes.
+    fn pace(&self, bytes: usize) {
+        let rate = self.rate();
+
+        if rate == 0 {
+            return;
+        }
+
+        let mut next_transfer = self.next_transfer.lock().unwrap();
+        let now = Instant::now();
+
+        if let Some(pause) = next_transfer.and_then(|next_transfer| next_transfer.checked_duration_since(now)) {
+            std::thread::sleep(pause);
+            self.time.fetch_add(pause.as_micros() as u64, Ordering::Relaxed);
+        }
+
+        *next_transfer = Some(Instant::now() + Duration::from_secs_f64(bytes as f64 / rate as f64));
+    }
+
+    fn time(&self) -> Duration {
+        Duration::from_micros(self.time.load(Ordering::Relaxed))
+    }
+}
+
+static READS: Counters = Counters::new();
+static WRITES: Counters = Counters::new();
+
+/// Totals of the transfers in one direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Totals {
+    pub bytes: u64,
+    pub transfers: u64,
+    pub time: Duration,
+}
+
+impl Totals {
+    /// Bytes per second over `time`, or 0 if no time was spent.
+    pub fn throughput(&self, time: Duration) -> f64 {
+        match time.is_zero() {
+            true => 0.0,
+            false => self.bytes as f64 / time.as_secs_f64(),
+        }
+    }
+}
+
+/// Start measuring transfers.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Display the progress of transfers of at least `threshold` bytes on stderr, which must be a terminal.
+pub fn enable_progress(threshold: u64) {
+    PROGRESS_THRESHOLD.store(threshold.max(1), Ordering::Relaxed);
+}
+
+/// Limit transfers to `rate` bytes per second on average, leaving the bus idle for other devices in between.
+pub fn set_throttle(rate: u64) {
+    THROTTLE.rate.store(rate, Ordering::Relaxed);
+}
+
+/// Wait as long as `--throttle` requires before a transfer of `bytes` bytes, which must be called before each of the
+/// transfers paced. The wait is only ever between transfers, never within one.
+pub fn throttle(bytes: usize) {
+    THROTTLE.pace(bytes);
+}
+
+/// Run `transfer`, transferring `bytes` bytes (not counting the address) in `direction`, and count it.
+pub fn measure<T>(direction: Direction, bytes: usize, transfer: impl FnOnce() -> T) -> T {
+    if !enabled() && PROGRESS_THRESHOLD.load(Ordering::Relaxed) == 0 {
+        return transfer();
+    }
+
+    let start = Instant::now();
+    let result = transfer();
+
+    let counters = match direction {
+        Direction::Read => &READS,
+        Direction::Write => &WRITES,
+    };
+
+    counters.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    counters.transfers.fetch_add(1, Ordering::Relaxed);
+    counters.time.fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+
+    if let Some(progress) = PROGRESS.lock().unwrap().as_mut().filter(|progress| progress.direction == direction) {
+        progress.update();
+    }
+
+    result
+}
+
+/// Progress display of a transfer, counting the bytes transferred in its direction since it started.
+struct Progress {
+    direction: Direction,
+    /// Bytes transferred in `direction` before the transfer started.
+    start_bytes: u64,
+    total: u64,
+    start: Instant,
+    /// When the display was last updated, if ever.
+    drawn: Option<Instant>,
+}
+
+impl Progress {
+    fn update(&mut self) {
+        let now = Instant::now();
+
+        if self.drawn.is_some_and(|drawn| now - drawn < PROGRESS_INTERVAL) {
+            return;
+        }
+
+        let done = totals(self.direction).bytes - self.start_bytes;
+        let mut stderr = std::io::stderr().lock();
+
+        self.drawn = Some(now);
+        let _ = write!(stderr, "\r\x1b[2K{}", render(self.direction, done, self.total, now - self.start));
+        let _ = stderr.flush();
+    }
+}
+
+/// Display of a transfer in `direction` having transferred `done` of `total` bytes in `elapsed`.
+fn render(direction: Direction, done: u64, total: u64, elapsed: Duration) -> String {
+    // Padding of partial pages can take the bytes written past the total.
+    let done = done.min(total);
+    let filled = (done as usize * BAR_WIDTH).checked_div(total as usize).unwrap_or(BAR_WIDTH);
+    let rate = Totals { bytes: done, transfers: 0, time: elapsed }.throughput(elapsed);
+    let eta = match rate > 0.0 {
+        true => format!("{:.0}s", (total - done) as f64 / rate),
+        false => "?".to_string(),
+    };
+    let label = match direction {
+        Direction::Read => "Reading",
+        Direction::Write => "Writing",
+    };
+
+    format!("{label} [{}{}] {done}/{total} bytes, {rate:.0} bytes/s, ETA {eta}", "#".repeat(filled), " ".repeat(BAR_WIDTH - filled))
+}
+
+/// Display of the progress of a transfer, cleared when dropped.
+pub struct ProgressGuard {
+    /// Whether this guard shows the display, rather than a transfer it is part of.
+    owned: bool,
+}
+
+/// Display the progress of a transfer of `total` bytes in `direction` until the returned guard is dropped, if
+/// progress is displayed, the transfer is large enough and it is not part of a transfer already displayed.
+pub fn progress(direction: Direction, total: usize) -> ProgressGuard {
+    let threshold = PROGRESS_THRESHOLD.load(Ordering::Relaxed);
+
+    if threshold == 0 || (total as u64) < threshold {
+        return ProgressGuard { owned: false };
+    }
+
+    let mut progress = PROGRESS.lock().unwrap();
+
+    if progress.is_some() {
+        return ProgressGuard { owned: false };
+    }
+
+    *progress = Some(Progress {
+        direction,
+        start_bytes: totals(direction).bytes,
+        total: total as u64,
+        start: Instant::now(),
+        drawn: None,
+    });
+
+    ProgressGuard { owned: true }
+}
+
+impl Drop for ProgressGuard {
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+
+        if let Some(Progress { drawn: Some(_), .. }) = PROGRESS.lock().unwrap().take() {
+            eprint!("\r\x1b[2K");
+        }
+    }
+}
+
+/// Totals of the transfers in `direction` so far.
+pub fn totals(direction: Direction) -> Totals {
+    let counters = match direction {
+        Direction::Read => &READS,
+        Direction::Write => &WRITES,
+    };
+
+    Totals {
+        bytes: counters.bytes.load(Ordering::Relaxed),
+        transfers: counters.transfers.load(Ordering::Relaxed),
+        time: Duration::from_micros(counters.time.load(Ordering::Relaxed)),
+    }
+}
+
+/// Summary of the transfers so far for stderr, given the number of `retries`, the number of bytes `corrected` by
+/// majority vote if reads are voted on, and the time spent waiting for write cycles.
+pub fn summary(retries: u64, corrected: Option<u64>, stall_time: Duration) -> String {
+    let reads = totals(Direction::Read);
+    let writes = totals(Direction::Write);
+    let summary = format!(
+        "Read {} bytes in {} transfers in {:.3}s ({:.0} bytes/s).\n\
+         Wrote {} bytes in {} transfers in {:.3}s, plus {:.3}s waiting for write cycles ({:.0} bytes/s overall).\n\
+         Retried {retries} transfers.",
+        reads.bytes, reads.transfers, reads.time.as_secs_f64(), reads.throughput(reads.time),
+        writes.bytes, writes.transfers, writes.time.as_secs_f64(), stall_time.as_secs_f64(), writes.throughput(writes.time + stall_time),
+    );
+    let summary = match corrected {
+        Some(corrected) => format!("{summary}\nCorrected {corrected} bytes read by majority vote."),
+        None => summary,
+    };
+
+    match THROTTLE.rate() {
+        0 => summary,
+        rate => format!(
+            "{summary}\nPaused {:.3}s between transfers for --throttle {rate}, for an effective {:.0} bytes/s.",
+            THROTTLE.time().as_secs_f64(), effective_rate(stall_time),
+        ),
+    }
+}
+
+/// Bytes transferred per second, counting the time spent in transfers, waiting for write cycles and pausing for
+/// `--throttle`.
+fn effective_rate(stall_time: Duration) -> f64 {
+    let reads = totals(Direction::Read);
+    let writes = totals(Direction::Write);
+    let time = reads.time + writes.time + stall_time + THROTTLE.time();
+
+    Totals { bytes: reads.bytes + writes.bytes, transfers: reads.transfers + writes.transfers, time }.throughput(time)
+}
+
+/// Same as `summary`, as a JSON object.
+pub fn json(retries: u64, corrected: Option<u64>, stall_time: Duration) -> String {
+    let reads = totals(Direction::Read);
+    let writes = totals(Direction::Write);
+    let corrected = corrected.map_or("null".to_string(), |corrected| corrected.to_string());
+
+    format!(
+        "{{\"read_bytes\":{},\"read_transfers\":{},\"read_seconds\":{:.6},\"write_bytes\":{},\"write_transfers\":{},\"write_seconds\":{:.6},\"write_cycle_seconds\":{:.6},\"throttle_seconds\":{:.6},\"effective_bytes_per_second\":{:.0},\"retries\":{retries},\"corrected_bytes\":{corrected}}}",
+        reads.bytes, reads.transfers, reads.time.as_secs_f64(), writes.bytes, writes.transfers, writes.time.as_secs_f64(), stall_time.as_secs_f64(),
+        THROTTLE.time().as_secs_f64(), effective_rate(stall_time),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throughput_is_zero_without_time() {
+        let totals = Totals { bytes: 1000, transfers: 4, time: Duration::ZERO };
+
+        assert_eq!(totals.throughput(Duration::ZERO), 0.0);
+        assert_eq!(totals.throughput(Duration::from_millis(500)), 2000.0);
+    }
+
+    #[test]
+    fn throttle_paces_transfers() {
+        // A throttle of its own, as the one of `--throttle` would pace the transfers of the other tests.
+        let throttle = Throttle::new();
+        throttle.rate.store(10_000, Ordering::Relaxed);
+
+        let start = Instant::now();
+
+        // The first transfer starts right away, the next ones once the previous ones had time to go at the rate.
+        for _ in 0..3 {
+            throttle.pace(200);
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(40), "{:?}", start.elapsed());
+        assert!(throttle.time() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn renders_progress() {
+        assert_eq!(
+            render(Direction::Write, 2048, 8192, Duration::from_secs(2)),
+            format!("Writing [{}{}] 2048/8192 bytes, 1024 bytes/s, ETA 6s", "#".repeat(7), " ".repeat(23)),
+        );
+        assert_eq!(render(Direction::Read, 0, 8192, Duration::ZERO), format!("Reading [{}] 0/8192 bytes, 0 bytes/s, ETA ?", " ".repeat(30)));
+        assert!(render(Direction::Write, 9000, 8192, Duration::from_secs(1)).starts_with(&format!("Writing [{}] 8192/8192", "#".repeat(30))));
+    }
+}
@@ -0,0 +1,139 @@
+//! Key-value container, storing small records as a sequence of type-length-value entries in the file content.
+//!
+//! Each entry is laid out as follows, the value length being little-endian:
+//!
+//! | Bytes                   | Field        |
+//! |-------------------------|--------------|
+//! | `0`                     | key length   |
+//! | `1..1+k`                | key (ASCII)  |
+//! | `1+k..3+k`              | value length |
+//! | `3+k..3+k+v`            | value        |
+
+use std::fmt;
+
+/// Maximum length of a key in bytes.
+pub const MAX_KEY_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub value: Vec<u8>,
+}
+
+/// Error while parsing a container, with the offset in the content where parsing failed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.reason, self.offset)
+    }
+}
+
+/// Check that `key` is a short identifier made of ASCII letters, digits, `_`, `-` and `.`.
+pub fn is_valid_key(key: &str) -> bool {
+    (1..=MAX_KEY_LENGTH).contains(&key.len())
+        && key.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"_-.".contains(&byte))
+}
+
+/// Parse the entries laid out back to back in `bytes`, see the module documentation, in the order they are stored. An
+/// empty container has no entries.
+///
+/// Fails at the offset of the first entry that is cut short by the end of `bytes` (its key, value length or value),
+/// whose key is not valid (see `is_valid_key`), or whose key was already used by an earlier entry.
+pub fn parse(bytes: &[u8]) -> Result<Vec<Entry>, ParseError> {
+    let mut entries = Vec::<Entry>::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let entry_offset = offset;
+        let key_length = bytes[offset] as usize;
+        offset += 1;
+
+        let key = bytes.get(offset..offset + key_length)
+            .ok_or(ParseError { offset: entry_offset, reason: "truncated key" })?;
+        let key = std::str::from_utf8(key).ok()
+            .filter(|key| is_valid_key(key))
+            .ok_or(ParseError { offset: entry_offset, reason: "invalid key" })?;
+        offset += key_length;
+
+        let value_length = bytes.get(offset..offset + 2)
+            .ok_or(ParseError { offset: entry_offset, reason: "truncated value length" })?;
+        let value_length = u16::from_le_bytes([value_length[0], value_length[1]]) as usize;
+        offset += 2;
+
+        let value = bytes.get(offset..offset + value_length)
+            .ok_or(ParseError { offset: entry_offset, reason: "truncated value" })?;
+        offset += value_length;
+
+        if entries.iter().any(|entry| entry.key == key) {
+            return Err(ParseError { offset: entry_offset, reason: "duplicate key" });
+        }
+
+        entries.push(Entry { key: key.to_string(), value: value.to_vec() });
+    }
+
+    Ok(entries)
+}
+
+/// Lay out `entries` back to back in order, each as a key length byte, the key, the value length as two little-endian
+/// bytes and the value, so that `parse` returns them.
+///
+/// Nothing is checked: the keys must be valid and unique (see `is_valid_key`) and the values at most `u16::MAX` bytes,
+/// or the container does not parse back.
+pub fn serialize(entries: &[Entry]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    for entry in entries {
+        bytes.push(entry.key.len() as u8);
+        bytes.extend(entry.key.as_bytes());
+        bytes.extend((entry.value.len() as u16).to_le_bytes());
+        bytes.extend(&entry.value);
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let entries = vec![
+            Entry { key: "serial".to_string(), value: b"VK-0001".to_vec() },
+            Entry { key: "lens_id".to_string(), value: vec![0x00, 0xFF] },
+            Entry { key: "empty".to_string(), value: vec![] },
+        ];
+
+        assert_eq!(parse(&serialize(&entries)).unwrap(), entries);
+        assert_eq!(parse(&[]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn reports_offset_of_corrupt_entry() {
+        let mut bytes = serialize(&[Entry { key: "a".to_string(), value: vec![1, 2, 3] }]);
+        bytes.extend([1, b'b', 10, 0, 1]);
+
+        assert_eq!(parse(&bytes), Err(ParseError { offset: 7, reason: "truncated value" }));
+        assert_eq!(parse(&[3, b'a', b' ', b'c', 0, 0]), Err(ParseError { offset: 0, reason: "invalid key" }));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn parse_never_panics(bytes: Vec<u8>) {
+            let _ = parse(&bytes);
+        }
+
+        // Keys are unique, as parse rejects duplicate keys.
+        #[test]
+        fn arbitrary_entries_round_trip(entries in proptest::collection::btree_map("[a-zA-Z0-9_.-]{1,32}", proptest::collection::vec(proptest::prelude::any::<u8>(), 0..64), 0..8)) {
+            let entries = entries.into_iter().map(|(key, value)| Entry { key, value }).collect::<Vec<_>>();
+
+            proptest::prop_assert_eq!(parse(&serialize(&entries)), Ok(entries));
+        }
+    }
+}
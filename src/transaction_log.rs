@@ -0,0 +1,167 @@
+//! Log of the reads and writes of the EEPROM, appended to a file with `--transaction-log` to keep an audit trail of
+//! each unit provisioned.
+//!
+//! Every transaction is logged as a record with its time, direction, region, offset, length, outcome and the number of
+//! retries it took, as a CSV or JSON line. The file is flushed after each record, so that a crash leaves every
+//! transaction made until then in the log.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::json;
+use crate::stats::Direction;
+
+/// Format of the records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Comma-separated values, with a header line when the file is created.
+    Csv,
+    /// A JSON object per line.
+    Jsonl,
+}
+
+/// Header line of a CSV log, naming the fields of the records.
+const CSV_HEADER: &str = "timestamp_ms,direction,region,offset,length,outcome,retries,error";
+
+/// Part of the EEPROM a transaction is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Metadata,
+    Content,
+}
+
+/// Transaction with the EEPROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record<'a> {
+    /// Unix time of the end of the transaction, in milliseconds.
+    pub timestamp_ms: u128,
+    pub direction: Direction,
+    pub region: Region,
+    pub offset: u16,
+    /// Bytes transferred, not counting the address.
+    pub length: usize,
+    pub retries: u64,
+    /// Description of the failure of the transaction, if it failed once retries were exhausted.
+    pub error: Option<&'a str>,
+}
+
+/// Log file records are appended to.
+pub struct Log {
+    file: File,
+    format: LogFormat,
+}
+
+impl Log {
+    /// Open the file at `path` to append records to it, creating it if needed.
+    pub fn open(path: &Path, format: LogFormat) -> io::Result<Log> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        if format == LogFormat::Csv && file.metadata()?.len() == 0 {
+            writeln!(file, "{CSV_HEADER}")?;
+        }
+
+        Ok(Log { file, format })
+    }
+
+    /// Append `record` and flush it to the file.
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        writeln!(self.file, "{}", render(record, self.format))?;
+        self.file.flush()
+    }
+}
+
+/// Log of the transactions of this invocation, from `--transaction-log`.
+static LOG: Mutex<Option<Log>> = Mutex::new(None);
+
+/// Log the transactions made from now on into `log`.
+pub fn start(log: Log) {
+    *LOG.lock().unwrap() = Some(log);
+}
+
+/// Append a record of a transaction that just ended, if a log was started. Failing to write the log only warns, so
+/// that it never stops a write halfway.
+pub fn log(direction: Direction, region: Region, offset: u16, length: usize, retries: u64, error: Option<&str>) {
+    let mut log = LOG.lock().unwrap();
+
+    let Some(log) = log.as_mut() else {
+        return;
+    };
+
+    let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis());
+
+    if let Err(error) = log.append(&Record { timestamp_ms, direction, region, offset, length, retries, error }) {
+        log::warn!("Failed to write to the transaction log: {error}");
+    }
+}
+
+/// Line of the log for `record`.
+fn render(record: &Record, format: LogFormat) -> String {
+    let direction = match record.direction {
+        Direction::Read => "read",
+        Direction::Write => "write",
+    };
+    let region = match record.region {
+        Region::Metadata => "metadata",
+        Region::Content => "content",
+    };
+    let outcome = if record.error.is_some() { "error" } else { "ok" };
+
+    match format {
+        LogFormat::Csv => format!(
+            "{},{direction},{region},0x{:04x},{},{outcome},{},{}",
+            record.timestamp_ms, record.offset, record.length, record.retries, record.error.map_or(String::new(), csv_field),
+        ),
+        LogFormat::Jsonl => format!(
+            "{{\"timestamp_ms\":{},\"direction\":\"{direction}\",\"region\":\"{region}\",\"offset\":{},\"length\":{},\"outcome\":\"{outcome}\",\"retries\":{},\"error\":{}}}",
+            record.timestamp_ms, record.offset, record.length, record.retries, record.error.map_or("null".to_string(), json::string),
+        ),
+    }
+}
+
+/// Quote `value` as a CSV field, on a single line.
+fn csv_field(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\"").replace('\n', "; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_records() {
+        let mut record = Record { timestamp_ms: 1_700_000_000_123, direction: Direction::Write, region: Region::Content, offset: 0x40, length: 32, retries: 2, error: None };
+
+        assert_eq!(render(&record, LogFormat::Csv), "1700000000123,write,content,0x0040,32,ok,2,");
+        assert_eq!(
+            render(&record, LogFormat::Jsonl),
+            r#"{"timestamp_ms":1700000000123,"direction":"write","region":"content","offset":64,"length":32,"outcome":"ok","retries":2,"error":null}"#,
+        );
+
+        record.error = Some("no device is answering\ncaused by: \"ENXIO\"");
+        assert!(render(&record, LogFormat::Csv).ends_with(r#",error,2,"no device is answering; caused by: ""ENXIO""""#));
+        assert!(render(&record, LogFormat::Jsonl).ends_with(r#""outcome":"error","retries":2,"error":"no device is answering\u000acaused by: \"ENXIO\""}"#));
+    }
+
+    #[test]
+    fn appends_flushed_records() {
+        let path = std::env::temp_dir().join(format!("vki2cfile-transactions-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(path.as_path());
+
+        let record = Record { timestamp_ms: 0, direction: Direction::Read, region: Region::Metadata, offset: 0, length: 32, retries: 0, error: None };
+        Log::open(path.as_path(), LogFormat::Csv).unwrap().append(&record).unwrap();
+
+        // Opening the log again appends to it, without another header.
+        let record = Record { direction: Direction::Write, region: Region::Content, offset: 0x20, retries: 1, error: Some("failed"), ..record };
+        Log::open(path.as_path(), LogFormat::Csv).unwrap().append(&record).unwrap();
+
+        let lines: Vec<String> = std::fs::read_to_string(path.as_path()).unwrap().lines().map(str::to_string).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[1].ends_with(",read,metadata,0x0000,32,ok,0,"), "{}", lines[1]);
+        assert!(lines[2].ends_with(",write,content,0x0020,32,error,1,\"failed\""), "{}", lines[2]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+//! Watchdog bounding the time a single I2C transfer may take, for `--io-timeout-ms`.
+//!
+//! A device holding SCL low can make the kernel block in the transfer ioctl for a very long time, or forever with some
+//! adapters, and nothing can be done about it from the blocked thread. Transfers mark when they start and end, and a
+//! watchdog thread exits the process if one is still running past the timeout.
+
+use std::sync::{Once, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Start of the transfer in progress, in microseconds since `origin()` plus 1, or 0 if no transfer is in progress.
+static STARTED: AtomicU64 = AtomicU64::new(0);
+
+/// Reference instant the start of transfers is measured from.
+fn origin() -> Instant {
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+
+    *ORIGIN.get_or_init(Instant::now)
+}
+
+/// Run `transfer`, marking it as in progress for the watchdog. Only the Linux backend makes transfers.
+pub fn transfer<T>(transfer: impl FnOnce() -> T) -> T {
+    STARTED.store(origin().elapsed().as_micros() as u64 + 1, Ordering::Relaxed);
+
+    let result = transfer();
+
+    STARTED.store(0, Ordering::Relaxed);
+    result
+}
+
+/// How long the transfer in progress has been running, if it has been for more than `timeout`.
+fn overdue(timeout: Duration) -> Option<Duration> {
+    let started = match STARTED.load(Ordering::Relaxed) {
+        0 => return None,
+        started => Duration::from_micros(started - 1),
+    };
+
+    Some(origin().elapsed().saturating_sub(started)).filter(|elapsed| *elapsed > timeout)
+}
+
+/// Start a thread exiting the process with `exit_code` once a transfer on the bus at `path` runs for more than
+/// `timeout`, unless it was already started.
+pub fn start(timeout: Duration, path: String, exit_code: i32) {
+    static START: Once = Once::new();
+
+    let period = (timeout / 4).clamp(Duration::from_millis(1), Duration::from_millis(100));
+
+    origin();
+    START.call_once(|| drop(std::thread::spawn(move || loop {
+        std::thread::sleep(period);
+
+        if let Some(elapsed) = overdue(timeout) {
+            eprintln!(
+                "Transfer on {path} exceeded {} ms (still running after {} ms), the bus may be stuck. Check for a device holding SCL or SDA low, e.g. one left mid-transfer: power cycling the bus usually frees it.",
+                timeout.as_millis(), elapsed.as_millis(),
+            );
+            std::process::exit(exit_code);
+        }
+    })));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_running_transfers_are_overdue() {
+        assert_eq!(overdue(Duration::ZERO), None);
+
+        transfer(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            assert!(overdue(Duration::from_millis(10)).is_some());
+            assert_eq!(overdue(Duration::from_secs(10)), None);
+        });
+
+        assert_eq!(overdue(Duration::ZERO), None);
+    }
+}
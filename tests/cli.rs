@@ -0,0 +1,178 @@
+//! The binary run end to end against simulated EEPROMs holding pre-baked images, as scripts would run it.
+
+mod common;
+
+use std::process::Command;
+use common::{baked, run, run_with, scratch, simulated, Image, EEPROM_SIZE, STORED};
+
+#[test]
+fn write_then_read_round_trips() {
+    let directory = scratch("cli-round-trip");
+    let eeprom = simulated(&directory);
+    let source = directory.join("source");
+    let destination = directory.join("read");
+
+    std::fs::write(source.as_path(), STORED).unwrap();
+
+    assert_eq!(run(&eeprom, &["write", source.to_str().unwrap()]), 0);
+    assert_eq!(run(&eeprom, &["read", destination.to_str().unwrap()]), 0);
+    assert_eq!(std::fs::read(destination.as_path()).unwrap(), STORED);
+
+    let stdout = run_with(&eeprom, false, &["read", "-"]);
+    assert!(stdout.status.success());
+    assert_eq!(stdout.stdout, STORED);
+}
+
+#[test]
+fn valid_image_reads_as_written_by_the_library() {
+    let directory = scratch("cli-valid");
+    let eeprom = baked(&directory, Image::Valid);
+
+    assert_eq!(run_with(&eeprom, false, &["read", "-"]).stdout, STORED);
+    assert_eq!(run(&eeprom, &["verify"]), 0);
+}
+
+#[test]
+fn blank_image_only_reads_with_allow_empty() {
+    let directory = scratch("cli-blank");
+    let eeprom = baked(&directory, Image::Blank);
+    let destination = directory.join("read");
+
+    let output = run_with(&eeprom, false, &["read", destination.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(12));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is blank"));
+    assert!(!destination.exists());
+
+    assert_eq!(run(&eeprom, &["read", "--allow-empty", destination.to_str().unwrap()]), 0);
+    assert_eq!(std::fs::read(destination.as_path()).unwrap(), b"");
+}
+
+#[test]
+fn crc_failure_only_reads_with_ignore_crc() {
+    let directory = scratch("cli-corrupt");
+    let eeprom = baked(&directory, Image::CorruptCrc);
+    let destination = directory.join("read");
+
+    assert_eq!(run(&eeprom, &["read", destination.to_str().unwrap()]), 15);
+    assert!(!destination.exists());
+
+    assert_eq!(run(&eeprom, &["read", "--ignore-crc", destination.to_str().unwrap()]), 0);
+    let read = std::fs::read(destination.as_path()).unwrap();
+    assert_eq!(read.len(), STORED.len());
+    assert_ne!(read, STORED);
+}
+
+#[test]
+fn garbage_header_is_rejected() {
+    let directory = scratch("cli-garbage");
+    let eeprom = baked(&directory, Image::GarbageHeader);
+
+    let output = run_with(&eeprom, false, &["read", "-"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid"), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn oversized_source_is_rejected_before_writing() {
+    let directory = scratch("cli-oversized");
+    let eeprom = baked(&directory, Image::Valid);
+    let source = directory.join("source");
+
+    std::fs::write(source.as_path(), vec![0x42; EEPROM_SIZE]).unwrap();
+
+    assert_eq!(run(&eeprom, &["write", "--force", source.to_str().unwrap()]), 17);
+    assert_eq!(std::fs::read(eeprom.as_path()).unwrap(), common::image_bytes(Image::Valid));
+}
+
+#[test]
+fn simulator_is_selected_from_the_environment() {
+    let directory = scratch("cli-environment");
+    let eeprom = baked(&directory, Image::Valid);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_vki2cfile"))
+        .env("VKI2CFILE_SIMULATE", eeprom.as_path())
+        .args(["--simulate-fast", "read", "-"])
+        .output().unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, STORED);
+}
+
+#[test]
+fn verbosity_adds_transactions_and_dumps() {
+    let directory = scratch("cli-verbosity");
+    let eeprom = baked(&directory, Image::Valid);
+    let stderr = |flags: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_vki2cfile"))
+            .env_remove("RUST_LOG")
+            .arg("--simulate").arg(eeprom.as_path())
+            .args(flags)
+            .arg("verify")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    assert_eq!(stderr(&[]), "");
+    assert!(!stderr(&["-v"]).contains("[DEBUG"));
+    assert!(stderr(&["-vv"]).contains("Read of 32 bytes at 0x0000 (Metadata) succeeded"));
+    assert!(!stderr(&["-vv"]).contains("[TRACE"));
+    assert!(stderr(&["-vvv"]).contains("32 bytes read at 0x0000:\n  0000: 56 4b"));
+}
+
+#[test]
+fn metadata_layouts_are_interchangeable() {
+    let images = ["bincode", "v1"].map(|layout| {
+        let directory = scratch(&format!("cli-layout-{layout}"));
+        let eeprom = simulated(&directory);
+        let source = directory.join("source");
+
+        std::fs::write(source.as_path(), STORED).unwrap();
+        assert_eq!(run(&eeprom, &["--metadata-layout", layout, "write", "--write-format", "v1", source.to_str().unwrap()]), 0);
+        assert_eq!(run_with(&eeprom, false, &["--metadata-layout", if layout == "v1" { "bincode" } else { "v1" }, "read", "-"]).stdout, STORED);
+
+        std::fs::read(eeprom).unwrap()
+    });
+
+    assert_eq!(images[0], images[1]);
+}
+
+#[test]
+fn write_json_prints_only_json() {
+    let directory = scratch("cli-json");
+    let eeprom = simulated(&directory);
+    let source = directory.join("source");
+
+    std::fs::write(source.as_path(), STORED).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_vki2cfile"))
+        .arg("--simulate").arg(eeprom.as_path())
+        .args(["--simulate-fast", "write", "--json", "--diff-write", source.to_str().unwrap()])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.starts_with('{') && stdout.ends_with("}\n") && stdout.lines().count() == 1, "{stdout}");
+}
+
+#[test]
+fn if_changed_writes_new_content_with_a_given_crc() {
+    let directory = scratch("cli-if-changed-crc");
+    let eeprom = simulated(&directory);
+    let source = directory.join("source");
+    let destination = directory.join("read");
+    let changed = STORED.iter().map(|byte| !byte).collect::<Vec<_>>();
+
+    std::fs::write(source.as_path(), STORED).unwrap();
+    assert_eq!(run(&eeprom, &["write", "--crc-value", "beef", source.to_str().unwrap()]), 0);
+
+    // Same size, options and CRC: only the content tells the files apart.
+    std::fs::write(source.as_path(), changed.as_slice()).unwrap();
+    assert_eq!(run(&eeprom, &["write", "--crc-value", "beef", "--if-changed", source.to_str().unwrap()]), 0);
+    assert_eq!(run(&eeprom, &["read", destination.to_str().unwrap()]), 0);
+    assert_eq!(std::fs::read(destination.as_path()).unwrap(), changed);
+}
@@ -0,0 +1,107 @@
+//! Fixture of the integration tests: the binary run against an EEPROM simulated in a scratch file with `--simulate`.
+
+// Each test crate only uses some of the helpers.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Size of the simulated EEPROM.
+pub const EEPROM_SIZE: usize = 8192;
+
+/// Address of the first byte of content.
+pub const CONTENT_OFFSET: usize = 32;
+
+/// Empty directory of scratch files for the test `name`. Names must be unique, as the lock of the bus is named after
+/// the simulated EEPROM.
+pub fn scratch(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("vki2cfile-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(directory.as_path());
+
+    std::fs::create_dir_all(directory.as_path()).unwrap();
+    directory
+}
+
+/// Path of the EEPROM simulated in `directory`, created blank on first use.
+pub fn simulated(directory: &Path) -> PathBuf {
+    directory.join(format!("{}.bin", directory.file_name().unwrap().to_string_lossy()))
+}
+
+/// Run the binary with `args` on the EEPROM simulated at `eeprom`, skipping its write cycles unless `write_cycles`.
+pub fn run_with(eeprom: &Path, write_cycles: bool, args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_vki2cfile"));
+    command.arg("--simulate").arg(eeprom).arg("--quiet");
+
+    if !write_cycles {
+        command.arg("--simulate-fast");
+    }
+
+    command.args(args).output().unwrap()
+}
+
+/// Exit code of the binary run with `args` on the EEPROM simulated at `eeprom`, without write cycles.
+pub fn run(eeprom: &Path, args: &[&str]) -> i32 {
+    run_with(eeprom, false, args).status.code().unwrap()
+}
+
+/// Write `content` as a file into the EEPROM simulated at `eeprom`.
+pub fn write(eeprom: &Path, content: &[u8]) {
+    let source = eeprom.with_extension("src");
+    std::fs::write(source.as_path(), content).unwrap();
+
+    assert_eq!(run(eeprom, &["write", "--force", source.to_str().unwrap()]), 0);
+}
+
+/// Content of the file stored in the `Image::Valid` and `Image::CorruptCrc` images.
+pub const STORED: &[u8] = b"{\"gain\": 1.5, \"offset\": -3}";
+
+/// Metadata of `STORED` as the tool writes it, assembled by hand rather than by the library under test so that the
+/// tests pin the format: a v3 block (`VK`, version 3) with no flags, payload version or serial number, its own CRC at
+/// 13..15, and the CRC-16/USB and size of the content at 28..32, all little-endian.
+const STORED_METADATA: [u8; 32] = [
+    b'V', b'K', 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x4c, 0x69, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xbb, 0x98, 0x1b, 0x00,
+];
+
+/// Pre-baked contents of a simulated EEPROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Image {
+    /// Never written, all 0xFF.
+    Blank,
+    /// Holding `STORED` with valid metadata.
+    Valid,
+    /// Holding `STORED` with a byte of its content flipped, so that it does not match its CRC.
+    CorruptCrc,
+    /// Holding `STORED` after metadata overwritten with garbage.
+    GarbageHeader,
+}
+
+/// Bytes of the EEPROM holding `image`, as the tool writes them: the content follows the metadata, zero-padded to the
+/// end of its 32-byte block.
+pub fn image_bytes(image: Image) -> Vec<u8> {
+    let mut memory = vec![0xFF; EEPROM_SIZE];
+
+    if image != Image::Blank {
+        let end = CONTENT_OFFSET + STORED.len();
+
+        memory[..CONTENT_OFFSET].copy_from_slice(&STORED_METADATA);
+        memory[CONTENT_OFFSET..end].copy_from_slice(STORED);
+        memory[end..end.next_multiple_of(32)].fill(0);
+    }
+
+    match image {
+        Image::Blank | Image::Valid => {}
+        Image::CorruptCrc => memory[CONTENT_OFFSET + 5] ^= 0x20,
+        Image::GarbageHeader => memory[..CONTENT_OFFSET].iter_mut().enumerate().for_each(|(index, byte)| *byte = (index * 37 + 11) as u8),
+    }
+
+    memory
+}
+
+/// Path of a simulated EEPROM in `directory` holding `image`.
+pub fn baked(directory: &Path, image: Image) -> PathBuf {
+    let path = simulated(directory);
+
+    std::fs::write(path.as_path(), image_bytes(image)).unwrap();
+    path
+}
@@ -0,0 +1,117 @@
+//! Exit codes of the binary for each outcome, run against simulated EEPROMs so that the contract scripts rely on
+//! cannot drift unnoticed.
+
+mod common;
+
+use std::process::Command;
+use common::{baked, run, scratch, simulated, write, Image, EEPROM_SIZE};
+
+#[test]
+fn codes_are_listed() {
+    let output = Command::new(env!("CARGO_BIN_EXE_vki2cfile")).arg("codes").output().unwrap();
+    let table = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert!(table.contains(" 12  the EEPROM is blank"), "{table}");
+    assert!(table.contains(" 15  the file in EEPROM does not match its CRC"), "{table}");
+    assert!(table.contains(" 19  the file in EEPROM is empty"), "{table}");
+    assert!(table.contains(" 20  another instance of this tool is using the I2C bus"), "{table}");
+    assert!(table.contains(" 21  the mux in front of the EEPROM"), "{table}");
+}
+
+#[test]
+fn valid_file_succeeds() {
+    let directory = scratch("valid");
+    let eeprom = simulated(&directory);
+    let destination = directory.join("read");
+
+    write(&eeprom, b"calibration");
+    assert_eq!(run(&eeprom, &["read", destination.to_str().unwrap()]), 0);
+    assert_eq!(run(&eeprom, &["verify"]), 0);
+    assert_eq!(std::fs::read(destination).unwrap(), b"calibration");
+}
+
+#[test]
+fn blank_eeprom_exits_with_12() {
+    let directory = scratch("blank");
+    let eeprom = simulated(&directory);
+
+    assert_eq!(run(&eeprom, &["read", directory.join("read").to_str().unwrap()]), 12);
+    assert_eq!(run(&eeprom, &["verify"]), 12);
+}
+
+#[test]
+fn empty_file_and_corrupted_file_exit_with_different_codes() {
+    let directory = scratch("empty");
+    let eeprom = simulated(&directory);
+
+    write(&eeprom, b"");
+    assert_eq!(run(&eeprom, &["read", directory.join("read").to_str().unwrap()]), 19);
+
+    let directory = scratch("corrupted");
+    let eeprom = baked(&directory, Image::CorruptCrc);
+
+    assert_eq!(run(&eeprom, &["read", directory.join("read").to_str().unwrap()]), 15);
+    assert_eq!(run(&eeprom, &["verify"]), 15);
+}
+
+#[test]
+fn oversized_file_exits_with_17() {
+    let directory = scratch("oversized");
+    let eeprom = simulated(&directory);
+    let source = directory.join("oversized.src");
+
+    std::fs::write(source.as_path(), vec![0x42; EEPROM_SIZE]).unwrap();
+    assert_eq!(run(&eeprom, &["write", source.to_str().unwrap()]), 17);
+}
+
+#[test]
+fn usage_and_missing_bus_exit_with_their_codes() {
+    let directory = scratch("usage");
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_vki2cfile")).args(args).output().unwrap().status.code().unwrap();
+
+    assert_eq!(run(&["--simulate", directory.join("usage.bin").to_str().unwrap(), "write"]), 2);
+    assert_eq!(run(&["--device", directory.join("missing").to_str().unwrap(), "verify"]), 16);
+}
+
+#[test]
+fn invalid_write_timing_exits_with_2() {
+    let directory = scratch("timing");
+    let eeprom = simulated(&directory);
+    let source = directory.join("timing.src");
+
+    std::fs::write(source.as_path(), b"calibration").unwrap();
+    assert_eq!(run(&eeprom, &["--min-delay", "5", "--max-delay", "2", "write", source.to_str().unwrap()]), 2);
+    assert_eq!(run(&eeprom, &["--min-delay", "1", "--max-delay", "5", "write", "--fast", source.to_str().unwrap()]), 2);
+}
+
+#[test]
+fn busy_bus_exits_with_20() {
+    use std::os::fd::AsRawFd;
+
+    let directory = scratch("busy");
+    let eeprom = simulated(&directory);
+    // The lock file the tool takes for the simulated EEPROM, see `lock::lock_path`.
+    let lock_directory = match std::path::Path::new("/run/lock").is_dir() {
+        true => std::path::PathBuf::from("/run/lock"),
+        false => std::env::temp_dir(),
+    };
+    let lock_path = lock_directory.join(format!("vki2cfile-{}.lock", eeprom.file_name().unwrap().to_string_lossy()));
+    let lock = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(lock_path.as_path()).unwrap();
+
+    // SAFETY: `flock` only operates on the file descriptor, which stays valid for the duration of the call.
+    assert_eq!(unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) }, 0);
+    assert_eq!(run(&eeprom, &["--no-wait", "verify"]), 20);
+
+    drop(lock);
+    std::fs::remove_file(lock_path).unwrap();
+}
+
+#[test]
+fn unreachable_mux_exits_with_21() {
+    let directory = scratch("mux");
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_vki2cfile")).args(args).output().unwrap().status.code().unwrap();
+    let device = directory.join("missing");
+
+    assert_eq!(run(&["--device", device.to_str().unwrap(), "--mux-address", "0x70", "--mux-channel", "2", "verify"]), 21);
+}
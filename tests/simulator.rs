@@ -0,0 +1,85 @@
+//! Whole cycles of the binary against a simulated EEPROM, write cycles included.
+
+mod common;
+
+use common::{run_with, scratch, simulated, CONTENT_OFFSET, EEPROM_SIZE};
+
+#[test]
+fn write_read_verify_erase() {
+    let directory = scratch("cycle");
+    let eeprom = simulated(&directory);
+    let source = directory.join("source");
+    let destination = directory.join("read");
+    let content: Vec<u8> = (0..1000).map(|index| (index * 7) as u8).collect();
+
+    std::fs::write(source.as_path(), content.as_slice()).unwrap();
+
+    assert!(run_with(&eeprom, true, &["write", source.to_str().unwrap()]).status.success());
+    assert_eq!(std::fs::metadata(eeprom.as_path()).unwrap().len(), EEPROM_SIZE as u64);
+    assert_eq!(std::fs::read(eeprom.as_path()).unwrap()[CONTENT_OFFSET..][..content.len()], content);
+
+    assert!(run_with(&eeprom, true, &["read", destination.to_str().unwrap()]).status.success());
+    assert_eq!(std::fs::read(destination.as_path()).unwrap(), content);
+
+    let verify = run_with(&eeprom, true, &["verify"]);
+    assert!(verify.status.success(), "{}", String::from_utf8_lossy(&verify.stderr));
+
+    assert!(run_with(&eeprom, true, &["erase"]).status.success());
+    assert_eq!(run_with(&eeprom, true, &["verify"]).status.code(), Some(12));
+    assert!(std::fs::read(eeprom.as_path()).unwrap().iter().all(|&byte| byte == 0xFF));
+}
+
+#[test]
+fn erase_slot_keeps_the_other_slots_unless_locked() {
+    let directory = scratch("erase-slot");
+    let eeprom = simulated(&directory);
+    let source = directory.join("source");
+
+    for (slot, content) in [("0", b"calibration"), ("1", b"factory-dat")] {
+        std::fs::write(source.as_path(), content).unwrap();
+        assert!(run_with(&eeprom, true, &["write", "--slot", slot, source.to_str().unwrap()]).status.success());
+    }
+
+    assert!(run_with(&eeprom, true, &["erase", "--slot", "1"]).status.success());
+    assert!(run_with(&eeprom, true, &["verify", "--slot", "0"]).status.success());
+    assert_eq!(run_with(&eeprom, true, &["verify", "--slot", "1"]).status.code(), Some(1));
+
+    // A locked EEPROM is only erased with --force.
+    assert!(run_with(&eeprom, true, &["lock"]).status.success());
+    assert_eq!(run_with(&eeprom, true, &["erase", "--slot", "0"]).status.code(), Some(1));
+    assert_eq!(run_with(&eeprom, true, &["erase"]).status.code(), Some(1));
+    assert!(run_with(&eeprom, true, &["verify", "--slot", "0"]).status.success());
+    assert!(run_with(&eeprom, true, &["erase", "--force"]).status.success());
+    assert_eq!(run_with(&eeprom, true, &["verify"]).status.code(), Some(12));
+}
+
+#[test]
+fn simulated_eeprom_of_another_size_is_refused() {
+    let directory = scratch("size");
+    let eeprom = simulated(&directory);
+
+    std::fs::write(eeprom.as_path(), [0xFF; 256]).unwrap();
+
+    let output = run_with(&eeprom, false, &["verify"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("holds 256 bytes instead of 8192"));
+}
+
+#[test]
+fn reserve_shrinks_the_space_for_content() {
+    let directory = scratch("reserve");
+    let eeprom = simulated(&directory);
+    let source = directory.join("source");
+    let max_size = EEPROM_SIZE - CONTENT_OFFSET - 1000;
+
+    std::fs::write(source.as_path(), vec![0x42; max_size + 1]).unwrap();
+    assert_eq!(run_with(&eeprom, false, &["--reserve", "1000", "write", source.to_str().unwrap()]).status.code(), Some(17));
+
+    std::fs::write(source.as_path(), vec![0x42; max_size]).unwrap();
+    assert!(run_with(&eeprom, false, &["--reserve", "1000", "write", source.to_str().unwrap()]).status.success());
+    assert!(std::fs::read(eeprom.as_path()).unwrap()[EEPROM_SIZE - 1000..].iter().all(|&byte| byte == 0xFF));
+
+    // A file reaching into a larger reserve is not trusted, and no reserve may leave no room for content.
+    assert_eq!(run_with(&eeprom, false, &["--reserve", "1001", "verify"]).status.code(), Some(1));
+    assert_eq!(run_with(&eeprom, false, &["--reserve", "8160", "verify"]).status.code(), Some(1));
+}